@@ -7,8 +7,8 @@
 //:
 //: [lib]
 //: # The name of the native library. This is the name which will be used in Python to import the
-//: # library (i.e. `import string_sum`). If you change this, you must also change the name of the
-//: # `#[pymodule]` below.
+//: # library (i.e. `import singlefile`). If you change this, you must also change the name of the
+//: # `#[pymodule]` below (rustimport reports a mismatch before building).
 //: name = "singlefile"
 //:
 //: # Downstream Rust code (including code in `bin/`, `examples/`, and `examples/`) will not be able
@@ -0,0 +1,15 @@
+// rustimport:pyo3
+
+// Use mimalloc as the global allocator. The `//prelude:` line is inserted at the very top of the
+// generated source, so the allocator is declared before any other item:
+//: [dependencies]
+//: mimalloc = { version = "0.1", default-features = false }
+//prelude: #[global_allocator] static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+use pyo3::prelude::*;
+
+/// Joins the given strings, allocating through mimalloc.
+#[pyfunction]
+fn join(parts: Vec<String>, separator: &str) -> String {
+    parts.join(separator)
+}
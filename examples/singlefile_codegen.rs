@@ -0,0 +1,20 @@
+// rustimport:pyo3
+
+// Run a build script before compiling this file. Its path is relative to this file, and rustimport
+// automatically tracks it for changes.
+//: [package]
+//: build = "singlefile_codegen_build.rs"
+
+use pyo3::prelude::*;
+
+// Include the code generated by the build script. Cargo sets OUT_DIR for every crate that has a
+// build script, so this works just like in a regular crate.
+include!(concat!(env!("OUT_DIR"), "/generated.rs"));
+
+/// Returns the square of `n`, looked up from a table generated at build time.
+#[pyfunction]
+fn lookup_square(n: usize) -> PyResult<u64> {
+    SQUARES.get(n).copied().ok_or_else(|| {
+        pyo3::exceptions::PyIndexError::new_err(format!("n must be smaller than {}", SQUARES.len()))
+    })
+}
@@ -0,0 +1,18 @@
+// The build script for singlefile_codegen.rs, which generates a lookup table at build time.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let squares: Vec<String> = (0..16u64).map(|i| (i * i).to_string()).collect();
+    let code = format!(
+        "const SQUARES: [u64; {}] = [{}];\n",
+        squares.len(),
+        squares.join(", ")
+    );
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("generated.rs"), code).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,24 @@
+// rustimport:pyo3(manifest=manual)
+
+// Note: the "manifest=manual" option tells the "pyo3" template to not generate a Cargo.toml manifest,
+// but to use the one below as is. The #[pymodule] macro is still generated automatically.
+
+//: [package]
+//: name = "singlefile_pymodule_only_templating"
+//: version = "0.1.0"
+//: edition = "2021"
+//:
+//: [lib]
+//: name = "singlefile_pymodule_only_templating"
+//: crate-type = ["cdylib"]
+//:
+//: [dependencies]
+//: pyo3 = { version = "0.16.2", features = ["extension-module"] }
+
+use pyo3::prelude::*;
+
+/// Formats the sum of two numbers as string.
+#[pyfunction]
+fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
+    Ok((a + b).to_string())
+}
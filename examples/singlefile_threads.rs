@@ -0,0 +1,59 @@
+// rustimport:pyo3
+
+// Statements to run when the module is initialized, e.g. to set up a thread pool or logging. They
+// are inserted into the generated #[pymodule], where the module is available as `m`.
+//module-init: init_pool(std::thread::available_parallelism().map_or(4, |n| n.get()));
+//module-init: m.add("pool_size", POOL.get().map_or(0, |p| p.len()))?;
+
+use pyo3::prelude::*;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A minimal thread pool. In real code, you'd likely use a crate like `rayon` instead.
+static POOL: OnceLock<Vec<mpsc::Sender<Job>>> = OnceLock::new();
+
+fn init_pool(size: usize) {
+    POOL.get_or_init(|| {
+        (0..size).map(|_| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            thread::spawn(move || {
+                for job in rx {
+                    job();
+                }
+            });
+            tx
+        }).collect()
+    });
+}
+
+/// Computes the sum of squares of each chunk on the thread pool.
+///
+/// The GIL is released while waiting for the results, so that other Python threads can run in
+/// the meantime. Rust code running without the GIL must not touch any Python objects; use
+/// `Python::with_gil` if a worker needs to call back into Python.
+#[pyfunction]
+fn parallel_sum_of_squares(py: Python, chunks: Vec<Vec<u64>>) -> PyResult<Vec<u64>> {
+    let pool = POOL.get().expect("the pool is initialized on import");
+    py.allow_threads(|| {
+        let results = Arc::new(Mutex::new(vec![0; chunks.len()]));
+        let (done_tx, done_rx) = mpsc::channel();
+        let count = chunks.len();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let results = Arc::clone(&results);
+            let done_tx = done_tx.clone();
+            let job: Job = Box::new(move || {
+                results.lock().unwrap()[i] = chunk.iter().map(|x| x * x).sum();
+                done_tx.send(()).unwrap();
+            });
+            pool[i % pool.len()].send(job).unwrap();
+        }
+        for _ in 0..count {
+            done_rx.recv().unwrap();
+        }
+        let results = results.lock().unwrap().clone();
+        Ok(results)
+    })
+}
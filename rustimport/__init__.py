@@ -34,13 +34,22 @@ For more information check the Readme on GitHub: https://github.com/mityax/rusti
 
 import logging as _logging
 from types import ModuleType
+from typing import Optional, Union
 
 from rustimport import settings
+from rustimport.settings import Settings
 
 _logger = _logging.getLogger("rustimport")
+# Libraries shouldn't configure logging for their users, so rustimport's messages are only shown if the application
+# (or `configure_logging`) adds a handler:
+_logger.addHandler(_logging.NullHandler())
+# The handler added by `configure_logging`, if any:
+_log_handler: Optional[_logging.Handler] = None
 
 
-def imp(fullname, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild) -> ModuleType:
+def imp(fullname, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild,
+        return_handle: bool = False, settings: Optional[Settings] = None,
+        verbosity: Optional[int] = None, module_name: Optional[str] = None) -> Union[ModuleType, 'ModuleHandle']:
     """
     `imp` is the explicit alternative to using rustimport.import_hook.
 
@@ -51,28 +60,53 @@ def imp(fullname, opt_in: bool = False, force_rebuild: bool = settings.force_reb
              the first line of the file? This is on by default for the
              import hook, but is off by default for this function since the
              intent to import a rust module is clearly specified.
+    return_handle : if true, a `ModuleHandle` is returned instead of the module,
+                    which allows to rebuild and reload the module later on using
+                    `handle.rebuild_if_changed()`. Its `build_result` tells
+                    whether the extension was rebuilt, how long that took and
+                    which warnings the compiler emitted.
+    settings : a `Settings` object overriding the global settings for this
+               call only (and for rebuilds through the returned handle).
+    verbosity : how much of cargo's output to print while building, from 0
+                (only errors) to 2 (everything). Defaults to `settings.verbosity`.
+    module_name : the name to import the module as, if it differs from the
+                  name of its file or crate given by `fullname`. The extension
+                  is named after it, and using the pyo3 template, so are the
+                  generated `lib.name` and `#[pymodule]`.
 
     Returns
     -------
     module : the compiled and loaded Python extension module
     """
+    call_settings = _call_settings(settings, verbosity=verbosity)
+    with call_settings.applied():
+        return _imp(fullname, opt_in, force_rebuild, return_handle, call_settings, module_name)
+
+
+def _imp(fullname, opt_in, force_rebuild, return_handle, call_settings, module_name):
     from rustimport.load import dlopen_flags
+    from rustimport.handle import ModuleHandle
 
     if settings.release_mode:
         import importlib
         with dlopen_flags():
-            return importlib.import_module(fullname)
+            module = importlib.import_module(module_name or fullname)
+        return ModuleHandle(module) if return_handle else module
 
     from rustimport.find import find_module_importable
-    from rustimport.importable import should_rebuild
+    from rustimport.importable import build_if_needed_and_load
 
-    importable = find_module_importable(fullname, opt_in)
-    if should_rebuild(importable, force_rebuild=force_rebuild):
-        importable.build(release=settings.compile_release_binaries)
-    return importable.load()
+    importable = find_module_importable(fullname, opt_in, module_name=module_name,
+                                        call_settings=call_settings)
+    module, result = build_if_needed_and_load(importable, force_rebuild=force_rebuild, return_build_result=True)
+    return ModuleHandle(module, importable, settings=call_settings, build_result=result) if return_handle else module
 
 
-def imp_from_path(path, fullname=None, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild) -> ModuleType:
+def imp_from_path(path, fullname=None, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild,
+                  return_handle: bool = False, settings: Optional[Settings] = None,
+                  verbosity: Optional[int] = None,
+                  module_name: Optional[str] = None,
+                  package: Optional[str] = None) -> Union[ModuleType, 'ModuleHandle']:
     """
     `imp_from_path` serves the same purpose as `imp` except allows
     specifying the exact path of the rust file or crate.
@@ -82,30 +116,175 @@ def imp_from_path(path, fullname=None, opt_in: bool = False, force_rebuild: bool
     filepath : the filepath to the C++ file to build and import.
     fullname : the name of the module to import. This can be different from the
                module name inferred from the filepath if desired.
+    return_handle : if true, a `ModuleHandle` is returned instead of the module
+                    (see `imp`).
+    settings : a `Settings` object overriding the global settings for this
+               call only (see `imp`).
+    verbosity : how much of cargo's output to print while building (see `imp`).
+    module_name : the name to import the module as (see `imp`). Unlike
+                  `fullname`, it also overrides a crate's `lib.name` when
+                  using the pyo3 template.
+    package : the name of a workspace member to build, if `path` is the
+              root (or any crate) of a cargo workspace. The member is built
+              within its workspace using `cargo build -p <package>`.
 
     Returns
     -------
     module : the compiled and loaded Python extension module
     """
+    call_settings = _call_settings(settings, verbosity=verbosity)
+    with call_settings.applied():
+        return _imp_from_path(path, fullname, opt_in, force_rebuild, return_handle, call_settings, module_name,
+                              package)
+
+
+def _imp_from_path(path, fullname, opt_in, force_rebuild, return_handle, call_settings, module_name, package):
     from rustimport.load import dlopen_flags
+    from rustimport.handle import ModuleHandle
 
     if settings.release_mode:
         import importlib
         with dlopen_flags():
-            return importlib.import_module(fullname)
+            module = importlib.import_module(module_name or fullname)
+        return ModuleHandle(module) if return_handle else module
+
+    import functools
+    from rustimport.importable import all_importables, CrateImportable
+    from rustimport.importable import build_if_needed_and_load
+
+    if package is not None:
+        # Only crates can be workspace members:
+        importables = [functools.partial(CrateImportable.try_create, package=package)]
+    else:
+        importables = [importable.try_create for importable in all_importables]
+
+    for try_create in importables:
+        if i := try_create(path, fullname=fullname, opt_in=opt_in, module_name=module_name,
+                           settings=call_settings):
+            module, result = build_if_needed_and_load(i, force_rebuild=force_rebuild, return_build_result=True)
+            return ModuleHandle(module, i, settings=call_settings, build_result=result) if return_handle else module
+
+
+def imp_from_git(url: str, rev: Optional[str] = None, tag: Optional[str] = None, branch: Optional[str] = None,
+                 member: Optional[str] = None, fullname: Optional[str] = None,
+                 force_rebuild: bool = settings.force_rebuild) -> ModuleType:
+    """
+    `imp_from_git` serves the same purpose as `imp_from_path` except that it
+    imports a crate hosted in a git repository. The repository is cloned into
+    the cache directory once per url and revision and reused afterwards.
+
+    Parameters
+    ----------
+    url : the url of the git repository.
+    rev, tag, branch : the commit, tag or branch to check out (at most one of
+                       them). If none is given, the default branch is used.
+    member : the path of the crate within the repository, if it is not located
+             at the repository's root (e.g. for workspaces).
+    fullname : the name of the module to import. Defaults to the crate's
+               library name.
+
+    Returns
+    -------
+    module : the compiled and loaded Python extension module
+    """
+    import os
+    import toml
+    from rustimport.git import get_cached_clone
+    from rustimport.importable import CrateImportable
+    from rustimport.importable import build_if_needed_and_load
+
+    path = get_cached_clone(url, rev=rev, tag=tag, branch=branch)
+    if member:
+        path = os.path.join(path, member)
+
+    if fullname is None and os.path.isfile(os.path.join(path, 'Cargo.toml')):
+        # The clone's directory name is not a valid module name, so we use the crate's name instead:
+        manifest = toml.load(os.path.join(path, 'Cargo.toml'))
+        fullname = manifest.get('lib', {}).get('name') or manifest.get('package', {}).get('name', '').replace('-', '_')
+
+    importable = CrateImportable.try_create(path, fullname=fullname, opt_in=False)
+    if importable is None:
+        raise ImportError(f"Couldn't find a crate in {url}" + (f" at {member}" if member else ""))
+
+    return build_if_needed_and_load(importable, force_rebuild=force_rebuild)
 
+
+def get_importable(path, fullname=None, opt_in: bool = False):
+    """
+    `get_importable` returns the `Importable` for the rust file or crate at the
+    given path without building or importing it, e.g. to inspect its build
+    status or to compute its `input_fingerprint()`.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate.
+    fullname : the name of the module. This can be different from the
+               module name inferred from the path if desired.
+
+    Returns
+    -------
+    importable : the `Importable` for the given path
+    """
     from rustimport.importable import all_importables
-    from rustimport.importable import should_rebuild
 
     for importable in all_importables:
         if i := importable.try_create(path, fullname=fullname, opt_in=opt_in):
-            if should_rebuild(importable, force_rebuild=force_rebuild):
-                i.build(release=settings.compile_release_binaries)
-            return importable.load()
+            return i
+    raise ImportError(f"Couldn't find a rust file or crate at {path} (opt_in: {opt_in})")
+
+
+def register_loader(predicate, loader):
+    """
+    `register_loader` registers a custom function to load built extensions,
+    e.g. for platforms where the default `dlopen`-based loading doesn't work.
+    The builtin loaders (for Python extensions and ctypes, see
+    `settings.load_mode`) are registered the same way; loaders registered
+    later take precedence.
+
+    Parameters
+    ----------
+    predicate : a callable without arguments returning whether the loader
+                applies, e.g. `lambda: sys.platform == "emscripten"`.
+    loader : a callable taking the path of the built library and the
+             module's full name, which returns the loaded module.
+    """
+    from rustimport.load import register_loader as _register_loader
+
+    _register_loader(predicate, loader)
+
+
+def configure_logging(level=_logging.INFO, stream=None) -> _logging.Handler:
+    """
+    `configure_logging` prints rustimport's log messages, e.g. when an
+    extension starts building, is up-to-date or fails to build. By default,
+    rustimport doesn't print any (only cargo's output, see
+    `settings.verbosity`), leaving it to the application to configure the
+    `rustimport` logger. Calling it again replaces the previous handler.
+
+    Parameters
+    ----------
+    level : the minimum level of the messages to print, e.g. `logging.DEBUG`
+            to also see the individual build steps.
+    stream : the stream to print the messages to. Defaults to stderr.
+
+    Returns
+    -------
+    handler : the added `logging.Handler`
+    """
+    global _log_handler
+
+    if _log_handler is not None:
+        _logger.removeHandler(_log_handler)
+    _log_handler = _logging.StreamHandler(stream)
+    _log_handler.setFormatter(_logging.Formatter("rustimport: %(message)s"))
+    _logger.addHandler(_log_handler)
+    _logger.setLevel(level)
+    return _log_handler
 
 
 def build(fullname, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild,
-          release: bool = settings.compile_release_binaries):
+          release: bool = settings.compile_release_binaries, verbosity: Optional[int] = None,
+          return_build_result: bool = False):
     """
     `build` builds a extension module like `imp` but does not import the
     extension.
@@ -113,22 +292,35 @@ def build(fullname, opt_in: bool = False, force_rebuild: bool = settings.force_r
     Parameters
     ----------
     fullname : the name of the module to import.
+    verbosity : how much of cargo's output to print while building (see `imp`).
+    return_build_result : if true, a tuple of the importable and a `BuildResult`
+                          is returned, telling whether the extension was rebuilt
+                          or up-to-date, how long that took and which warnings
+                          the compiler emitted.
 
     Returns
     -------
     ext_path : the path to the compiled extension.
     """
+    import time
     from rustimport.find import find_module_importable
-    from rustimport.importable import should_rebuild
-
-    importable = find_module_importable(fullname, opt_in=opt_in)
-    if should_rebuild(importable, force_rebuild=force_rebuild, force_release=release):
-        importable.build(release=release)
-    return importable
+    from rustimport.importable import BuildResult, should_rebuild
+
+    start = time.perf_counter()
+    call_settings = _call_settings(None, verbosity=verbosity)
+    with call_settings.applied():
+        importable = find_module_importable(fullname, opt_in=opt_in, call_settings=call_settings)
+        if should_rebuild(importable, force_rebuild=force_rebuild, force_release=release):
+            result = importable.build(release=release)
+        else:
+            result = BuildResult(rebuilt=False, artifact_path=importable.extension_path,
+                                 duration=time.perf_counter() - start)
+    return (importable, result) if return_build_result else importable
 
 
 def build_filepath(path, opt_in: bool = False, force_rebuild: bool = settings.force_rebuild,
-                   release: bool = settings.compile_release_binaries):
+                   release: Optional[bool] = None, return_metadata: bool = False,
+                   settings: Optional[Settings] = None):
     """
     `build_filepath` builds a extension module like `build` but allows
     to directly specify a file path.
@@ -137,24 +329,187 @@ def build_filepath(path, opt_in: bool = False, force_rebuild: bool = settings.fo
     ----------
     filepath : the filepath to the C++ file to build.
     fullname : the name of the module to build.
+    release : whether to build optimized release binaries. Defaults to
+              `settings.compile_release_binaries`.
+    return_metadata : if true, a tuple of the importable and its build metadata
+                      (cargo and rustc versions, resolver and enabled features) is returned.
+    settings : a `Settings` object overriding the global settings for this
+               call only (see `imp`).
 
     Returns
     -------
     ext_path : the path to the compiled extension.
     """
+    call_settings = settings or Settings()
+    with call_settings.applied():
+        return _build_filepath(path, opt_in, force_rebuild, release, return_metadata, call_settings)
 
+
+def _build_filepath(path, opt_in, force_rebuild, release, return_metadata, call_settings):
     from rustimport.importable import all_importables
     from rustimport.importable import should_rebuild
 
+    if release is None:
+        release = settings.compile_release_binaries
+
     for importable in all_importables:
-        if i := importable.try_create(path, opt_in=opt_in):
+        if i := importable.try_create(path, opt_in=opt_in, settings=call_settings):
             if should_rebuild(i, force_rebuild=force_rebuild, force_release=release):
-                importable.build(release=release)
-                return importable
+                i.build(release=release)
+            return (i, i.build_info) if return_metadata else i
+
+
+def _call_settings(call_settings: Optional[Settings], **overrides) -> Settings:
+    """Combine the per-call `Settings` with the settings given as arguments, ignoring those which are `None`."""
+    overrides = {key: value for key, value in overrides.items() if value is not None}
+    return Settings(**{**(call_settings.overrides if call_settings else {}), **overrides})
+
+
+def generate_package_shim(path, output_dir, fallback=None, opt_in: bool = False,
+                          release: bool = settings.compile_release_binaries):
+    """
+    `generate_package_shim` builds the extension at the given path and lays
+    it out as a conventional python package in `output_dir`: the extension
+    itself, an `__init__.py` re-exporting its public functions and classes
+    (as detected from its `#[pyfunction]` and `#[pyclass]` annotations), a
+    `.pyi` type stub and a `py.typed` marker.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate.
+    output_dir : the package directory to generate.
+    fallback : a module to import the same names from if the extension can't
+               be imported, e.g. a pure-python implementation (`".pure"`).
+
+    Returns
+    -------
+    init_path : the path of the generated `__init__.py`.
+    """
+    from rustimport.importable import should_rebuild
+    from rustimport.package_shim import generate_package_shim as _generate_package_shim
+
+    importable = get_importable(path, opt_in=opt_in)
+    if should_rebuild(importable, force_release=release):
+        importable.build(release=release)
+    return _generate_package_shim(importable, output_dir, fallback=fallback)
+
+
+def build_wheel(path, output_dir=".", opt_in: bool = False, force_rebuild: bool = settings.force_rebuild) -> str:
+    """
+    `build_wheel` builds the extension at the given path in release mode and
+    packages it into a wheel, which can be installed without a Rust toolchain.
+    The distribution is named after the module and versioned by the
+    `package.version` of its manifest (e.g. given by `//:` lines), which is
+    required. Extensions built for the stable ABI (see `settings.use_abi3`)
+    are tagged accordingly, so that one wheel covers multiple Python versions.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate.
+    output_dir : the directory to put the wheel into.
+
+    Returns
+    -------
+    wheel_path : the path of the built wheel.
+    """
+    from rustimport.wheel import build_wheel as _build_wheel, get_wheel_version
+
+    importable = get_importable(path, opt_in=opt_in)
+    get_wheel_version(importable)  # Fail before building if the version is missing
+    if force_rebuild or importable.needs_rebuild(release=True):
+        importable.build(release=True)
+    return _build_wheel(importable, output_dir)
+
+
+def api_diff(old, new, opt_in: bool = False):
+    """
+    `api_diff` compares the python APIs (functions, classes, methods and their
+    signatures) of two versions of an extension, e.g. to detect breaking
+    changes in CI.
+
+    Each version can be given as the path of a rust file or crate, whose API
+    is detected from its `#[pyfunction]`, `#[pyclass]` and `#[pymethods]`
+    annotations, or as the path of a built extension, which is imported in a
+    separate interpreter to inspect it. Types are only compared if both
+    versions are given as sources.
+
+    Parameters
+    ----------
+    old : the path of the old version's source or built extension.
+    new : the path of the new version's source or built extension.
+
+    Returns
+    -------
+    diff : an `ApiDiff` listing the added, removed and changed items.
+    """
+    from rustimport.api_changes import diff_apis, introspect_extension, is_extension_artifact
+
+    apis = [
+        introspect_extension(p) if is_extension_artifact(p) else get_importable(p, opt_in=opt_in).detect_exports()
+        for p in (old, new)
+    ]
+    return diff_apis(*apis, typed=not any(map(is_extension_artifact, (old, new))))
+
+
+def check_compiles(path, opt_in: bool = False):
+    """
+    `check_compiles` checks whether the rust file or crate at the given path
+    compiles, using `cargo check`. As no code is generated and nothing is
+    loaded, this is much faster than a full build, e.g. to catch type errors
+    while editing. Compiler messages are printed to stderr.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate to check.
+
+    Returns
+    -------
+    success : whether the check passed.
+    """
+    return get_importable(path, opt_in=opt_in).check()
+
+
+def run_tests(path, opt_in: bool = False):
+    """
+    `run_tests` runs the Rust tests of the rust file or crate at the given
+    path (e.g. an inline `#[cfg(test)] mod tests`) using `cargo test`. Their
+    output is printed to stdout and stderr.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate to test.
+
+    Returns
+    -------
+    success : whether all tests passed.
+    """
+    return get_importable(path, opt_in=opt_in).test()
+
+
+def clean(path=None, opt_in: bool = False):
+    """
+    `clean` removes the build directory of the rust file or crate at the given
+    path, or those of all extensions in the cache directory
+    (`settings.cache_dir`) if no path is given. This frees disk space and
+    recovers from a corrupted build directory. Built extensions are kept, but
+    the next build of each of them starts from scratch. Builds in progress are
+    waited for.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate whose build directory to
+           remove, or `None` to remove all build directories.
+    """
+    from rustimport.importable import clean_all_build_dirs
+
+    if path is not None:
+        get_importable(path, opt_in=opt_in).clean()
+    else:
+        clean_all_build_dirs()
 
 
 def build_all(root_directory, opt_in: bool = True, force_rebuild: bool = settings.force_rebuild,
-              release: bool = settings.compile_release_binaries):
+              release: bool = settings.compile_release_binaries, parallel: int = 1):
     """
     `build_all` builds a extension module like `build` for each eligible (that is,
     containing the "rustimport" header) source file within the given `root_directory`.
@@ -162,50 +517,142 @@ def build_all(root_directory, opt_in: bool = True, force_rebuild: bool = setting
     Parameters
     ----------
     root_directory : the root directory to search for cpp source files in.
+    parallel : the number of extensions to build concurrently, each in its own
+               process. A failing build doesn't stop the others.
+
+    Returns
+    -------
+    summary : a `BuildSummary` listing the extensions which were built, skipped
+              (as they were up-to-date) and failed (with their errors).
     """
-    import os
-    from rustimport.importable import (
-        SingleFileImportable,
-        CrateImportable,
-        should_rebuild,
-    )
-
-    importables = []
-
-    _logger.info(f"Collecting rust extensions in {root_directory}…")
-    for directory, subdirs, files in os.walk(root_directory, topdown=True):
-        if any(f.lower() == 'cargo.toml' for f in files):
-            if i := CrateImportable.try_create(directory, opt_in=opt_in):
-                importables.append(i)
-            # We never recurse into subdirectories of crates:
-            del subdirs[:]
-        else:
-            for file in files:
-                if os.path.splitext(file)[1] == '.rs':
-                    i = SingleFileImportable.try_create(os.path.join(directory, file), opt_in=opt_in)
-                    if i is not None:
-                        importables.append(i)
-
-    _logger.info(f"Found {len(importables)} {'extension' if len(importables) == 1 else 'extensions'}.")
-    not_built = []
-    for index, i in enumerate(importables):
-        if should_rebuild(i, force_rebuild=force_rebuild, force_release=release):
-            _logger.info(f"Building {i.path} ({index + 1}/{len(importables)})…")
-            i.build(release=release)
-        else:
-            not_built.append(i)
+    from rustimport.batch import build_importables
+    from rustimport.find import find_all_importables
 
-    if not_built:
-        _logger.info(f"Skipped building {len(not_built)} {'extension' if len(not_built) == 1 else 'extensions'} due"
-                     f" to unchanged source files. Re-run with `--force-rebuild` to rebuild everything.")
-    _logger.info("Completed successfully.")
+    importables = find_all_importables(root_directory, opt_in=opt_in)
+    summary = build_importables(importables, jobs=parallel, force_rebuild=force_rebuild, release=release)
+
+    if summary.skipped:
+        _logger.info(f"Skipped building {len(summary.skipped)} {'extension' if len(summary.skipped) == 1 else 'extensions'}"
+                     f" due to unchanged source files. Re-run with `--force-rebuild` to rebuild everything.")
+    if summary.failed:
+        _logger.error(f"Failed to build {len(summary.failed)} of {len(importables)} extensions: "
+                      + ", ".join(summary.failed))
+    else:
+        _logger.info("Completed successfully.")
+    return summary
+
+
+def bench(path, func, iterations: int = 1000, baseline=None, fullname=None):
+    """
+    `bench` builds the extension at the given path with release optimizations,
+    imports it and times the given callable.
+
+    Parameters
+    ----------
+    path : the path of the rust file or crate to benchmark.
+    func : a callable receiving the imported module, e.g. `lambda m: m.fib(20)`.
+    iterations : how often to call `func`.
+    baseline : an optional callable without arguments (e.g. a pure Python
+               implementation) to time for comparison.
+    fullname : the name of the module to import.
+
+    Returns
+    -------
+    result : a `BenchmarkResult` containing min, mean and p99 timings (in
+             seconds) and the speedup compared to the baseline, if given.
+    """
+    from rustimport.bench import measure
+    from rustimport.importable import all_importables
+    from rustimport.importable import should_rebuild
+
+    for importable in all_importables:
+        if i := importable.try_create(path, fullname=fullname, opt_in=False):
+            if should_rebuild(i, force_release=True):
+                i.build(release=True)
+            module = i.load()
+            break
+    else:
+        raise ImportError(f"Couldn't find a rust file or crate at {path}")
+
+    result = measure(func, (module,), iterations)
+    if baseline is not None:
+        result.baseline = measure(baseline, (), iterations)
+
+    _logger.info(f"Benchmark of {path}: {result}")
+    return result
 
 
 class BuildError(Exception):
     """Raised if building a native rust extension fails"""
 
 
+class CircularDependencyError(BuildError):
+    """Raised if building a native rust extension (transitively) requires building itself"""
+
+    def __init__(self, cycle):
+        self.cycle = cycle
+        super().__init__("Circular dependency detected while building: " + " -> ".join(cycle))
+
+
+class BuildLockTimeoutError(BuildError):
+    """Raised if another process building the same native rust extension didn't finish in time (see `settings.build_lock_timeout`)"""
+
+
+class ModuleNameMismatchError(BuildError):
+    """Raised if the `#[pymodule]` name or `lib.name` of a native rust extension don't match its python module name"""
+
+    def __init__(self, path, import_name, pymodule_name, lib_name, explicit=False):
+        self.path = path
+        self.import_name = import_name
+        self.pymodule_name = pymodule_name
+        self.lib_name = lib_name
+        name = import_name.split('.')[-1]
+        if explicit and lib_name == name:
+            # The import name was requested explicitly (and the template named the library after it), so only a
+            # handwritten #[pymodule] can be at fault:
+            super().__init__(
+                f"{path} was requested to be imported as {import_name}, but its #[pymodule] is named "
+                f"{pymodule_name}, so Python can't find its init function PyInit_{name}. Rename the #[pymodule] "
+                f"(or set its name using `#[pyo3(name = \"{name}\")]`), or import it as {pymodule_name}."
+            )
+            return
+        if pymodule_name == lib_name:
+            # The #[pymodule] matches the library, e.g. as the template named the generated one after a `lib.name`
+            # given by the manifest, so only the import name is off:
+            super().__init__(
+                f"{path} is imported as {import_name}, but its library and #[pymodule] are named {lib_name} (e.g. "
+                f"due to `lib.name` in its Cargo manifest), so Python can't find its init function PyInit_{name}. "
+                f"Import it as {lib_name}, or set `lib.name` to {name!r} (renaming a handwritten #[pymodule] "
+                f"accordingly)."
+            )
+            return
+        super().__init__(
+            f"The module names of {path} don't match:\n"
+            f"  - python module name: {import_name} (Python calls the init function PyInit_{name} to load it)\n"
+            f"  - #[pymodule] name:   {pymodule_name} (pyo3 names the init function after it)\n"
+            f"  - lib.name:           {lib_name} (cargo names the library after it)\n"
+            f"Rename the #[pymodule] (or set its name using `#[pyo3(name = \"{name}\")]`) and set `lib.name` in the "
+            f"Cargo manifest to {name!r}, or rename the file or crate to match the #[pymodule]."
+        )
+
+
+class InsufficientDiskSpaceError(BuildError):
+    """Raised if there is not enough free disk space to build a native rust extension (see `settings.min_free_space`)"""
+
+    def __init__(self, path, free, required):
+        self.path = path
+        self.free = free
+        self.required = required
+        super().__init__(
+            f"Not enough free disk space to build in {path}: {free / 1024 ** 2:.0f} MiB available, but at least "
+            f"{required / 1024 ** 2:.0f} MiB required. Free up some space, move `settings.cache_dir` (and "
+            f"`settings.cargo_target_dir`) to a larger file system or lower `settings.min_free_space`."
+        )
+
+
 __all__ = [
-    'settings', 'imp', 'imp_from_path', 'build',
-    'build_filepath', 'build_all', 'BuildError',
+    'settings', 'Settings', 'imp', 'imp_from_path', 'imp_from_git', 'get_importable', 'register_loader', 'configure_logging',
+    'build', 'bench',
+    'build_filepath', 'build_all', 'build_wheel', 'check_compiles', 'run_tests', 'clean', 'generate_package_shim', 'api_diff', 'BuildError', 'CircularDependencyError',
+    'BuildLockTimeoutError', 'ModuleNameMismatchError', 'InsufficientDiskSpaceError',
 ]
@@ -1,10 +1,16 @@
 import argparse
+import json
 import logging
 import os
 import re
 import sys
+from typing import List
 
-from rustimport import build_all, build_filepath, settings
+from rustimport import api_diff, build_all, build_filepath, build_wheel, clean, get_importable, run_tests, settings
+from rustimport.importable import Importable
+
+# When run via `python -m rustimport`, `__name__` is "__main__", so the package's logger is used explicitly:
+_logger = logging.getLogger("rustimport")
 
 rust_lib_template = """// rustimport:pyo3
 
@@ -53,7 +59,15 @@ name = "{{EXTENSION_NAME}}"
 crate-type = ["cdylib"]
 
 [dependencies]
-pyo3 = { version = "0.16.2", features = ["extension-module"] }
+pyo3 = { version = "0.16.2" }
+
+[features]
+# pyo3's "extension-module" feature is required for Python extensions, but it prevents standalone
+# binaries (like those built by `cargo test` or `cargo run`) from linking to libpython. Therefore, it is
+# enabled by default, but can be disabled for such use cases, e.g.:
+# $ cargo test --no-default-features
+default = ["extension-module"]
+extension-module = ["pyo3/extension-module"]
 """
 
 
@@ -79,6 +93,53 @@ def create_extension(name: str):
             f.write("This is a marker-file to make this crate importable by rustimport.")
 
 
+def list_extensions(root_directory: str):
+    from rustimport.find import find_all_importables
+
+    for i in find_all_importables(root_directory):
+        info = i.build_info
+
+        if not os.path.isfile(i.extension_path):
+            status = "not built"
+        elif i.needs_rebuild(release=info.get('release', False) if info else False):
+            status = "outdated"
+        else:
+            status = "up-to-date"
+
+        print(f"{i.path} ({status})")
+        if info:
+            print(f"  cargo:    {info['cargo_version']}")
+            print(f"  rustc:    {info['rustc_version']}")
+            print(f"  resolver: {info['resolver']}")
+            print(f"  features: {', '.join(info['features']) or '-'}")
+            print(f"  release:  {info['release']}")
+            print(f"  built at: {info['built_at']}")
+        for checksum, path in i.old_artifacts:
+            print(f"  retained: {checksum} ({path})")
+
+
+def collect_importables(paths: List[str]) -> List[Importable]:
+    from rustimport.find import find_all_importables
+
+    importables = []
+    for path in paths:
+        path = os.path.abspath(os.path.expandvars(path))
+        if os.path.isdir(path) and not os.path.isfile(os.path.join(path, 'Cargo.toml')):
+            importables.extend(find_all_importables(path))
+        elif os.path.exists(path):
+            importables.append(get_importable(path))
+        else:
+            raise FileNotFoundError(f'The given root path "{path}" could not be found.')
+    return importables
+
+
+def check_extensions(path: str) -> bool:
+    failed = [i for i in collect_importables([path]) if not i.check()]
+    for i in failed:
+        _logger.error(f"{i.path} failed to compile.")
+    return not failed
+
+
 def _run_from_commandline(raw_args):
     parser = argparse.ArgumentParser("rustimport")
 
@@ -88,6 +149,11 @@ def _run_from_commandline(raw_args):
     parser.add_argument(
         "--quiet", "-q", action="store_true", help="Only print critical log messages."
     )
+    parser.add_argument(
+        "--verbosity", type=int, choices=(0, 1, 2), help="How much of cargo's output to print while building: 0 "
+                                                         "(only errors), 1 (errors and a line per built extension) "
+                                                         "or 2 (everything, the default)."
+    )
 
     subparsers = parser.add_subparsers(dest="action", required=True)
 
@@ -108,6 +174,77 @@ def _run_from_commandline(raw_args):
     build_parser.add_argument(
         "--release", "-r", action="store_true", help="Build release-optimized binaries (toggle's cargo's --release flag)."
     )
+    build_parser.add_argument(
+        "--check-only", action="store_true", help="Only check whether the extensions compile (using `cargo check`), "
+                                                  "without building them. This is much faster than a full build."
+    )
+    build_parser.add_argument(
+        "--watch", "-w", action="store_true", help="Keep running after building and rebuild the extensions whenever "
+                                                   "one of their source files changes."
+    )
+    build_parser.add_argument(
+        "--jobs", "-j", type=int, default=1, help="The number of extensions to build concurrently (when building "
+                                                  "directories)."
+    )
+    build_parser.add_argument(
+        "--target", help="The target triple to cross-compile the extensions for (e.g. aarch64-unknown-linux-gnu) "
+                         "instead of the host."
+    )
+    build_parser.add_argument(
+        "--pyodide", action="store_true", help="Build the extensions for Pyodide (WebAssembly) instead of the host."
+    )
+    build_parser.add_argument(
+        "--target-dir", help="A persistent cargo target directory to share between all builds and invocations, "
+                             "so that dependencies only need to be compiled once."
+    )
+
+    wheel_parser = subparsers.add_parser(
+        "wheel",
+        help="Build an extension in release mode and package it into a wheel, which can be installed without a Rust "
+             "toolchain. Requires the extension's manifest to specify a version.",
+    )
+    wheel_parser.add_argument("path")
+    wheel_parser.add_argument(
+        "--out-dir", "-o", default=".", help="The directory to put the wheel into (default: the current directory)."
+    )
+    wheel_parser.add_argument(
+        "--force", "-f", action="store_true", help="Force rebuild."
+    )
+
+    test_parser = subparsers.add_parser(
+        "test",
+        help="Run the Rust tests (e.g. inline `#[cfg(test)]` modules) of one or more source files or crates.",
+    )
+    test_parser.add_argument("path", nargs="+")
+
+    clean_parser = subparsers.add_parser(
+        "clean",
+        help="Remove the build directories of the given source files or crates, or those of all extensions if "
+             "none are given. Built extensions are kept.",
+    )
+    clean_parser.add_argument("path", nargs="*")
+    clean_parser.add_argument(
+        "--cache", action="store_true", help="Only purge the artifact cache (see `settings.artifact_cache`)."
+    )
+
+    list_parser = subparsers.add_parser(
+        "list",
+        help="List all eligible extensions, whether they are up-to-date and how they were built.",
+    )
+    list_parser.add_argument(
+        "root",
+        help="The directories to search for extensions in (recursively).",
+        nargs="*",
+    )
+
+    api_diff_parser = subparsers.add_parser(
+        "api-diff",
+        help="Compare the Python APIs of two versions of an extension (given as sources or built extensions) and "
+             "exit with status 1 if the API changed incompatibly (i.e. anything was removed or changed).",
+    )
+    api_diff_parser.add_argument("old")
+    api_diff_parser.add_argument("new")
+    api_diff_parser.add_argument("--json", action="store_true", help="Print the differences as JSON.")
 
     new_parser = subparsers.add_parser(
         "new",
@@ -120,23 +257,76 @@ def _run_from_commandline(raw_args):
 
     if args.quiet:
         logging.basicConfig(level=logging.CRITICAL)
+        settings.summary = False
     elif args.verbose:
         logging.basicConfig(level=logging.DEBUG)
     else:
         logging.basicConfig(level=logging.INFO)
 
+    if args.verbosity is not None:
+        settings.verbosity = args.verbosity
+
     if args.action == "build":
         release = args.release or settings.compile_release_binaries
         force = args.force or settings.force_rebuild
 
+        if args.target_dir:
+            settings.cargo_target_dir = os.path.abspath(os.path.expandvars(args.target_dir))
+        if args.target:
+            settings.target = args.target
+        if args.pyodide:
+            settings.pyodide = True
+
+        if args.check_only:
+            if not all(check_extensions(os.path.abspath(os.path.expandvars(path))) for path in args.root or ["."]):
+                sys.exit(1)
+            return
+
+        if args.watch:
+            from rustimport.watch import watch
+            watch(collect_importables(args.root or ["."]), release=release, force_rebuild=force)
+            return
+
+        failed = False
         for path in args.root or ["."]:
             path = os.path.abspath(os.path.expandvars(path))
             if os.path.isfile(path):
                 build_filepath(path, release=release, force_rebuild=force)
             elif os.path.isdir(path):
-                build_all(path, release=release, force_rebuild=force)
+                if not build_all(path, release=release, force_rebuild=force, parallel=args.jobs).success:
+                    failed = True
             else:
                 raise FileNotFoundError(f'The given root path "{path}" could not be found.')
+        if failed:
+            sys.exit(1)
+    elif args.action == "wheel":
+        try:
+            wheel_path = build_wheel(os.path.abspath(os.path.expandvars(args.path)), args.out_dir,
+                                     force_rebuild=args.force or settings.force_rebuild)
+        except ValueError as e:
+            sys.stderr.write(f"{e}\n")
+            sys.exit(1)
+        print(wheel_path)
+    elif args.action == "test":
+        if not all([run_tests(os.path.abspath(os.path.expandvars(path))) for path in args.path]):
+            sys.exit(1)
+    elif args.action == "clean":
+        if args.cache:
+            from rustimport.checksum import clear_artifact_cache
+            clear_artifact_cache()
+            return
+        for path in args.path:
+            clean(os.path.abspath(os.path.expandvars(path)))
+        if not args.path:
+            clean()
+    elif args.action == "list":
+        for path in args.root or ["."]:
+            list_extensions(os.path.abspath(os.path.expandvars(path)))
+    elif args.action == "api-diff":
+        diff = api_diff(os.path.abspath(args.old), os.path.abspath(args.new))
+        print(json.dumps(diff.to_dict(), indent=2) if args.json else diff)
+        if diff.is_breaking:
+            sys.exit(1)
     elif args.action == "new":
         create_extension(args.path)
     else:
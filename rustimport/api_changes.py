@@ -0,0 +1,185 @@
+import json
+import os
+import re
+import subprocess
+import sys
+from dataclasses import dataclass, field, replace
+from typing import List, Optional, Dict
+
+from rustimport.pre_processing.stubs import ModuleApi, FunctionSignature, ClassSignature
+
+
+@dataclass
+class ApiChange:
+    """A change of a function, class, method or submodule of an extension's API."""
+    kind: str
+    """One of `"added"`, `"removed"` or `"changed"`."""
+    name: str
+    """The qualified name of the changed item, e.g. `"myfunc"` or `"MyClass.method"`."""
+    old: Optional[str] = None
+    """The old signature, unless the item has been added."""
+    new: Optional[str] = None
+    """The new signature, unless the item has been removed."""
+
+
+@dataclass
+class ApiDiff:
+    """The differences between the python APIs of two versions of an extension (see `rustimport.api_diff`)."""
+    changes: List[ApiChange] = field(default_factory=list)
+    typed: bool = True
+    """Whether types have been compared. This is only the case if both versions have been given as sources."""
+
+    @property
+    def added(self) -> List[ApiChange]:
+        return [c for c in self.changes if c.kind == 'added']
+
+    @property
+    def removed(self) -> List[ApiChange]:
+        return [c for c in self.changes if c.kind == 'removed']
+
+    @property
+    def changed(self) -> List[ApiChange]:
+        return [c for c in self.changes if c.kind == 'changed']
+
+    @property
+    def is_breaking(self) -> bool:
+        """Whether any item has been removed or changed, which might break code using the extension."""
+        return bool(self.removed or self.changed)
+
+    def to_dict(self) -> Dict:
+        return {'typed': self.typed, 'breaking': self.is_breaking, 'changes': [c.__dict__ for c in self.changes]}
+
+    def __str__(self):
+        lines = []
+        for c in self.changes:
+            if c.kind == 'added':
+                lines.append(f'+ {c.name}: {c.new}')
+            elif c.kind == 'removed':
+                lines.append(f'- {c.name}: {c.old}')
+            else:
+                lines.append(f'~ {c.name}: {c.old}  ->  {c.new}')
+        return '\n'.join(lines) or 'No API changes'
+
+
+def diff_apis(old: ModuleApi, new: ModuleApi, typed: bool = True) -> ApiDiff:
+    """
+    Compare two APIs. If `typed` is false, only the names, parameters and kinds (e.g. `staticmethod`) of
+    functions and methods are compared, but not their types.
+    """
+    old_items, new_items = _flatten(old, typed), _flatten(new, typed)
+
+    changes = []
+    for name in sorted(old_items.keys() | new_items.keys()):
+        old_signature, new_signature = old_items.get(name), new_items.get(name)
+        if old_signature is None:
+            changes.append(ApiChange('added', name, new=new_signature))
+        elif new_signature is None:
+            changes.append(ApiChange('removed', name, old=old_signature))
+        elif old_signature != new_signature and _UNKNOWN_SIGNATURE not in (old_signature, new_signature):
+            changes.append(ApiChange('changed', name, old=old_signature, new=new_signature))
+    return ApiDiff(changes=changes, typed=typed)
+
+
+# The signature of a function introspected from an extension which doesn't provide its text signature:
+_UNKNOWN_SIGNATURE = '(...)'
+
+
+def _flatten(api: ModuleApi, typed: bool) -> Dict[str, str]:
+    def render(f: FunctionSignature) -> str:
+        if not f.signature_known:
+            return _UNKNOWN_SIGNATURE
+        if not typed:
+            f = replace(f, parameters=[_strip_annotation(p) for p in f.parameters], returns='Any')
+        return ' '.join(f'@{d}' for d in f.decorators) + (' ' if f.decorators else '') \
+            + f"({', '.join(f.parameters)})" + (f' -> {f.returns}' if typed else '')
+
+    items = {s: 'module' for s in api.submodules}
+    items.update({f.name: render(f) for f in api.functions})
+    for c in api.classes:
+        items[c.name] = 'class'
+        items.update({f'{c.name}.{m.name}': render(m) for m in c.methods})
+    return items
+
+
+def _strip_annotation(parameter: str) -> str:
+    name, _, rest = parameter.partition(':')
+    return name.strip() + (' = ...' if '=' in rest or '=' in name else '')
+
+
+_INTROSPECTION_SCRIPT = """
+import importlib.util, inspect, json, sys
+spec = importlib.util.spec_from_file_location(sys.argv[2], sys.argv[1])
+module = importlib.util.module_from_spec(spec)
+spec.loader.exec_module(module)
+
+def signature(obj, receiver=None, decorators=()):
+    try:
+        params = []
+        for p in inspect.signature(obj).parameters.values():
+            if p.kind == p.VAR_POSITIONAL:
+                params.append('*' + p.name)
+            elif p.kind == p.VAR_KEYWORD:
+                params.append('**' + p.name)
+            else:
+                params.append(p.name + (' = ...' if p.default is not p.empty else ''))
+        known = True
+    except (ValueError, TypeError):
+        params, known = [], False
+    return {'parameters': ([receiver] if receiver and known else []) + params, 'known': known,
+            'decorators': list(decorators)}
+
+api = {'functions': [], 'classes': [], 'submodules': []}
+for name, obj in vars(module).items():
+    if name.startswith('_'):
+        continue
+    if inspect.ismodule(obj):
+        api['submodules'].append(name)
+    elif isinstance(obj, type):
+        constructor = signature(obj, 'self')
+        methods = [{'name': '__init__', **constructor}] if constructor['known'] else []
+        for attr, value in vars(obj).items():
+            if attr.startswith('_'):
+                continue
+            if inspect.isdatadescriptor(value):
+                methods.append({'name': attr, 'parameters': ['self'], 'known': True, 'decorators': ['property']})
+            elif isinstance(value, (staticmethod, classmethod)) or type(value).__name__ == 'classmethod_descriptor':
+                kind = 'staticmethod' if isinstance(value, staticmethod) else 'classmethod'
+                methods.append({'name': attr, **signature(getattr(obj, attr), 'cls' if kind == 'classmethod' else None, [kind])})
+            elif callable(value):
+                methods.append({'name': attr, **signature(value)})
+        api['classes'].append({'name': name, 'methods': methods})
+    elif callable(obj):
+        api['functions'].append({'name': name, **signature(obj)})
+print(json.dumps(api))
+"""
+
+
+def introspect_extension(extension_path: str, fullname: Optional[str] = None) -> ModuleApi:
+    """
+    Determine the API of a built extension by importing it in a separate interpreter. Types aren't available
+    this way, and signatures only if the extension provides them (pyo3 does so since version 0.19).
+    """
+    fullname = fullname or os.path.basename(extension_path).split('.')[0]
+    proc = subprocess.run(
+        [sys.executable, '-c', _INTROSPECTION_SCRIPT, extension_path, fullname],
+        capture_output=True,
+        text=True,
+    )
+    if proc.returncode != 0:
+        raise ImportError(f"Failed to import {extension_path} to determine its API:\n{proc.stderr}",
+                          name=fullname, path=extension_path)
+    api = json.loads(proc.stdout)
+
+    def function(f: Dict) -> FunctionSignature:
+        return FunctionSignature(name=f['name'], parameters=f['parameters'], returns='Any',
+                                 decorators=f['decorators'], signature_known=f['known'])
+
+    return ModuleApi(
+        functions=[function(f) for f in api['functions']],
+        classes=[ClassSignature(name=c['name'], methods=[function(m) for m in c['methods']]) for c in api['classes']],
+        submodules=api['submodules'],
+    )
+
+
+def is_extension_artifact(path: str) -> bool:
+    return os.path.isfile(path) and re.search(r'\.(so|pyd|dylib)$', path) is not None
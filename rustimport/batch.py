@@ -0,0 +1,113 @@
+import logging
+from collections import OrderedDict
+from concurrent.futures import ProcessPoolExecutor
+from dataclasses import dataclass, field
+from typing import List, Dict, Any, Tuple, Optional, Type
+
+from rustimport import settings
+from rustimport.importable import Importable, should_rebuild
+from rustimport.settings import Settings
+
+_logger = logging.getLogger(__name__)
+
+
+@dataclass
+class BuildSummary:
+    """The outcome of building several extensions, e.g. using `build_all`."""
+    built: List[str] = field(default_factory=list)
+    """The paths of the extensions which have been built."""
+    skipped: List[str] = field(default_factory=list)
+    """The paths of the extensions which have not been built, as they were up-to-date."""
+    failed: Dict[str, str] = field(default_factory=dict)
+    """The paths of the extensions which failed to build, mapped to the error."""
+
+    @property
+    def success(self) -> bool:
+        return not self.failed
+
+    def __str__(self):
+        return f"{len(self.built)} built, {len(self.skipped)} up-to-date, {len(self.failed)} failed"
+
+
+# An importable in a form which can be sent to worker processes: its class, path, full name, explicit
+# module name (if any), per-call settings and index.
+_ImportableRef = Tuple[Type[Importable], str, str, Optional[str], Settings, int]
+
+
+def build_importables(importables: List[Importable], jobs: int = 1, force_rebuild: bool = False,
+                      release: bool = False) -> BuildSummary:
+    """
+    Build the given importables, if they need to be rebuilt, using up to `jobs` worker processes. A failing
+    build doesn't stop the others; all failures are collected in the returned summary.
+
+    Importables sharing a build directory (e.g. the same crate found twice) are built one after another by the
+    same worker, so that they never write to the same directory at the same time.
+    """
+    groups: Dict[str, List[_ImportableRef]] = OrderedDict()
+    for index, i in enumerate(importables):
+        groups.setdefault(i.build_tempdir, []).append((type(i), i.path, i.fullname, i.module_name, i.settings, index))
+
+    summary = BuildSummary()
+    total = len(importables)
+    if jobs <= 1 or len(groups) <= 1:
+        for group in groups.values():
+            _add_results(summary, _build_group(group, force_rebuild, release, total))
+        return summary
+
+    # The workers don't necessarily inherit settings assigned in code (e.g. with the "spawn" start method):
+    settings_snapshot = {k: getattr(settings, k) for k in settings._CONFIGURABLE_SETTINGS}
+    _logger.info(f"Building {total} {'extension' if total == 1 else 'extensions'} using up to {jobs} processes…")
+    with ProcessPoolExecutor(max_workers=jobs, initializer=_apply_settings, initargs=(settings_snapshot,)) as executor:
+        futures = [
+            executor.submit(_build_group, [_to_worker_ref(ref) for ref in g], force_rebuild, release, total)
+            for g in groups.values()
+        ]
+        for future in futures:
+            _add_results(summary, future.result())
+    return summary
+
+
+def _apply_settings(snapshot: Dict[str, Any]):
+    for key, value in snapshot.items():
+        setattr(settings, key, value)
+
+
+def _to_worker_ref(ref: _ImportableRef) -> _ImportableRef:
+    """
+    Prepare the reference to an importable for being sent to a worker process. Settings which can only be given in
+    code (e.g. `progress_callback`) are dropped from its per-call settings, as they can't necessarily be pickled.
+    """
+    cls, path, fullname, module_name, call_settings, index = ref
+    overrides = {k: v for k, v in call_settings.overrides.items() if k in settings._CONFIGURABLE_SETTINGS}
+    return cls, path, fullname, module_name, Settings(**overrides), index
+
+
+def _build_group(group: List[_ImportableRef], force_rebuild: bool, release: bool,
+                 total: int) -> List[Tuple[str, str, Optional[str]]]:
+    """Build the given importables one after another and return a `(path, status, error)` tuple for each."""
+    results = []
+    for cls, path, fullname, module_name, call_settings, index in group:
+        try:
+            importable = cls.try_create(path, fullname=fullname, opt_in=False, module_name=module_name,
+                                        settings=call_settings)
+            if not should_rebuild(importable, force_rebuild=force_rebuild, force_release=release):
+                results.append((path, 'skipped', None))
+                continue
+            _logger.info(f"Building {path} ({index + 1}/{total})…")
+            importable.build(release=release)
+            results.append((path, 'built', None))
+        except Exception as e:
+            # Exceptions are reported as strings, as not all of them can be sent back from worker processes:
+            _logger.error(f"Failed to build {path}: {e}")
+            results.append((path, 'failed', str(e) or type(e).__name__))
+    return results
+
+
+def _add_results(summary: BuildSummary, results: List[Tuple[str, str, Optional[str]]]):
+    for path, status, error in results:
+        if status == 'built':
+            summary.built.append(path)
+        elif status == 'skipped':
+            summary.skipped.append(path)
+        else:
+            summary.failed[path] = error
@@ -0,0 +1,49 @@
+import math
+import statistics
+import time
+from dataclasses import dataclass
+from typing import Callable, Any, List, Optional
+
+
+@dataclass
+class BenchmarkResult:
+    iterations: int
+    min: float
+    mean: float
+    p99: float
+    baseline: Optional['BenchmarkResult'] = None
+
+    @property
+    def speedup(self) -> Optional[float]:
+        """The speedup compared to the baseline (based on the mean), if a baseline was measured."""
+        return self.baseline.mean / self.mean if self.baseline and self.mean else None
+
+    def __str__(self):
+        res = f"{self.iterations} iterations: min {_fmt(self.min)}, mean {_fmt(self.mean)}, p99 {_fmt(self.p99)}"
+        if self.baseline:
+            res += f" (baseline: mean {_fmt(self.baseline.mean)}, speedup {self.speedup:.2f}x)"
+        return res
+
+
+def measure(func: Callable[..., Any], args: tuple, iterations: int) -> BenchmarkResult:
+    """Call `func(*args)` `iterations` times and collect timing statistics (in seconds)."""
+    timings: List[float] = []
+    for _ in range(iterations):
+        start = time.perf_counter()
+        func(*args)
+        timings.append(time.perf_counter() - start)
+
+    timings.sort()
+    return BenchmarkResult(
+        iterations=iterations,
+        min=timings[0],
+        mean=statistics.fmean(timings),
+        p99=timings[min(len(timings) - 1, math.ceil(len(timings) * 0.99) - 1)],
+    )
+
+
+def _fmt(seconds: float) -> str:
+    for unit, factor in (('s', 1), ('ms', 1e-3), ('µs', 1e-6)):
+        if seconds >= factor:
+            return f"{seconds / factor:.3f}{unit}"
+    return f"{seconds / 1e-9:.0f}ns"
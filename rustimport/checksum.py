@@ -1,8 +1,11 @@
 import glob
+import hashlib
+import json
 import logging
 import os
+import shutil
 import struct
-from typing import List, Tuple, Optional
+from typing import List, Optional, Dict
 
 from rustimport import settings
 
@@ -12,16 +15,25 @@ _FMT = struct.Struct("q" + str(len(_TAG)) + "s")
 logger = logging.getLogger(__name__)
 
 
-def is_checksum_valid(extension_path: str, file_patterns: List[str], release: bool = False) -> bool:
+def is_checksum_valid(extension_path: str, file_patterns: List[str], release: bool = False,
+                      build_env: Optional[Dict[str, str]] = None, inputs: Optional[Dict[str, str]] = None) -> bool:
     """
-    Load the saved checksum from the extension file check if it matches the
+    Load the saved checksum for the extension file and check if it matches the
     checksum computed from current source files.
+
+    `build_env` contains the environment variables supplied to cargo which affect code
+    generation, and `inputs` further values affecting the build (e.g. settings used by
+    preprocessing); any change to them invalidates the checksum just like a source change.
     """
-    old_checksum = _load_checksum_trailer(extension_path)
+    old_checksum = _load_checksum(extension_path)
     if old_checksum is None:
-        return False  # Already logged error in load_checksum_trailer.
+        return False  # Already logged error in _load_checksum.
     try:
-        return old_checksum == _calc_cur_checksum(file_patterns, release=release)
+        if settings.checksum_strategy == 'mtime_size':
+            return _is_mtime_size_checksum_valid(extension_path, old_checksum, file_patterns, release=release,
+                                                 build_env=build_env, inputs=inputs)
+        return old_checksum == _calc_cur_checksum(file_patterns, release=release, build_env=build_env,
+                                                  base_dir=os.path.dirname(extension_path), inputs=inputs)
     except OSError as e:
         logger.info(
             "Checksummed file not found while checking rustimport checksum "
@@ -30,12 +42,214 @@ def is_checksum_valid(extension_path: str, file_patterns: List[str], release: bo
         return False
 
 
-def save_checksum(extension_path: str, file_patterns: List[str], release: bool = False):
+def save_checksum(extension_path: str, file_patterns: List[str], release: bool = False,
+                  build_env: Optional[Dict[str, str]] = None, inputs: Optional[Dict[str, str]] = None):
+    """
+    Calculate the module checksum and then save it to the checksum directory
+    (see `settings.checksum_dir`).
+    """
+    index = {}
+    _save_checksum(extension_path, _calc_cur_checksum(
+        file_patterns, release=release, build_env=build_env, base_dir=os.path.dirname(extension_path), index=index,
+        inputs=inputs,
+    ))
+    if settings.checksum_strategy == 'mtime_size':
+        _save_file_index(extension_path, index)
+
+
+def is_toolchain_fingerprint_valid(extension_path: str, fingerprint: Dict[str, Optional[str]]) -> bool:
+    """
+    Check whether the extension has been built with the toolchain described by the given fingerprint (e.g. the
+    versions of rustc and pyo3, see `save_toolchain_fingerprint`). Extensions whose toolchain is unknown, e.g.
+    because they were built by an older version of rustimport, are considered outdated.
+    """
+    try:
+        with open(_get_toolchain_fingerprint_path(extension_path), "r") as f:
+            old_fingerprint = json.load(f)
+    except (FileNotFoundError, json.JSONDecodeError):
+        logger.info("The toolchain the extension has been built with is unknown; rebuilding.")
+        return False
+
+    for key in sorted(old_fingerprint.keys() | fingerprint.keys()):
+        if old_fingerprint.get(key) != fingerprint.get(key):
+            logger.info(f"The {key} version changed from {old_fingerprint.get(key)} to {fingerprint.get(key)}; "
+                        f"rebuilding.")
+            return False
+    return True
+
+
+def save_toolchain_fingerprint(extension_path: str, fingerprint: Dict[str, Optional[str]]):
+    """Save the fingerprint of the toolchain the extension has been built with, next to its checksum."""
+    path = _get_toolchain_fingerprint_path(extension_path)
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, "w") as f:
+        json.dump(fingerprint, f, indent=2, sort_keys=True)
+
+
+def get_checksum(extension_path: str) -> Optional[str]:
+    """
+    Return the (content) checksum of the sources the given extension has been built from,
+    or `None` if the extension has no checksum.
+    """
+    checksum = _load_checksum(extension_path)
+    # The "mtime_size" strategy saves two checksums separated by a slash, the latter being the content checksum:
+    return checksum.split(b"/")[-1].decode() if checksum is not None else None
+
+
+def calc_input_fingerprint(file_patterns: List[str], base_dir: str, inputs: Dict[str, str], release: bool = False,
+                           build_env: Optional[Dict[str, str]] = None, hasher=None) -> str:
+    """
+    Calculate a hash of the contents of all files matching `file_patterns` (with their paths relative
+    to `base_dir`), the build configuration and the given additional `inputs`. Unlike the checksum, this
+    never depends on file modification times, so that it is stable across checkouts and machines.
+    """
+    hasher = hasher or settings.checksum_hasher
+    config = _build_config_payload(release, build_env, inputs)
+    return _calc_payload_checksum(_content_entries(collect_files(file_patterns), hasher, base_dir), hasher, config).decode()
+
+
+def get_artifact_cache_dir() -> str:
+    """The directory of the artifact cache (see `settings.artifact_cache`)."""
+    return os.path.join(settings.cache_dir, 'artifacts')
+
+
+def restore_cached_artifact(fingerprint: str, destinations: Dict[str, str]) -> bool:
+    """
+    Copy the files cached for the given input fingerprint to their destinations, given by their names in the
+    cache entry (see `store_cached_artifact`). Returns whether the cache contained them.
+    """
+    entry = os.path.join(get_artifact_cache_dir(), fingerprint)
+    if not all(os.path.isfile(os.path.join(entry, name)) for name in destinations):
+        return False
+
+    for name, destination in destinations.items():
+        os.makedirs(os.path.dirname(destination), exist_ok=True)
+        # Replace the destination at once, as overwriting a library which is currently loaded corrupts it:
+        shutil.copy2(os.path.join(entry, name), destination + '.tmp')
+        os.replace(destination + '.tmp', destination)
+    # Mark the entry as recently used for the eviction:
+    os.utime(entry)
+    logger.info(f"Restored {', '.join(destinations.values())} from the artifact cache ({fingerprint}).")
+    return True
+
+
+def store_cached_artifact(fingerprint: str, files: Dict[str, str]):
+    """
+    Store the given files (by their names in the cache entry) in the artifact cache under the given input
+    fingerprint and evict the least recently used entries if the cache exceeds `settings.artifact_cache_max_size`.
+    """
+    cache_dir = get_artifact_cache_dir()
+    entry = os.path.join(cache_dir, fingerprint)
+    if os.path.isdir(entry):
+        return
+
+    # The entry is written to a temporary directory first, so that concurrent builds (possibly on other
+    # machines sharing the cache) never see incomplete entries:
+    tmp_entry = f'{entry}.tmp-{os.getpid()}'
+    os.makedirs(tmp_entry, exist_ok=True)
+    for name, path in files.items():
+        shutil.copy2(path, os.path.join(tmp_entry, name))
+    try:
+        os.rename(tmp_entry, entry)
+    except OSError:
+        shutil.rmtree(tmp_entry, ignore_errors=True)  # Stored by another build in the meantime
+
+    _evict_cached_artifacts(cache_dir, keep=fingerprint)
+
+
+def clear_artifact_cache():
+    """Remove all artifacts from the artifact cache."""
+    if os.path.isdir(get_artifact_cache_dir()):
+        shutil.rmtree(get_artifact_cache_dir())
+
+
+def _evict_cached_artifacts(cache_dir: str, keep: str):
+    if settings.artifact_cache_max_size <= 0:
+        return
+
+    entries = []
+    for name in os.listdir(cache_dir):
+        entry = os.path.join(cache_dir, name)
+        try:
+            size = sum(os.path.getsize(os.path.join(entry, f)) for f in os.listdir(entry))
+            entries.append((os.path.getmtime(entry), size, name))
+        except OSError:
+            continue  # Evicted by another process in the meantime
+
+    total = sum(size for _, size, _ in entries)
+    for _, size, name in sorted(entries):
+        if total <= settings.artifact_cache_max_size:
+            break
+        if name == keep:
+            continue
+        shutil.rmtree(os.path.join(cache_dir, name), ignore_errors=True)
+        total -= size
+        logger.info(f"Evicted {name} from the artifact cache.")
+
+
+def _get_checksum_path(extension_path: str) -> str:
+    # Checksums are never stored next to the sources (which might be read-only), but keyed by the
+    # extension's path in the checksum directory:
+    directory = settings.checksum_dir or os.path.join(settings.cache_dir, 'checksums')
+    key = hashlib.md5(os.path.abspath(extension_path).encode()).hexdigest()
+    return os.path.join(directory, f'{os.path.basename(extension_path)}-{key}.checksum')
+
+
+def _get_toolchain_fingerprint_path(extension_path: str) -> str:
+    return os.path.splitext(_get_checksum_path(extension_path))[0] + '.toolchain.json'
+
+
+def _get_file_index_path(extension_path: str) -> str:
+    return os.path.splitext(_get_checksum_path(extension_path))[0] + '.files.json'
+
+
+def _load_file_index(extension_path: str, hasher=None) -> Dict[str, list]:
     """
-    Calculate the module checksum and then write it to the end of the shared
-    object.
+    Load the modification times, sizes and digests of the files the extension has been built from (see
+    `_content_entries`), or an empty index if there is none or it has been created using another hasher.
     """
-    _save_checksum_trailer(extension_path, _calc_cur_checksum(file_patterns, release=release))
+    hasher = hasher or settings.checksum_hasher
+    try:
+        with open(_get_file_index_path(extension_path), "r") as f:
+            index = json.load(f)
+    except (FileNotFoundError, json.JSONDecodeError):
+        return {}
+    return index.get("files", {}) if index.get("hasher") == _hasher_name(hasher) else {}
+
+
+def _save_file_index(extension_path: str, index: Dict[str, list], hasher=None):
+    hasher = hasher or settings.checksum_hasher
+    _write_atomically(_get_file_index_path(extension_path),
+                      json.dumps({"hasher": _hasher_name(hasher), "files": index}, sort_keys=True).encode())
+
+
+def _hasher_name(hasher) -> str:
+    return hasher(b"", usedforsecurity=False).name
+
+
+def _load_checksum(extension_path: str) -> Optional[bytes]:
+    if not os.path.isfile(extension_path):
+        logger.info("Failed to find compiled extension; rebuilding.")
+        return None
+    try:
+        with open(_get_checksum_path(extension_path), "rb") as f:
+            return f.read()
+    except FileNotFoundError:
+        # Extensions built by previous versions of rustimport carry their checksum in a trailer:
+        return _load_checksum_trailer(extension_path)
+
+
+def _save_checksum(extension_path: str, cur_checksum: bytes):
+    _write_atomically(_get_checksum_path(extension_path), cur_checksum)
+
+
+def _write_atomically(path: str, contents: bytes):
+    # Checksums are also updated while checking them (see `_is_mtime_size_checksum_valid`), so concurrent
+    # readers must never see a partially written file:
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(f'{path}.tmp-{os.getpid()}', "wb") as f:
+        f.write(contents)
+    os.replace(f'{path}.tmp-{os.getpid()}', path)
 
 
 def _load_checksum_trailer(extension_path: str) -> Optional[bytes]:
@@ -56,22 +270,78 @@ def _load_checksum_trailer(extension_path: str) -> Optional[bytes]:
         return None
 
 
-def _save_checksum_trailer(extension_path: str, cur_checksum: bytes):
-    # We can just append the checksum to the shared object; this is effectively
-    # legal (see e.g. https://stackoverflow.com/questions/10106447).
-    with open(extension_path, "ab") as file:
-        file.write(cur_checksum + _FMT.pack(len(cur_checksum), _TAG))
-
-
-def _calc_cur_checksum(file_patterns: List[str], hasher=settings.checksum_hasher, release: bool = False) -> bytes:
+def _calc_cur_checksum(file_patterns: List[str], hasher=None, release: bool = False,
+                       strategy: Optional[str] = None, build_env: Optional[Dict[str, str]] = None,
+                       base_dir: Optional[str] = None, index: Optional[Dict[str, list]] = None,
+                       inputs: Optional[Dict[str, str]] = None) -> bytes:
     """
-    Calculate the checksum for the given list of file patterns.
+    Calculate the checksum for the given list of file patterns, using the given `strategy` (see
+    `settings.checksum_strategy`). File paths are included relative to `base_dir` (if given), so
+    that the content checksum does not depend on the location of the project. For the "mtime_size"
+    strategy, the digests of the files are recorded in `index` (see `_content_entries`), if given.
 
     By default, sha1 is used as it has the [best performance](https://github.com/SharkyRawr/python-hashlib-benchmark)
     and is [reasonably collision-proof](https://crypto.stackexchange.com/a/2584).
     """
-    checksums: List[Tuple[str, str]] = []
+    hasher = hasher or settings.checksum_hasher
+    strategy = strategy or settings.checksum_strategy
+    all_files = collect_files(file_patterns)
+    config = _build_config_payload(release, build_env, inputs)
+
+    if strategy == 'content':
+        return _calc_payload_checksum(_content_entries(all_files, hasher, base_dir), hasher, config)
+    elif strategy == 'content+mtime':
+        return _calc_payload_checksum([
+            f'{e}:{os.stat(p).st_mtime_ns}' for p, e in zip(all_files, _content_entries(all_files, hasher, base_dir))
+        ], hasher, config)
+    elif strategy == 'mtime_size':
+        return _calc_mtime_size_checksum(all_files, hasher, config) + b"/" + \
+               _calc_payload_checksum(_content_entries(all_files, hasher, base_dir, index), hasher, config)
+    raise ValueError(f"Unknown checksum strategy: {strategy}")
+
+
+def _is_mtime_size_checksum_valid(extension_path: str, old_checksum: bytes, file_patterns: List[str],
+                                  hasher=None, release: bool = False,
+                                  build_env: Optional[Dict[str, str]] = None,
+                                  inputs: Optional[Dict[str, str]] = None) -> bool:
+    """
+    The "mtime_size" checksum consists of two parts: A checksum of all files' paths, modification times and
+    sizes, which is quick to compute and thus checked first, and a regular content checksum, which is only
+    calculated if the former does not match (e.g. because a file has been touched but not modified).
+
+    To calculate the latter, only the files whose modification time or size changed since the build are hashed,
+    reusing the digests of all others saved alongside the checksum. If the contents turn out to be unchanged, the
+    checksum is updated to the files' new modification times, so that they aren't hashed again on the next check.
+    """
+    hasher = hasher or settings.checksum_hasher
+    old_metadata_checksum, _, old_content_checksum = old_checksum.partition(b"/")
+    all_files = collect_files(file_patterns)
+    config = _build_config_payload(release, build_env, inputs)
+
+    metadata_checksum = _calc_mtime_size_checksum(all_files, hasher, config)
+    if old_metadata_checksum == metadata_checksum:
+        return True
+
+    index = _load_file_index(extension_path, hasher)
+    old_files = set(index)
+    content_checksum = _calc_payload_checksum(
+        _content_entries(all_files, hasher, os.path.dirname(extension_path), index), hasher, config,
+    )
+    if content_checksum != old_content_checksum:
+        if old_files and (added := sorted(index.keys() - old_files)):
+            logger.info(f"Tracked files have been added: {', '.join(added)}; rebuilding.")
+        if old_files and (removed := sorted(old_files - index.keys())):
+            logger.info(f"Tracked files have been removed: {', '.join(removed)}; rebuilding.")
+        return False
+
+    logger.debug("Tracked files have been touched, but their contents are unchanged.")
+    _save_checksum(extension_path, metadata_checksum + b"/" + content_checksum)
+    _save_file_index(extension_path, index, hasher)
+    return True
 
+
+def collect_files(file_patterns: List[str]) -> List[str]:
+    """Expand the given file paths, directories and glob patterns (e.g. an `Importable`'s `dependencies`) to files."""
     all_files: List[str] = []
 
     for entity in file_patterns:
@@ -84,18 +354,57 @@ def _calc_cur_checksum(file_patterns: List[str], hasher=settings.checksum_hasher
         else:
             all_files.append(entity)
 
-    for filepath in sorted(all_files):
+    return sorted(set(all_files))
+
+
+def _content_entries(all_files: List[str], hasher, base_dir: Optional[str] = None,
+                     index: Optional[Dict[str, list]] = None) -> List[str]:
+    """
+    Hash the contents of the given files. If an `index` mapping file names to their modification time, size and
+    digest is given, the digests of files whose modification time and size match are reused instead of reading
+    them, and the index is updated to the given files.
+    """
+    entries = []
+    for filepath in all_files:
+        name = os.path.relpath(filepath, base_dir) if base_dir else filepath
+        if index is not None:
+            stat = os.stat(filepath)
+            if (cached := index.get(name)) and cached[:2] == [stat.st_mtime_ns, stat.st_size]:
+                entries.append(f'{name}:{cached[2]}')
+                continue
         with open(filepath, "rb") as f:
-            if not any(c[0] == filepath for c in checksums):
-                checksums.append((filepath, hasher(f.read(), usedforsecurity=False).hexdigest()))
+            digest = hasher(f.read(), usedforsecurity=False).hexdigest()
+        if index is not None:
+            index[name] = [stat.st_mtime_ns, stat.st_size, digest]
+        entries.append(f'{name}:{digest}')
+
+    if index is not None:
+        for name in index.keys() - {os.path.relpath(p, base_dir) if base_dir else p for p in all_files}:
+            del index[name]
+    return entries
+
+
+def _calc_mtime_size_checksum(all_files: List[str], hasher, config: bytes) -> bytes:
+    entries = []
+    for filepath in all_files:
+        stat = os.stat(filepath)
+        entries.append(f'{filepath}:{stat.st_mtime_ns}:{stat.st_size}')
+    return _calc_payload_checksum(entries, hasher, config)
+
+
+def _build_config_payload(release: bool, build_env: Optional[Dict[str, str]],
+                          inputs: Optional[Dict[str, str]] = None) -> bytes:
+    payload = b"r\n" if release else b""
+    for k, v in sorted((build_env or {}).items()):
+        payload += f"env:{k}={v}\n".encode()
+    for k, v in sorted((inputs or {}).items()):
+        payload += f"input:{k}={v}\n".encode()
+    return payload
 
-    payload = '\n'.join(
-        f'{p}:{c}' for p, c in checksums
-    ).encode()
 
-    if release:
-        payload = b"r\n" + payload
+def _calc_payload_checksum(entries: List[str], hasher, config: bytes) -> bytes:
+    payload = config + '\n'.join(entries).encode()
 
-    logging.debug(f"Checksum payload: {payload}")
+    logger.debug(f"Checksum payload: {payload}")
 
     return hasher(payload, usedforsecurity=False).hexdigest().encode()
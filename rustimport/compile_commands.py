@@ -0,0 +1,87 @@
+"""
+Collects the C/C++ compiler invocations of build scripts (e.g. of `-sys` crates using the `cc` crate) into a
+`compile_commands.json` file, as used by editors and clang tooling.
+
+This works by pointing `CC` and `CXX` to small wrapper scripts, which log each invocation and then run the
+actual compiler.
+"""
+
+import json
+import logging
+import os
+import shlex
+import stat
+import sys
+from typing import Dict
+
+_logger = logging.getLogger(__name__)
+
+_SOURCE_EXTENSIONS = ('.c', '.cc', '.cpp', '.cxx', '.c++', '.m', '.mm', '.s', '.S')
+
+_WRAPPER_TEMPLATE = """#!{python}
+import json, os, sys
+with open({log_path!r}, "a") as f:
+    f.write(json.dumps({{"directory": os.getcwd(), "arguments": [{compiler!r}, *sys.argv[1:]]}}) + "\\n")
+os.execvp({compiler!r}, [{compiler!r}, *sys.argv[1:]])
+"""
+
+
+def create_compiler_wrappers(directory: str, cc: str, cxx: str) -> Dict[str, str]:
+    """
+    Create logging wrappers for the given C and C++ compilers in `directory` and return the
+    environment variables to supply to cargo to make build scripts use them.
+    """
+    if os.name == 'nt':
+        _logger.warning("Emitting compile_commands.json is not supported on Windows.")
+        return {}
+
+    os.makedirs(directory, exist_ok=True)
+    env = {}
+    for var, compiler in (('CC', cc), ('CXX', cxx)):
+        # Wrappers are not meant to be called with additional arguments (e.g. "ccache gcc"):
+        compiler, *compiler_args = shlex.split(compiler)
+        path = os.path.join(directory, f'{var.lower()}-wrapper')
+        with open(path, 'w') as f:
+            f.write(_WRAPPER_TEMPLATE.format(
+                python=sys.executable,
+                log_path=_get_log_path(directory),
+                compiler=compiler,
+            ))
+        os.chmod(path, os.stat(path).st_mode | stat.S_IXUSR | stat.S_IXGRP | stat.S_IXOTH)
+        env[var] = ' '.join([shlex.quote(path), *map(shlex.quote, compiler_args)])
+    return env
+
+
+def write_compile_commands(directory: str, output_path: str):
+    """
+    Convert the compiler invocations logged by the wrappers in `directory` into a compilation
+    database at `output_path`.
+
+    Since cargo does not re-run build scripts which are up-to-date, entries from a previously written
+    compilation database are retained unless the respective file has been compiled again.
+    """
+    entries = {}
+    if os.path.isfile(output_path):
+        with open(output_path, 'r') as f:
+            entries = {e['file']: e for e in json.load(f)}
+
+    log_path = _get_log_path(directory)
+    if os.path.isfile(log_path):
+        with open(log_path, 'r') as f:
+            for line in filter(str.strip, f):
+                invocation = json.loads(line)
+                if '-c' not in invocation['arguments']:
+                    continue  # e.g. preprocessor-only invocations or linking
+                for arg in invocation['arguments'][1:]:
+                    if arg.endswith(_SOURCE_EXTENSIONS):
+                        file = os.path.join(invocation['directory'], arg)
+                        entries[file] = {**invocation, 'file': file}
+        os.remove(log_path)
+
+    with open(output_path, 'w') as f:
+        json.dump(list(entries.values()), f, indent=2)
+    _logger.debug(f"Wrote {len(entries)} compile commands to {output_path}")
+
+
+def _get_log_path(directory: str) -> str:
+    return os.path.join(directory, 'compiler-invocations.log')
@@ -1,17 +1,48 @@
+import functools
 import json
 import logging
 import os
+import re
 import shutil
 import subprocess
 import sys
-from dataclasses import dataclass
-from typing import Optional, List, Dict, Any
+import threading
+from dataclasses import dataclass, field
+from typing import Optional, List, Dict, Any, Callable
+
+import toml
 
 from rustimport import settings
 
 _logger = logging.getLogger(__name__)
 
 
+@dataclass
+class BuildProgress:
+    """A progress event emitted while cargo builds an extension (see `settings.progress_callback`)."""
+
+    phase: str
+    """
+    One of `"started"` (before cargo runs), `"compiled"` (a crate or build script has been compiled, or was
+    up-to-date), `"build_script_executed"` (a build script has been run) or `"finished"` (cargo exited).
+    """
+    completed: int
+    """The number of compilation units (crates and build scripts) compiled so far."""
+    total: Optional[int]
+    """The estimated total number of compilation units, or `None` if it couldn't be determined."""
+    crate: Optional[str] = None
+    """The name of the crate the event refers to, if any."""
+    fresh: bool = False
+    """Whether the unit was up-to-date and thus didn't need to be compiled (for `"compiled"` events)."""
+    success: Optional[bool] = None
+    """Whether the build succeeded (for `"finished"` events)."""
+    module: Optional[str] = None
+    """The full name of the module being built."""
+
+
+ProgressCallback = Callable[[BuildProgress], None]
+
+
 class Cargo:
     def __init__(self, executable_path: Optional[str] = None):
         self.executable_path = executable_path or settings.cargo_executable or require('cargo')
@@ -23,21 +54,83 @@ class Cargo:
         success: bool
         error_output: List[str]
         compiler_messages: List[Dict[str, Any]]
+        features: List[str] = field(default_factory=list)
+        build_script_output: List[str] = field(default_factory=list)
+        """The output of build scripts (i.e. their `cargo:warning=` lines) held back by `suppress_build_script_output`."""
+        stdout: str = ''
+        """Cargo's output on stdout, i.e. its JSON messages."""
+        stderr: str = ''
+        """Cargo's output on stderr, if it was captured (i.e. with `suppress_output` or `suppress_build_script_output`)."""
+
+    def version(self) -> str:
+        """Returns the version string of the cargo executable (i.e. the output of `cargo --version`)."""
+        return _get_version(self.executable_path)
+
+    def rustc_version(self) -> str:
+        """Returns the version string of the rustc executable used by cargo (i.e. the output of `rustc --version`)."""
+        return _get_version(self.__rustc_executable)
+
+    def host_triple(self) -> str:
+        """
+        Returns the target triple of the host (e.g. `x86_64-unknown-linux-gnu`), as given by `settings.host_triple`
+        or the `host:` line of `rustc -vV`'s output.
+        """
+        if settings.host_triple:
+            return settings.host_triple
+        output = subprocess.check_output([self.__rustc_executable, '-vV']).decode()
+        if m := re.search(r'^host:\s*(\S+)', output, re.MULTILINE):
+            return m.group(1)
+        raise RuntimeError(f"Could not detect the host triple from the output of `rustc -vV`:\n{output}")
+
+    @property
+    def __rustc_executable(self) -> str:
+        return os.getenv('RUSTC') \
+            or shutil.which('rustc', path=os.path.dirname(self.executable_path)) \
+            or require('rustc')
+
+    def generate_lockfile(self, crate_path: str, env: Optional[Dict[str, str]] = None) -> str:
+        """
+        Resolve the crate's dependencies into its `Cargo.lock` without building it, and return the lockfile's
+        path. An existing lockfile is kept as is, unless it doesn't match the manifest anymore.
+        """
+        subprocess.run(
+            [self.executable_path, 'metadata', '--format-version', '1'],
+            cwd=crate_path,
+            env={**os.environ, **(env or {})},
+            stdout=subprocess.DEVNULL,
+            check=True,
+        )
+        return os.path.join(crate_path, 'Cargo.lock')
 
     def build(self, crate_path: str,
               destination_path: Optional[str] = None,
               release: bool = False,
               suppress_output: bool = False,
-              additional_args: Optional[List[str]] = None) -> BuildResult:
+              additional_args: Optional[List[str]] = None,
+              target_dir: Optional[str] = None,
+              env: Optional[Dict[str, str]] = None,
+              progress_callback: Optional[ProgressCallback] = None,
+              target: Optional[str] = None,
+              rewrite_message: Optional[Callable[[str], str]] = None,
+              suppress_build_script_output: bool = False) -> BuildResult:
         """
         Runs `cargo build --lib` for the given `crate_path`.
 
         @param crate_path: The path of the crate's root directory (the directory containing Cargo.toml).
         @param destination_path: Copy the built library artifact to this folder or file path.
         @param release: Whether to build a release binary (toggles Cargo's "--release" flag)
-        @param suppress_output: If true, no process output will be printed to stdout. In case of build failure,
-                                the output will be collected and logged using `logging.error()` for debugging.
+        @param suppress_output: If true, no process output will be printed. In case of build failure, the output
+                                will be collected and logged using `logging.error()` for debugging.
         @param additional_args: Additional command line arguments to supply to the cargo executable.
+        @param target_dir: Use this directory as cargo's target directory (sets `CARGO_TARGET_DIR`) instead of
+                           the default `target` directory within `crate_path`.
+        @param env: Additional environment variables to set for the cargo process.
+        @param progress_callback: A function receiving `BuildProgress` events while building.
+        @param target: The target triple to build for (toggles Cargo's "--target" flag), if not the host.
+        @param rewrite_message: A function applied to each rendered compiler message before it is printed or
+                                collected, e.g. to refer to the original sources instead of preprocessed ones.
+        @param suppress_build_script_output: If true, the output of build scripts (e.g. chatty `-sys` crates) is
+                                             held back and only printed if the build fails.
         """
 
         cmd = [
@@ -50,40 +143,233 @@ class Cargo:
             cmd.append("--quiet")
         if release:
             cmd.append('--release')
+        if target:
+            cmd.extend(['--target', target])
         if additional_args:
             cmd.extend(additional_args)
 
+        if os.name == 'nt':
+            cmd = _shorten_command_line(cmd, crate_path)
+
+        env = self.__process_env(env, target_dir)
+
         _logger.debug(f'Building {crate_path}: {" ".join(cmd)}')
 
-        proc = subprocess.Popen(
+        progress = _ProgressTracker(
+            progress_callback,
+            total=self.__count_units(crate_path, env) if progress_callback else None,
+        )
+        progress.emit('started')
+
+        hold_back = None
+        if suppress_output:
+            hold_back = _hold_back_all
+        elif suppress_build_script_output:
+            hold_back = _BUILD_SCRIPT_WARNING.match
+
+        stderr = []
+        held_back = []
+        with subprocess.Popen(
             cmd,
             cwd=crate_path,
+            env=env,
             stdout=subprocess.PIPE,
-            stderr=subprocess.PIPE if suppress_output else None,
-        )
+            stderr=subprocess.PIPE if hold_back else None,
+        ) as proc:
+            stderr_thread = None
+            if hold_back:
+                # Read stderr while stdout is being handled, so that neither pipe can fill up and block cargo:
+                stderr_thread = threading.Thread(target=_forward_stderr,
+                                                 args=(proc.stderr, stderr, held_back, hold_back), daemon=True)
+                stderr_thread.start()
 
-        result = self.__handle_build_process(crate_path, proc)
+            result = self.__handle_build_process(crate_path, proc, progress, rewrite_message,
+                                                 collect_messages=suppress_output)
+            if stderr_thread:
+                stderr_thread.join()
+                result.stderr = ''.join(stderr)
+        if suppress_build_script_output:
+            result.build_script_output = [line for line in held_back if _BUILD_SCRIPT_WARNING.match(line)]
+            if not result.success and not suppress_output:
+                # The build scripts' output might explain the failure:
+                sys.stderr.write(''.join(held_back))
+        if result.success:
+            progress.total = progress.completed
+            if not result.artifact_path:
+                result.artifact_path = self.__find_artifact(crate_path, env, release, target)
+        progress.emit('finished', success=result.success)
 
         if not result.success and suppress_output:
             _logger.error(f"Compilation failed. Cargo build output:\n\n"
-                          + '\n'.join(result.error_output) +
-                          f"{proc.stderr.read().decode()}")
+                          + '\n'.join(result.error_output) + ''.join(held_back))
 
         _logger.info(f'Cargo exited with code {result.exit_code}.')
 
         if result.success and result.artifact_path and destination_path:
+            if os.path.isdir(destination_path):
+                destination_path = os.path.join(destination_path, os.path.basename(result.artifact_path))
             _logger.info(f"Copying artifact {result.artifact_path} to {destination_path}")
-            shutil.copy2(result.artifact_path, destination_path)
+            # Copy to a temporary file first and then replace the destination, as overwriting a library
+            # which is currently loaded in place corrupts it for the process that loaded it:
+            shutil.copy2(result.artifact_path, destination_path + '.tmp')
+            os.replace(destination_path + '.tmp', destination_path)
 
         return result
 
+    def check(self, crate_path: str,
+              additional_args: Optional[List[str]] = None,
+              target_dir: Optional[str] = None,
+              env: Optional[Dict[str, str]] = None,
+              target: Optional[str] = None,
+              rewrite_message: Optional[Callable[[str], str]] = None) -> BuildResult:
+        """
+        Runs `cargo check --lib` for the given `crate_path`, which reports compilation errors much faster than a
+        full build, as no code is generated. The compiler messages are not printed, but returned in the result's
+        `error_output` (along with cargo's own error output, if any).
+
+        @param additional_args: Additional command line arguments to supply to the cargo executable. Arguments
+                                for rustc (i.e. following a `--`) are ignored, as they are only relevant to
+                                code generation and `cargo check` doesn't accept them.
+        @param target_dir: Use this directory as cargo's target directory (sets `CARGO_TARGET_DIR`).
+        @param env: Additional environment variables to set for the cargo process.
+        @param target: The target triple to check for, if not the host.
+        @param rewrite_message: A function applied to each rendered compiler message before it is collected.
+        """
+        additional_args = additional_args or []
+        if '--' in additional_args:
+            additional_args = additional_args[:additional_args.index('--')]
+
+        cmd = [
+            self.executable_path, 'check',
+            '--lib',
+            '--message-format', 'json',
+            '--quiet',
+            *(['--target', target] if target else []),
+            *additional_args,
+        ]
+
+        _logger.debug(f'Checking {crate_path}: {" ".join(cmd)}')
+
+        stderr = []
+        with subprocess.Popen(
+            cmd,
+            cwd=crate_path,
+            env=self.__process_env(env, target_dir),
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        ) as proc:
+            # Read stderr while stdout is being handled, so that neither pipe can fill up and block cargo:
+            stderr_thread = threading.Thread(target=_forward_stderr, args=(proc.stderr, stderr, [], _hold_back_all),
+                                             daemon=True)
+            stderr_thread.start()
+            result = self.__handle_build_process(crate_path, proc, _ProgressTracker(None, None), rewrite_message,
+                                                 collect_messages=True)
+            stderr_thread.join()
+        if stderr:
+            result.error_output.append(''.join(stderr))
+        return result
+
+    def test(self, crate_path: str,
+             additional_args: Optional[List[str]] = None,
+             target_dir: Optional[str] = None,
+             env: Optional[Dict[str, str]] = None) -> int:
+        """
+        Runs `cargo test --lib` for the given `crate_path`, printing cargo's and the tests' output.
+
+        @param additional_args: Additional command line arguments to supply to the cargo executable. Arguments
+                                for rustc (i.e. following a `--`) are ignored, as cargo would pass them on to
+                                the test binary instead.
+        @param target_dir: Use this directory as cargo's target directory (sets `CARGO_TARGET_DIR`).
+        @param env: Additional environment variables to set for the cargo process.
+        @return: cargo's exit code.
+        """
+        additional_args = additional_args or []
+        if '--' in additional_args:
+            additional_args = additional_args[:additional_args.index('--')]
+
+        cmd = [self.executable_path, 'test', '--lib', *additional_args]
+
+        _logger.debug(f'Testing {crate_path}: {" ".join(cmd)}')
+
+        return subprocess.run(cmd, cwd=crate_path, env=self.__process_env(env, target_dir)).returncode
+
+    def __find_artifact(self, crate_path: str, env: Dict[str, str], release: bool,
+                        target: Optional[str] = None) -> Optional[str]:
+        """
+        Look for the built library in cargo's target directory, in case its path couldn't be extracted from
+        cargo's messages.
+        """
+        with open(os.path.join(crate_path, 'Cargo.toml'), 'r') as f:
+            manifest = toml.load(f)
+        name = manifest.get('lib', {}).get('name') or manifest.get('package', {}).get('name', '').replace('-', '_')
+
+        target_dir = env.get('CARGO_TARGET_DIR') or os.path.join(crate_path, 'target')
+        profile = 'release' if release else 'debug'
+        triple = target or self.host_triple()
+
+        for directory in (os.path.join(target_dir, triple, profile), os.path.join(target_dir, profile)):
+            # Emscripten (e.g. for Pyodide) names libraries like `<name>.wasm`:
+            for filename in (f'lib{name}.so', f'lib{name}.dylib', f'{name}.dll', f'{name}.wasm'):
+                if os.path.isfile(path := os.path.join(directory, filename)):
+                    _logger.debug(f"Found the artifact of {crate_path} at {path}")
+                    return path
+
+        _logger.warning(f"Could not find the built library of {crate_path} in {target_dir}. If it was built for "
+                        f"a different target triple than {triple}, set `settings.host_triple`.")
+        return None
+
+    @staticmethod
+    def __process_env(env: Optional[Dict[str, str]], target_dir: Optional[str]) -> Dict[str, str]:
+        env = {**os.environ, **(env or {})}
+        if target_dir:
+            env['CARGO_TARGET_DIR'] = os.path.abspath(target_dir)
+        return env
+
+    def __count_units(self, crate_path: str, env: Optional[Dict[str, str]]) -> Optional[int]:
+        """
+        Estimate the number of compilation units of building the given crate, i.e. the number of crates it
+        (transitively) depends on, including itself, plus the number of build scripts among them.
+        """
+        try:
+            metadata = json.loads(subprocess.check_output(
+                [self.executable_path, 'metadata', '--format-version', '1'],
+                cwd=crate_path,
+                env={**os.environ, **(env or {})},
+                stderr=subprocess.DEVNULL,
+            ))
+        except (subprocess.CalledProcessError, json.JSONDecodeError):
+            return None
+
+        nodes = {node['id']: node for node in metadata['resolve']['nodes']}
+        packages = {package['id']: package for package in metadata['packages']}
+
+        # Collect all packages required to build the library, excluding dev-dependencies:
+        required, stack = set(), [metadata['resolve']['root']]
+        while stack:
+            if (package_id := stack.pop()) in required or package_id not in nodes:
+                continue
+            required.add(package_id)
+            stack.extend(
+                dep['pkg'] for dep in nodes[package_id].get('deps', [])
+                if any(k['kind'] in (None, 'build') for k in dep.get('dep_kinds', [{'kind': None}]))
+            )
+
+        build_scripts = sum(
+            any('custom-build' in t['kind'] for t in packages[p]['targets']) for p in required if p in packages
+        )
+        return len(required) + build_scripts
+
     @classmethod
-    def __handle_build_process(cls, crate_path: str, proc: subprocess.Popen) -> BuildResult:
+    def __handle_build_process(cls, crate_path: str, proc: subprocess.Popen, progress: '_ProgressTracker',
+                               rewrite_message: Optional[Callable[[str], str]] = None,
+                               collect_messages: bool = False) -> BuildResult:
         """
         Handle json messages received from the given cargo process `proc`.
 
         This method extracts build processes main library's artifact path (the python extension), if possible.
 
+        @param collect_messages: Collect the rendered compiler messages in the result's `error_output` instead of
+                                 printing them to stderr.
         @return: A `Cargo.BuildResult`. Note that artifact_path might be `None` if extraction
                  fails (mostly in case of compilation errors).
         """
@@ -91,23 +377,36 @@ class Cargo:
         abs_crate_path = os.path.realpath(crate_path).rstrip("/")
 
         artifact_path = None
+        features = []
         messages = []
         error_output = []
+        stdout = []
 
-        while (exit_code := proc.poll()) is None:
-            line = proc.stdout.readline()
-
+        # Read until the end of the output, as messages may still be buffered once cargo has exited:
+        for line in proc.stdout:
+            stdout.append(line.decode(errors='replace'))
             if line.strip():
                 messages.append(message := json.loads(line))
 
                 if message.get('reason') == 'compiler-artifact':
                     if os.path.dirname(message.get('manifest_path')) == abs_crate_path:
                         artifact_path = message['filenames'][0]
+                        features = message.get('features', [])
+                    progress.completed += 1
+                    progress.emit('compiled', crate=_get_package_name(message['package_id']),
+                                  fresh=message.get('fresh', False))
+                elif message.get('reason') == 'build-script-executed':
+                    progress.emit('build_script_executed', crate=_get_package_name(message['package_id']))
                 elif message.get('reason') == 'compiler-message':
-                    if not proc.stderr:
-                        sys.stderr.write(message['message']['rendered'])
+                    rendered = message['message']['rendered'] or ''
+                    if rewrite_message:
+                        rendered = rewrite_message(rendered)
+                    if collect_messages:
+                        error_output.append(rendered)
                     else:
-                        error_output.append(message['message']['rendered'])
+                        sys.stderr.write(rendered)
+
+        exit_code = proc.wait()
 
         return cls.BuildResult(
             success=exit_code == 0,
@@ -115,8 +414,125 @@ class Cargo:
             compiler_messages=messages,
             error_output=error_output,
             artifact_path=artifact_path,
+            features=features,
+            stdout=''.join(stdout),
+        )
+
+
+# A line printed by cargo for a build script's `cargo:warning=` instruction, e.g. "warning: foo-sys@0.1.0: ...":
+_BUILD_SCRIPT_WARNING = re.compile(r'^warning: [\w-]+@\S+: ')
+
+
+def _hold_back_all(line: str) -> bool:
+    return True
+
+
+def _forward_stderr(stream, output: List[str], held_back: List[str], hold_back: Callable[[str], Any]):
+    """
+    Copy cargo's stderr to ours line by line, except for lines matching `hold_back`, which are collected in
+    `held_back` instead. All lines are collected in `output`.
+    """
+    for line in iter(stream.readline, b''):
+        line = line.decode(errors='replace')
+        output.append(line)
+        if hold_back(line):
+            held_back.append(line)
+        else:
+            sys.stderr.write(line)
+
+
+class _ProgressTracker:
+    def __init__(self, callback: Optional[ProgressCallback], total: Optional[int]):
+        self.callback = callback
+        self.total = total
+        self.completed = 0
+
+    def emit(self, phase: str, **kwargs):
+        if self.callback is None:
+            return
+        # The total is an estimate, so make sure we never report more than 100%:
+        total = max(self.total, self.completed) if self.total is not None else None
+        try:
+            self.callback(BuildProgress(phase=phase, completed=self.completed, total=total, **kwargs))
+        except Exception:
+            _logger.exception("The progress callback raised an exception")
+
+
+def _get_package_name(package_id: str) -> str:
+    # Package ids are either formatted like "name 1.0.0 (source)" or, since cargo 1.77, like
+    # "source#name@1.0.0", where "name@" is omitted if the name matches the source's last path component:
+    if '#' not in package_id:
+        return package_id.split(' ')[0]
+    source, _, fragment = package_id.partition('#')
+    if '@' in fragment:
+        return fragment.split('@')[0]
+    return source.rstrip('/').rsplit('/', 1)[-1].split('?')[0]
+
+
+# Windows limits command lines to 32767 characters (including the terminating null character):
+_MAX_COMMAND_LINE_LENGTH = 32767
+
+
+def _shorten_command_line(cmd: List[str], crate_path: str) -> List[str]:
+    """
+    Crates with many features or flags can exceed Windows' command line length limit, which fails the build
+    with obscure errors. Thus, if the given cargo command is too long, move as many arguments as possible into
+    files in `crate_path`:
+
+    - `--config KEY=VALUE` arguments are written to a config file, which is passed using `--config <path>`
+    - Arguments for rustc (i.e. after `--`) are written to a response file, which is passed as `@<path>`
+
+    @return: The (possibly) shortened command.
+    """
+    if len(subprocess.list2cmdline(cmd)) < _MAX_COMMAND_LINE_LENGTH:
+        return cmd
+
+    if '--' in cmd:
+        cmd, rustc_args = cmd[:cmd.index('--')], cmd[cmd.index('--') + 1:]
+    else:
+        rustc_args = []
+
+    cargo_args, config_entries = [], []
+    i = 0
+    while i < len(cmd):
+        if cmd[i] == '--config' and i + 1 < len(cmd) and '=' in cmd[i + 1]:
+            config_entries.append(cmd[i + 1])
+            i += 2
+        elif cmd[i].startswith('--config=') and '=' in cmd[i][len('--config='):]:
+            config_entries.append(cmd[i][len('--config='):])
+            i += 1
+        else:
+            cargo_args.append(cmd[i])
+            i += 1
+
+    if config_entries:
+        config_path = os.path.abspath(os.path.join(crate_path, 'rustimport-config.toml'))
+        with open(config_path, 'w') as f:
+            f.write('\n'.join(config_entries) + '\n')
+        cargo_args.extend(['--config', config_path])
+
+    if rustc_args:
+        response_file_path = os.path.abspath(os.path.join(crate_path, 'rustimport-rustc-args.txt'))
+        with open(response_file_path, 'w') as f:
+            f.write('\n'.join(rustc_args) + '\n')
+        cargo_args.extend(['--', f'@{response_file_path}'])
+
+    if len(subprocess.list2cmdline(cargo_args)) >= _MAX_COMMAND_LINE_LENGTH:
+        raise ValueError(
+            f"The cargo command line for building {crate_path} exceeds Windows' length limit of "
+            f"{_MAX_COMMAND_LINE_LENGTH} characters even when passing rustc arguments and config entries via "
+            f"files. Reduce the number of enabled features, e.g. by grouping them into a feature of your crate."
         )
 
+    _logger.debug(f"Shortened the cargo command line for {crate_path} using argument files")
+    return cargo_args
+
+
+@functools.lru_cache(maxsize=None)
+def _get_version(executable_path: str) -> str:
+    # Cached, as the versions are checked before every import (see `Importable.toolchain_fingerprint`):
+    return subprocess.check_output([executable_path, '--version']).decode().strip()
+
 
 def require(executable_name: str):
     path = shutil.which(executable_name)
@@ -124,16 +540,14 @@ def require(executable_name: str):
     if path:
         return path
     else:
-        sys.stderr.write(
-            "Could not find the rust toolchain installation. Make sure it is installed and "
-            "the `PATH` environment variable is set correctly.\n\n"
-        )
         if os.name != 'nt':
-            sys.stderr.write("You can install the toolchain like this:\n$ curl https://sh.rustup.rs | sh\n\n")
+            hint = "You can install the toolchain like this:\n$ curl https://sh.rustup.rs | sh"
         else:
-            sys.stderr.write(
-                "To install the toolchain, visit https://forge.rust-lang.org/infra/other-installation-methods.html"
-                "#other-ways-to-install-rustup\n\n"
-            )
+            hint = ("To install the toolchain, visit https://forge.rust-lang.org/infra/other-installation-methods.html"
+                    "#other-ways-to-install-rustup")
+        _logger.error(
+            "Could not find the rust toolchain installation. Make sure it is installed and "
+            f"the `PATH` environment variable is set correctly.\n{hint}"
+        )
 
         raise FileNotFoundError(f'Could not find {executable_name} binary.')
@@ -1,24 +1,66 @@
+import logging
 import os
 import sys
+from typing import List, Optional
 
-from rustimport.importable import all_importables, Importable
+from rustimport import settings
+from rustimport.importable import all_importables, Importable, CrateImportable, SingleFileImportable
+from rustimport.settings import Settings
 
+_logger = logging.getLogger(__name__)
 
-def find_module_importable(modulename: str, opt_in: bool = False) -> Importable:
-    importable = _find_importable(modulename, opt_in)
+
+def find_module_importable(modulename: str, opt_in: bool = False, module_name: Optional[str] = None,
+                           call_settings: Optional[Settings] = None) -> Importable:
+    """
+    Find the file or crate implementing the given module on `sys.path`.
+
+    @param module_name: The name to import the module as, if it differs from `modulename`. If not given, import
+                        names mapped to a differently named file or crate by `settings.module_names` are resolved.
+    @param call_settings: The per-call settings of the importable (see `Importable.settings`).
+    """
+    if module_name is None and modulename in settings.module_names:
+        module_name, modulename = modulename, settings.module_names[modulename]
+    importable = _find_importable(modulename, opt_in, module_name, call_settings)
     if importable is None:
         raise ImportError(
             f"Couldn't find a file or crate matching the module"
             f" name: {modulename} (opt_in: {opt_in})"
+            + (f", to import as {module_name}" if module_name else "")
         )
     return importable
 
 
-def _find_importable(modulename, opt_in=False):
+def _find_importable(modulename, opt_in=False, module_name=None, call_settings=None):
     modulepath = modulename.replace(".", os.sep)
 
     for pth in sys.path:
         for importable in all_importables:
-            if i := importable.try_create(os.path.join(pth, modulepath), fullname=modulename, opt_in=opt_in):
+            if i := importable.try_create(os.path.join(pth, modulepath), fullname=modulename, opt_in=opt_in,
+                                          module_name=module_name, settings=call_settings):
                 return i
 
+
+
+def find_all_importables(root_directory: str, opt_in: bool = True) -> List[Importable]:
+    """
+    Recursively collect all eligible rust files and crates within `root_directory`.
+    """
+    importables = []
+
+    _logger.info(f"Collecting rust extensions in {root_directory}…")
+    for directory, subdirs, files in os.walk(root_directory, topdown=True):
+        if any(f.lower() == 'cargo.toml' for f in files):
+            if i := CrateImportable.try_create(directory, opt_in=opt_in):
+                importables.append(i)
+            # We never recurse into subdirectories of crates:
+            del subdirs[:]
+        else:
+            for file in files:
+                if os.path.splitext(file)[1] == '.rs':
+                    i = SingleFileImportable.try_create(os.path.join(directory, file), opt_in=opt_in)
+                    if i is not None:
+                        importables.append(i)
+
+    _logger.info(f"Found {len(importables)} {'extension' if len(importables) == 1 else 'extensions'}.")
+    return importables
@@ -0,0 +1,56 @@
+import hashlib
+import logging
+import os
+import re
+import shutil
+import subprocess
+from typing import Optional
+
+from rustimport import settings
+
+_logger = logging.getLogger(__name__)
+
+
+def get_cached_clone(url: str, rev: Optional[str] = None, tag: Optional[str] = None,
+                     branch: Optional[str] = None) -> str:
+    """
+    Clone the git repository at `url` into the cache directory, or reuse an existing clone of the
+    same url and revision, and return its path.
+
+    At most one of `rev` (a commit hash), `tag` or `branch` may be given; if none is, the
+    repository's default branch is used.
+    """
+    if sum(x is not None for x in (rev, tag, branch)) > 1:
+        raise ValueError("Only one of `rev`, `tag` and `branch` may be specified.")
+
+    ref = rev or tag or branch
+    repo_name = re.sub(r'\.git$', '', url.rstrip('/').rsplit('/', 1)[-1]) or 'repo'
+    key = hashlib.md5(f"{url}#{ref or ''}".encode()).hexdigest()
+    path = os.path.join(settings.cache_dir, 'git', f'{repo_name}-{key}')
+
+    if os.path.isdir(path):
+        _logger.debug(f"Using cached clone of {url} ({ref or 'default branch'}) in {path}")
+        return path
+
+    git = shutil.which('git')
+    if not git:
+        raise FileNotFoundError("Could not find git binary.")
+
+    # Clone into a temporary location first, so that an interrupted clone is never mistaken for a cached one:
+    tmp_path = path + '.tmp'
+    shutil.rmtree(tmp_path, ignore_errors=True)
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+
+    _logger.info(f"Cloning {url} ({ref or 'default branch'})…")
+    if rev:
+        subprocess.check_call([git, 'clone', '--quiet', url, tmp_path])
+        subprocess.check_call([git, 'checkout', '--quiet', rev], cwd=tmp_path)
+    else:
+        subprocess.check_call([
+            git, 'clone', '--quiet', '--depth', '1',
+            *(['--branch', tag or branch] if tag or branch else []),
+            url, tmp_path,
+        ])
+
+    os.rename(tmp_path, path)
+    return path
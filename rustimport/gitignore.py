@@ -0,0 +1,79 @@
+import os
+import re
+from typing import List, Optional, Tuple
+
+
+class GitIgnore:
+    """
+    Matches paths against the patterns of a `.gitignore` file. Supports comments, negated (`!`), anchored (containing
+    a `/`) and directory-only (trailing `/`) patterns as well as the `*`, `?`, `[...]` and `**` wildcards. As in git,
+    the contents of an ignored directory are ignored as a whole, so callers shouldn't descend into it.
+    """
+
+    def __init__(self, patterns: List[str]):
+        self.__rules = [rule for rule in map(self.__parse, patterns) if rule is not None]
+
+    @classmethod
+    def load(cls, path: str) -> Optional['GitIgnore']:
+        """Load the `.gitignore` file at `path`, or return `None` if it doesn't exist."""
+        try:
+            with open(path, 'r', encoding='utf-8', errors='replace') as f:
+                return cls(f.read().splitlines())
+        except FileNotFoundError:
+            return None
+
+    def matches(self, path: str, is_dir: bool = False) -> bool:
+        """Whether the given path (relative to the `.gitignore` file's directory) is ignored."""
+        path = os.path.normpath(path).replace(os.sep, '/')
+        ignored = False
+        for regex, negated, dir_only in self.__rules:
+            if dir_only and not is_dir:
+                continue
+            if regex.fullmatch(path):
+                ignored = not negated
+        return ignored
+
+    @staticmethod
+    def __parse(line: str) -> Optional[Tuple[re.Pattern, bool, bool]]:
+        line = line.rstrip()
+        if not line or line.startswith('#'):
+            return None
+
+        negated = line.startswith('!')
+        if negated:
+            line = line[1:]
+        elif line.startswith('\\'):
+            line = line[1:]  # Escaped leading `#` or `!`
+
+        dir_only = line.endswith('/')
+        line = line.rstrip('/')
+        # Patterns containing a slash (except a trailing one) are relative to the `.gitignore` file's directory,
+        # all others match at any depth:
+        anchored = '/' in line
+        line = line.lstrip('/')
+        if not line:
+            return None
+
+        regex = '' if anchored else '(?:.*/)?'
+        i = 0
+        while i < len(line):
+            if line.startswith('**/', i):
+                regex += '(?:.*/)?'
+                i += 3
+            elif line.startswith('**', i):
+                regex += '.*'
+                i += 2
+            elif line[i] == '*':
+                regex += '[^/]*'
+                i += 1
+            elif line[i] == '?':
+                regex += '[^/]'
+                i += 1
+            elif line[i] == '[' and (end := line.find(']', i + 2)) != -1:
+                content = line[i + 1:end]
+                regex += '[' + ('^' + content[1:] if content.startswith('!') else content).replace('\\', '\\\\') + ']'
+                i = end + 1
+            else:
+                regex += re.escape(line[i])
+                i += 1
+        return re.compile(regex), negated, dir_only
@@ -0,0 +1,76 @@
+import logging
+import os
+import shutil
+import sys
+import types
+from typing import Optional
+
+from rustimport import settings, load
+from rustimport.checksum import get_checksum
+from rustimport.importable import Importable, BuildResult, should_rebuild
+from rustimport.settings import Settings
+
+_logger = logging.getLogger(__name__)
+
+
+class ModuleHandle:
+    """
+    A handle to an imported extension, which allows to pick up changes to its sources later on,
+    e.g. in a long-running development server, without restarting the process.
+
+    Since an extension can't be unloaded once it has been imported, a rebuilt extension is loaded
+    from a copy with a unique file name. The previously loaded module stays intact, so references
+    to it (e.g. `from mymod import myfunc`) keep pointing to the old code – access the current
+    module through `handle.module` instead.
+    """
+
+    def __init__(self, module: types.ModuleType, importable: Optional[Importable] = None,
+                 settings: Optional[Settings] = None, build_result: Optional[BuildResult] = None):
+        self.module = module
+        self.build_result = build_result
+        """The `BuildResult` of the last (re)build, or `None` in release mode."""
+        self.__importable = importable
+        # The per-call settings the extension was imported with, which apply to rebuilds, too:
+        self.__settings = settings or Settings()
+
+    @property
+    def source_path(self) -> Optional[str]:
+        """The path of the source file or crate the extension is built from."""
+        return self.__importable.path if self.__importable else None
+
+    def rebuild_if_changed(self) -> bool:
+        """
+        Rebuild and reload the extension if any of its sources changed since it was last built.
+
+        @return: Whether the extension was rebuilt, i.e. `handle.module` is a new module.
+        """
+        if self.__importable is None:
+            # In release mode there are no sources to rebuild from:
+            return False
+
+        with self.__settings.applied():
+            if not should_rebuild(self.__importable):
+                return False
+
+            self.build_result = self.__importable.build(release=settings.compile_release_binaries)
+            self.__reload()
+        return True
+
+    def __reload(self):
+        importable = self.__importable
+        checksum = get_checksum(importable.extension_path) or 'latest'
+        copy_path = os.path.join(settings.cache_dir, 'reload', f"{importable.name}-{checksum[:16]}" + importable.extension_suffix)
+        os.makedirs(os.path.dirname(copy_path), exist_ok=True)
+        shutil.copy2(importable.extension_path, copy_path)
+
+        # The old module is superseded, so it must not be detected as a conflicting extension:
+        registered = sys.modules.get(importable.fullname) is self.module
+        if registered:
+            del sys.modules[importable.fullname]
+
+        _logger.info(f"Reloading {importable.fullname} from {copy_path}")
+        self.module = load.load_module(copy_path, importable.fullname)
+        load.apply_build_info(self.module, importable.build_info)
+
+        if registered:
+            sys.modules[importable.fullname] = self.module
@@ -8,7 +8,7 @@ from typing import Sequence, Optional
 
 from rustimport import settings
 from rustimport.find import find_module_importable
-from rustimport.importable import Importable, should_rebuild
+from rustimport.importable import Importable, build_if_needed_and_load
 
 logger = logging.getLogger(__name__)
 
@@ -44,9 +44,7 @@ class Loader(importlib.abc.Loader):
         self.__importable = importable
 
     def load_module(self, fullname: str) -> types.ModuleType:
-        if should_rebuild(self.__importable):
-            self.__importable.build(release=settings.compile_release_binaries)
-        return self.__importable.load()
+        return build_if_needed_and_load(self.__importable)
 
 
 if settings.release_mode and settings.rtld_flags:
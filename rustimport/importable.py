@@ -1,35 +1,376 @@
 import abc
+import datetime
+import errno
+import glob
 import hashlib
+import json
 import logging
 import os.path
+import re
 import shutil
+import subprocess
+import sys
 import sysconfig
+import threading
+import time
 import types
-from typing import Optional, List, Type
+from contextlib import contextmanager
+from dataclasses import dataclass, field
+from functools import wraps
+from typing import Optional, List, Type, Dict, Any, Tuple, Set, Callable, Collection, Iterator, Mapping
 
-from rustimport import load, BuildError, settings
-from rustimport.checksum import is_checksum_valid, save_checksum
-from rustimport.compiler import Cargo
-from rustimport.pre_processing import Preprocessor
+import toml
+
+from rustimport import load, BuildError, BuildLockTimeoutError, CircularDependencyError, InsufficientDiskSpaceError, \
+    ModuleNameMismatchError, settings
+from rustimport.checksum import is_checksum_valid, save_checksum, get_checksum, calc_input_fingerprint, \
+    restore_cached_artifact, store_cached_artifact, is_toolchain_fingerprint_valid, save_toolchain_fingerprint
+from rustimport.compile_commands import create_compiler_wrappers, write_compile_commands
+from rustimport.compiler import Cargo, BuildProgress
+from rustimport.gitignore import GitIgnore
+from rustimport.locking import file_lock, LockTimeout
+from rustimport.pre_processing import Preprocessor, SourceMap
+from rustimport.pre_processing.base import merge_cargo_manifests, get_config_directives, get_env_directives, \
+    load_cargo_manifest, get_lib_name
+from rustimport.pre_processing.stubs import ModuleApi, detect_exports, detect_pymodule_name
+from rustimport.settings import Settings
 
 _logger = logging.getLogger(__name__)
 
+# The paths of the importables currently being built by this thread, used to detect cycles:
+_build_stack = threading.local()
+
+
+def _detect_cycles(build):
+    """
+    Decorator for `Importable.build` implementations tracking the importable as being built, which raises a
+    `CircularDependencyError` if it is already being built further up the stack, i.e. if building it
+    (transitively) requires building itself.
+    """
+
+    @wraps(build)
+    def wrapper(self: 'Importable', *args, **kwargs):
+        stack = _build_stack.__dict__.setdefault('paths', [])
+        if self.path in stack:
+            raise CircularDependencyError([*stack[stack.index(self.path):], self.path])
+
+        stack.append(self.path)
+        try:
+            return build(self, *args, **kwargs)
+        finally:
+            stack.pop()
+
+    return wrapper
+
+
+def _apply_settings(method):
+    """
+    Decorator for `Importable` methods applying the importable's per-call `settings` while they run, so that
+    it is built and loaded with the settings it was created with, wherever it is used.
+    """
+
+    @wraps(method)
+    def wrapper(self: 'Importable', *args, **kwargs):
+        with self.settings.applied():
+            return method(self, *args, **kwargs)
+
+    return wrapper
+
+
+# The preprocessing results of the importables whose `needs_rebuild` or `build` is running on this thread (see
+# `_cache_preprocessing`), or `None` if they haven't been preprocessed yet:
+_preprocessing_cache = threading.local()
+
+
+def _cache_preprocessing(method):
+    """
+    Decorator for `Importable.needs_rebuild` and `build` implementations preprocessing the sources at most once per
+    call (see `Importable._preprocessed`), rather than on every access to e.g. `dependencies`, `extension_path` or
+    `toolchain_fingerprint`. This keeps the up-to-date check before every import cheap.
+    """
+
+    @wraps(method)
+    def wrapper(self: 'Importable', *args, **kwargs):
+        cache = _preprocessing_cache.__dict__.setdefault('results', {})
+        if self in cache:
+            # Nested, e.g. `needs_rebuild` while building:
+            return method(self, *args, **kwargs)
+
+        cache[self] = None
+        try:
+            return method(self, *args, **kwargs)
+        finally:
+            del cache[self]
+
+    return wrapper
+
+
+def _lock_build(build):
+    """
+    Decorator for `Importable.build` implementations serializing builds of the same importable, also across
+    processes (e.g. pytest-xdist workers importing the same extension), as concurrent builds in the same build
+    directory corrupt each other. If another process was building the importable, it is only built again if it
+    is still outdated afterwards.
+    """
+
+    @wraps(build)
+    def wrapper(self: 'Importable', release: bool = False):
+        try:
+            with file_lock(self.build_tempdir + '.lock', settings.build_lock_timeout) as waited:
+                if waited and not self.needs_rebuild(release=release):
+                    _logger.info(f"{self.path} has been built by another process in the meantime.")
+                    return BuildResult(rebuilt=False, artifact_path=self.extension_path)
+                return build(self, release=release)
+        except LockTimeout as e:
+            raise BuildLockTimeoutError(
+                f"Another process has been building {self.path} for more than {settings.build_lock_timeout} seconds. "
+                f"Wait for it to finish or increase `settings.build_lock_timeout`. ({e})"
+            ) from e
+
+    return wrapper
+
+
+def _is_logging_configured(level: int) -> bool:
+    """Whether a handler (other than the `NullHandler` added by rustimport) would emit `_logger`'s messages."""
+    logger = _logger
+    while logger:
+        if any(not isinstance(h, logging.NullHandler) and h.level <= level for h in logger.handlers):
+            return True
+        if not logger.propagate:
+            break
+        logger = logger.parent
+    return False
+
+
+def _log_requested(message: str):
+    """
+    Log a message the user explicitly asked for (see `settings.summary` and `settings.verbosity`) at INFO level.
+    If the application hasn't configured logging, it is printed to stderr instead, so that it isn't swallowed.
+    """
+    if _is_logging_configured(logging.INFO):
+        _logger.info(message)
+    else:
+        print(f"rustimport: {message}", file=sys.stderr, flush=True)
+
+
+def _report_build(build):
+    """
+    Decorator for `Importable.build` implementations logging when the build starts and when it is done (at INFO
+    level, or at DEBUG level if `settings.verbosity` is `0`), or that it failed. With `settings.verbosity` `1`,
+    these messages are printed even if logging isn't configured (see `_log_requested`).
+    """
+
+    @wraps(build)
+    def wrapper(self: 'Importable', release: bool = False):
+        def report(message):
+            if settings.verbosity == 1:
+                _log_requested(message)
+            else:
+                _logger.log(logging.INFO if settings.verbosity >= 1 else logging.DEBUG, message)
+
+        report(f"Building {self.fullname}…")
+        start = time.perf_counter()
+        try:
+            result = build(self, release=release)
+        except BuildError as e:
+            _logger.error(f"Failed to build {self.fullname}: {e}")
+            raise
+        report(f"Done building {self.fullname} ({time.perf_counter() - start:.1f}s)")
+        return result
+
+    return wrapper
+
+
+# The names of the build directories in the cache directory (see `Importable.build_tempdir`):
+_BUILD_DIR_NAME = re.compile(r'.+-[0-9a-f]{32}')
+
+
+def _remove_build_dir(build_dir: str, source: str):
+    """Remove the given build directory, waiting for a build in progress in it (of `source`) to finish."""
+    try:
+        with file_lock(build_dir + '.lock', settings.build_lock_timeout):
+            if os.path.isdir(build_dir):
+                _logger.info(f"Removing the build directory {build_dir}")
+                shutil.rmtree(build_dir)
+    except LockTimeout as e:
+        raise BuildLockTimeoutError(
+            f"Another process has been building {source} for more than {settings.build_lock_timeout} seconds, "
+            f"so its build directory can't be removed. ({e})"
+        ) from e
+
+
+def clean_all_build_dirs():
+    """
+    Remove the build directories of all extensions in `settings.cache_dir` (see `Importable.clean`). The rest of
+    the cache directory, e.g. the checksums, the artifact cache and git clones, is kept.
+    """
+    if not os.path.isdir(settings.cache_dir):
+        return
+    for name in sorted(os.listdir(settings.cache_dir)):
+        path = os.path.join(settings.cache_dir, name)
+        if _BUILD_DIR_NAME.fullmatch(name) and os.path.isdir(path):
+            _remove_build_dir(path, name)
+
+
+# Limits the number of concurrent cargo invocations (see `settings.max_concurrent_builds`), created lazily
+# for the current value of the setting:
+_build_semaphore: Optional[Tuple[int, threading.BoundedSemaphore]] = None
+_build_semaphore_lock = threading.Lock()
+
+
+@contextmanager
+def _build_slot():
+    """Wait until fewer than `settings.max_concurrent_builds` cargo invocations are running."""
+    global _build_semaphore
+
+    if settings.max_concurrent_builds <= 0:
+        yield
+        return
+
+    with _build_semaphore_lock:
+        if _build_semaphore is None or _build_semaphore[0] != settings.max_concurrent_builds:
+            _build_semaphore = (settings.max_concurrent_builds, threading.BoundedSemaphore(settings.max_concurrent_builds))
+        semaphore = _build_semaphore[1]
+
+    if not semaphore.acquire(blocking=False):
+        _logger.info(f"Waiting for one of {settings.max_concurrent_builds} concurrent builds to finish…")
+        semaphore.acquire()
+    try:
+        yield
+    finally:
+        semaphore.release()
+
+
+@dataclass
+class BuildResult:
+    """The outcome of building an `Importable` (see `Importable.build()`)."""
+    rebuilt: bool
+    """Whether the extension has been built, or was skipped as it was up-to-date."""
+    artifact_path: str
+    """The path of the built extension."""
+    cargo_stdout: str = ''
+    """Cargo's output on stdout, i.e. its JSON messages."""
+    cargo_stderr: str = ''
+    """
+    Cargo's output on stderr, if it was captured instead of being printed live (i.e. with `settings.verbosity`
+    below 2 or `settings.suppress_build_script_output`).
+    """
+    from_cache: bool = False
+    """Whether the extension has been copied from the artifact cache (see `settings.artifact_cache`) instead of being compiled."""
+    warnings: List[str] = field(default_factory=list)
+    """The rendered compiler warnings, referring to the original sources."""
+    duration: float = 0.0
+    """The time building took, in seconds."""
+
 
 class Importable(abc.ABC):
     """Abstract interface for importable rust entities"""
 
-    def __init__(self, path: str, fullname: Optional[str] = None):
+    def __init__(self, path: str, fullname: Optional[str] = None, module_name: Optional[str] = None,
+                 settings: Optional[Settings] = None):
         self.path = os.path.realpath(path)
-        self.fullname = fullname or os.path.splitext(os.path.basename(path))[0]
+        self.fullname = module_name or fullname or os.path.splitext(os.path.basename(path))[0]
+        self.module_name = module_name
+        """
+        The import name given explicitly (e.g. using `imp(..., module_name=...)`), if any. Unlike `fullname`, it also takes
+        precedence over the `lib.name` of the extension's manifest when using a template.
+        """
+        self.settings = settings if settings is not None else Settings()
+        """
+        The per-call settings overriding the global ones for this importable (e.g. given using `imp_from_path(...,
+        settings=...)`), which its methods apply on top of those in effect when they are called.
+        """
 
     @property
     def extension_path(self):
-        return os.path.join(os.path.dirname(self.path), self.name) + get_extension_suffix()
+        return os.path.join(os.path.dirname(self.path), self.name) + self.extension_suffix
+
+    @property
+    def extension_suffix(self) -> str:
+        """The file name suffix of the extension, e.g. `.cpython-311-x86_64-linux-gnu.so` or `.abi3.so`."""
+        return get_extension_suffix(abi3=self.uses_abi3, target=self.cargo_target)
+
+    @property
+    def uses_abi3(self) -> bool:
+        """
+        Whether the extension is built for Python's stable ABI, as configured by `settings.use_abi3` or
+        the `//config: use_abi3=...` directive in its source.
+        """
+        return self._config_directives.get('use_abi3', settings.use_abi3)
+
+    @property
+    def cargo_target(self) -> Optional[str]:
+        """
+        The target triple the extension is built for, as configured by `settings.target`, the `//config: target=...`
+        directive in its source or `settings.pyodide`, or `None` if it is built for the host.
+        """
+        if settings.pyodide:
+            return _PYODIDE_TARGET
+        return self._config_directives.get('target', settings.target) or None
+
+    @property
+    def _config_directives(self) -> Dict[str, Any]:
+        """The settings overridden for this extension using `//config:` directives in its source."""
+        return get_config_directives(self._directives, self._main_source_path)
+
+    @property
+    def _env_directives(self) -> Dict[str, str]:
+        """The environment variables set for cargo using `//env:` directives in this extension's source."""
+        return get_env_directives(self._directives, self._main_source_path)
+
+    @property
+    def _directives(self) -> Dict[str, List[str]]:
+        """The named directives from the header of the extension's source, e.g. `//config:` lines."""
+        if not os.path.isfile(self._main_source_path):
+            return {}
+        if self in _preprocessing_cache.__dict__.get('results', {}):
+            return self._preprocessed().directives
+        return Preprocessor(self._main_source_path, lib_name=self.name).parse_directives()
+
+    @property
+    def stub_path(self):
+        """The path of the `.pyi` type stub written next to the extension if `settings.generate_stubs` is enabled."""
+        return os.path.join(os.path.dirname(self.path), self.name) + '.pyi'
 
     @property
     def build_tempdir(self):
         return os.path.join(settings.cache_dir, f'{self.fullname}-{hashlib.md5(self.path.encode()).hexdigest()}')
 
+    @property
+    def build_info_path(self):
+        return os.path.join(self.build_tempdir, 'build-info.json')
+
+    @property
+    def build_info(self) -> Optional[Dict[str, Any]]:
+        """
+        Metadata about the last build of this `Importable` (the cargo and rustc versions, the feature
+        resolver and the enabled features), or `None` if it is not available.
+        """
+        try:
+            with open(self.build_info_path, 'r') as f:
+                return json.load(f)
+        except (FileNotFoundError, json.JSONDecodeError):
+            return None
+
+    @property
+    def old_artifacts_dir(self):
+        return os.path.join(self.build_tempdir, 'old-artifacts')
+
+    @property
+    def old_artifacts(self) -> List[Tuple[str, str]]:
+        """
+        The previous builds of this `Importable` retained due to `settings.keep_old_artifacts`, as a list
+        of `(checksum, path)` tuples, the most recent build first.
+        """
+        if not os.path.isdir(self.old_artifacts_dir):
+            return []
+        paths = sorted(
+            (os.path.join(self.old_artifacts_dir, f) for f in os.listdir(self.old_artifacts_dir)),
+            key=os.path.getmtime,
+            reverse=True,
+        )
+        return [(os.path.basename(p)[:-len(self.extension_suffix)], p) for p in paths]
+
     @property
     def name(self):
         return self.fullname.split('.')[-1]
@@ -38,38 +379,623 @@ class Importable(abc.ABC):
     def dependencies(self):
         return [self.path]
 
+    @_apply_settings
+    def input_fingerprint(self, release: Optional[bool] = None) -> str:
+        """
+        Compute a stable hash of all inputs of building this extension, which external build systems
+        can use as a cache key. It covers the contents of all tracked source files (see `dependencies`),
+        the release flag, the build environment (see `build_env`), the settings affecting code generation,
+        the versions of rustimport, cargo and rustc as well as the interpreter's extension suffix (which
+        includes its ABI tag and platform).
+
+        @param release: Whether to fingerprint a release build. Defaults to `settings.compile_release_binaries`.
+        """
+        if release is None:
+            release = settings.compile_release_binaries
+        cargo = Cargo()
+        return calc_input_fingerprint(
+            self.dependencies,
+            base_dir=os.path.dirname(self.extension_path),
+            release=release,
+            build_env=self.build_env,
+            inputs={
+                'rustimport': _get_rustimport_version(),
+                'cargo': cargo.version(),
+                'rustc': cargo.rustc_version(),
+                'python': sys.implementation.cache_tag,
+                'extension_suffix': self.extension_suffix,
+                **self._codegen_inputs,
+            },
+        )
+
+    @property
+    def _codegen_inputs(self) -> Dict[str, str]:
+        """
+        The settings affecting the code and manifest generated by preprocessing, which are part of the checksum (see
+        `needs_rebuild`), so that changing them causes a rebuild.
+        """
+        return {
+            'export_visibility': settings.export_visibility,
+            'auto_export_pub': settings.auto_export_pub,
+            'unused_manifest_keys': settings.unused_manifest_keys,
+            'pyo3_version': settings.pyo3_version,
+            'default_template': settings.default_template or '',
+        }
+
+    @property
+    def build_env(self) -> Dict[str, str]:
+        """Environment variables supplied to cargo, which (might) affect the build output."""
+        env = {
+            'CC': settings.cc,
+            'CXX': settings.cxx,
+            'CFLAGS': settings.cflags,
+            # `//env:` directives take precedence over `settings.cargo_env`:
+            **settings.cargo_env,
+            **self._env_directives,
+        }
+
+        def getenv(var: str) -> str:
+            return env[var] if env.get(var) is not None else os.getenv(var, '')
+
+        if settings.include_paths:
+            include_flags = [f'-I{p}' for p in settings.include_paths]
+            for var in ('CFLAGS', 'CXXFLAGS', 'CPPFLAGS'):
+                env[var] = ' '.join([*getenv(var).split(), *include_flags])
+        if getenv('RUSTFLAGS'):
+            # Also part of the checksum this way, so that changing the flags (e.g. `-C target-cpu`) causes a rebuild:
+            env['RUSTFLAGS'] = getenv('RUSTFLAGS')
+        link_args = [a for platform, args in settings.link_args.items() if sys.platform.startswith(platform) for a in args]
+        target = self.cargo_target
+        if settings.link_search_paths or link_args or settings.pyodide:
+            rustflags = getenv('RUSTFLAGS').split()
+            rustflags.extend(f'-L{p}' for p in settings.link_search_paths)
+            rustflags.extend(f'-Clink-arg={a}' for a in link_args)
+            if settings.pyodide:
+                rustflags.extend(_PYODIDE_RUSTFLAGS)
+            # Unlike `RUSTFLAGS`, this allows spaces within flags (i.e. paths):
+            env['CARGO_ENCODED_RUSTFLAGS'] = '\x1f'.join(rustflags)
+        if target:
+            # Also part of the checksum this way, so that switching the target causes a rebuild:
+            env['CARGO_BUILD_TARGET'] = target
+            # pyo3 can't run the target's interpreter to detect its configuration, so it needs to be told the version:
+            env['PYO3_CROSS_PYTHON_VERSION'] = os.getenv('PYO3_CROSS_PYTHON_VERSION') or \
+                f'{sys.version_info.major}.{sys.version_info.minor}'
+        if settings.reproducible:
+            env.update({
+                'SOURCE_DATE_EPOCH': '0',
+                'CARGO_INCREMENTAL': '0',
+            })
+        return {k: v for k, v in env.items() if v is not None}
+
+    @property
+    def _cargo_env(self) -> Dict[str, str]:
+        """
+        The environment variables to supply to cargo. In addition to `build_env`, this contains
+        machine-specific variables which must not be part of the checksum.
+        """
+        env = self.build_env
+        if settings.reproducible:
+            # Strip all machine-specific paths from the artifact (e.g. in debug info or panic messages):
+            cargo_home = os.getenv('CARGO_HOME') or os.path.join(os.path.expanduser('~'), '.cargo')
+            remap = {self.build_tempdir: '.', cargo_home: '/cargo'}
+            if settings.cargo_target_dir:
+                remap[os.path.abspath(settings.cargo_target_dir)] = '/target'
+
+            rustflags = env['CARGO_ENCODED_RUSTFLAGS'].split('\x1f') if 'CARGO_ENCODED_RUSTFLAGS' in env \
+                else env.get('RUSTFLAGS', '').split()
+            rustflags.extend(f'--remap-path-prefix={src}={dst}' for src, dst in remap.items())
+
+            env['CARGO_ENCODED_RUSTFLAGS'] = '\x1f'.join(rustflags)
+        if settings.emit_compile_commands:
+            env.update(create_compiler_wrappers(
+                self.__compile_commands_dir,
+                cc=env.get('CC') or os.getenv('CC') or 'cc',
+                cxx=env.get('CXX') or os.getenv('CXX') or 'c++',
+            ))
+        return env
+
+    @property
+    def __compile_commands_dir(self):
+        return os.path.join(self.build_tempdir, 'compile-commands')
+
+    def _write_compile_commands(self, crate_path: str):
+        if settings.emit_compile_commands:
+            write_compile_commands(self.__compile_commands_dir, os.path.join(crate_path, 'compile_commands.json'))
+
     @classmethod
     @abc.abstractmethod
-    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True) -> Optional['Importable']:
+    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True,
+                   module_name: Optional[str] = None, settings: Optional[Settings] = None) -> Optional['Importable']:
         """
         Try to create an importable for the given file system path or return `None` if
         this is not possible.
 
         @param opt_in: If true, indicates the user's preference to require manual opt-in. This may
                        be ignored by some implementations, if it is not applicable.
+        @param module_name: The name to import the extension as, if it differs from its file or crate name.
+        @param settings: The per-call settings of the importable, see `settings`.
         @return: Either an `Importable` instance or `None`
         """
         raise NotImplemented
 
+    @_apply_settings
+    @_cache_preprocessing
     def needs_rebuild(self, release: bool = False) -> bool:
         if not os.path.isfile(self.extension_path):
             return True
-        if not is_checksum_valid(self.extension_path, self.dependencies, release=release):
+        if not is_checksum_valid(self.extension_path, self.dependencies, release=release, build_env=self.build_env,
+                                 inputs=self._codegen_inputs):
+            return True
+        if not is_toolchain_fingerprint_valid(self.extension_path, self.toolchain_fingerprint):
+            return True
+        if settings.generate_stubs and not os.path.isfile(self.stub_path):
             return True
         return False
 
     @abc.abstractmethod
-    def build(self, release: bool = False):
+    def build(self, release: bool = False) -> BuildResult:
         """
         Build the native extension for this `Importable`.
 
+        @return: A `BuildResult` describing the build, e.g. its duration and the compiler warnings.
         @raises: `BuildError` if compilation fails.
         """
         raise NotImplemented
 
-    def load(self) -> types.ModuleType:
-        """Load the native extension for this `Importable`, if it exists."""
-        return load.load_module(self.extension_path, self.fullname)
+    @property
+    def toolchain_fingerprint(self) -> Dict[str, Optional[str]]:
+        """
+        The versions of the toolchain and the Python bindings the extension is built with: those of cargo and rustc,
+        and that of pyo3 (or rust-cpython) as locked by the crate's `Cargo.lock`, or otherwise as required by its
+        manifest. As an extension built with other versions might be incompatible (and e.g. crash when imported),
+        changing any of them causes a rebuild.
+        """
+        cargo = Cargo()
+        fingerprint = {'cargo': cargo.version(), 'rustc': cargo.rustc_version()}
+        dependencies = load_cargo_manifest(self._preprocessed().cargo_manifest).get('dependencies', {})
+        locked_versions = _get_locked_versions(self._lockfile_path) if self._lockfile_path else {}
+        for crate in _BINDING_CRATES:
+            if (dependency := dependencies.get(crate)) is not None:
+                required = dependency.get('version') if isinstance(dependency, dict) else dependency
+                fingerprint[crate] = locked_versions.get(crate) or required
+        return fingerprint
+
+    @property
+    def _lockfile_path(self) -> Optional[str]:
+        """The path of the `Cargo.lock` maintained alongside the sources, if any."""
+        return None
+
+    @property
+    def _cargo_package_args(self) -> List[str]:
+        """The arguments selecting the package to build for cargo, e.g. `-p <name>` for a workspace member."""
+        return []
+
+    @abc.abstractmethod
+    def _preprocess(self) -> Preprocessor.PreprocessorResult:
+        """Preprocess the main source file, generating the crate's Cargo manifest and (possibly) updated source."""
+        raise NotImplemented
+
+    def _preprocessed(self) -> Preprocessor.PreprocessorResult:
+        """
+        The result of `_preprocess`, which is only computed once during `needs_rebuild` and `build` (see
+        `_cache_preprocessing`).
+        """
+        cache = _preprocessing_cache.__dict__.get('results', {})
+        if self not in cache:
+            return self._preprocess()
+        if cache[self] is None:
+            cache[self] = self._preprocess()
+        return cache[self]
+
+    @abc.abstractmethod
+    def _prepare_build_dir(self) -> Tuple[str, Preprocessor.PreprocessorResult, bytes]:
+        """
+        Preprocess the sources and write the crate to build to the build directory.
+
+        @return: A tuple of the crate's path in the build directory, the preprocessing result and the
+                 crate's final Cargo manifest.
+        """
+        raise NotImplemented
+
+    @property
+    @abc.abstractmethod
+    def _main_source_path(self) -> str:
+        """The path of the source file defining the module, i.e. the single file or the crate's `src/lib.rs`."""
+        raise NotImplemented
+
+    @_apply_settings
+    def detect_exports(self) -> ModuleApi:
+        """Detect the python API (functions, classes and their methods) of the extension from its source."""
+        with open(self._main_source_path, 'rb') as f:
+            contents = f.read()
+        directives = Preprocessor(self._main_source_path, lib_name=self.name).parse_directives()
+        return detect_exports(contents, directives)
+
+    @abc.abstractmethod
+    def _get_source_path(self, path: str) -> str:
+        """
+        Map a path relative to the crate in the build directory (e.g. from a compiler message) to the path
+        of the original source file.
+        """
+        raise NotImplemented
+
+    def _map_compiler_output(self, output: str, crate_path: str, source_map: Optional[SourceMap]) -> str:
+        """
+        Rewrite the locations in compiler messages (e.g. "--> src/lib.rs:3:5"), which refer to the crate in the
+        build directory, to the original sources. Locations within code generated by preprocessing (e.g. the
+        `#[pymodule]`) refer to the generated file in the build directory instead. This is best-effort: anything
+        not recognized as such a location is left untouched.
+        """
+        main_source = os.path.join('src', 'lib.rs')
+
+        def replace(m: re.Match) -> str:
+            path, line, column = m.group(2), int(m.group(3)), int(m.group(4))
+            if os.path.isabs(path):
+                return m.group(0)
+            if source_map and os.path.normpath(path) == main_source:
+                if (location := source_map.map(line, column)) is None:
+                    return f'{m.group(1)}{os.path.join(crate_path, path)}:{line}:{column}'
+                line, column = location
+            return f'{m.group(1)}{self._get_source_path(path)}:{line}:{column}'
+
+        return re.sub(r'(-->\s*)([^\s:][^:\n]*):(\d+):(\d+)', replace, output)
+
+    @_apply_settings
+    def check(self) -> bool:
+        """
+        Check whether this `Importable` compiles using `cargo check`, without building (or loading) the
+        extension. As no code is generated, this is much faster than a full build, e.g. for catching type
+        errors while editing. Compiler messages are printed to stderr, referring to the original sources.
+
+        @return: Whether the check passed.
+        """
+        crate_path, preprocessed, manifest = self._prepare_build_dir()
+        self._check_module_name(preprocessed, manifest)
+        lockfile_args = self._verify_lockfile(crate_path)
+
+        with _build_slot():
+            result = Cargo().check(
+                crate_path,
+                additional_args=[*self._cargo_package_args, *lockfile_args, *preprocessed.additional_cargo_args],
+                target_dir=settings.cargo_target_dir,
+                env=self._cargo_env,
+                target=_check_cargo_target(self.cargo_target),
+                rewrite_message=lambda m: self._map_compiler_output(m, crate_path, preprocessed.source_map),
+            )
+
+        for output in result.error_output:
+            sys.stderr.write(output)
+        return result.success
+
+    @_apply_settings
+    def clean(self):
+        """
+        Remove this `Importable`'s build directory (see `build_tempdir`), including cargo's incremental
+        compilation data and retained old artifacts. The built extension itself is kept, so it is only rebuilt
+        once it is outdated, which then takes longer. Waits for a build of this `Importable` in progress.
+        """
+        _remove_build_dir(self.build_tempdir, self.path)
+
+    @_apply_settings
+    def test(self) -> bool:
+        """
+        Run the extension's Rust tests (e.g. an inline `#[cfg(test)] mod tests`) using `cargo test`, printing
+        their output. pyo3's `extension-module` feature prevents test binaries from linking to libpython, so it
+        is disabled if it is enabled by default (as done by the `pyo3` template).
+
+        @return: Whether all tests passed.
+        """
+        crate_path, preprocessed, manifest = self._prepare_build_dir()
+
+        args = [*self._cargo_package_args, *self._verify_lockfile(crate_path), *preprocessed.additional_cargo_args]
+        if '--features' in args:
+            # `//no-default-features` enables "extension-module" explicitly, as extensions require it:
+            i = args.index('--features') + 1
+            args[i] = ','.join(f for f in args[i].split(',') if f != 'extension-module')
+            if not args[i]:
+                del args[i - 1:i + 1]
+        default_features = toml.loads(manifest.decode()).get('features', {}).get('default', [])
+        if 'extension-module' in default_features and '--no-default-features' not in args:
+            args.append('--no-default-features')
+            if other_features := [f for f in default_features if f != 'extension-module']:
+                args.extend(['--features', ','.join(other_features)])
+
+        with _build_slot():
+            return Cargo().test(crate_path, additional_args=args, target_dir=settings.cargo_target_dir,
+                                env=self._cargo_env) == 0
+
+    @_apply_settings
+    def load(self, checksum: Optional[str] = None) -> types.ModuleType:
+        """
+        Load the already built native extension for this `Importable`, without checking whether
+        it is up-to-date or building it.
+
+        @param checksum: Load the retained previous build with this checksum (or checksum prefix) instead
+                         of the current one (see `settings.keep_old_artifacts` and `old_artifacts`). It can be
+                         loaded alongside the current build, which stays registered in `sys.modules`.
+        @raises: `ImportError` if the extension has not been built yet.
+        """
+        if checksum is not None:
+            extension_path = next((p for c, p in self.old_artifacts if c.startswith(checksum)), None)
+            if extension_path is None:
+                raise ImportError(
+                    f"No retained build with checksum {checksum} found for {self.path}.",
+                    name=self.fullname,
+                )
+        elif not os.path.isfile(self.extension_path):
+            raise ImportError(
+                f"The extension for {self.path} has not been built yet (expected it at {self.extension_path}). "
+                f"Call `build()` first.",
+                name=self.fullname,
+                path=self.extension_path,
+            )
+        else:
+            extension_path = self.extension_path
+
+        if settings.pyodide:
+            raise ImportError(
+                f"The extension for {self.path} has been built for Pyodide (see `settings.pyodide`), so it can't "
+                f"be loaded by this interpreter. Use `rustimport.build` to build it without importing it.",
+                name=self.fullname,
+                path=extension_path,
+            )
+        if self.cargo_target:
+            raise ImportError(
+                f"The extension for {self.path} has been cross-compiled for {self.cargo_target} (see `settings.target` "
+                f"and `//config: target=...`), so it can't be loaded by this interpreter. Use `rustimport.build` to "
+                f"build it without importing it, and import it on the target.",
+                name=self.fullname,
+                path=extension_path,
+            )
+
+        # A retained build is loaded alongside the current one (e.g. to compare them), so the current build must
+        # neither be detected as a conflicting extension nor be replaced in `sys.modules`:
+        registered = sys.modules.get(self.fullname)
+        side_by_side = checksum is not None and registered is not None and self.__is_own_build(registered)
+        if side_by_side:
+            del sys.modules[self.fullname]
+        try:
+            module = load.load_module(extension_path, self.fullname)
+        except ImportError as e:
+            if 'module export function' not in str(e):
+                raise
+            raise self.__missing_init_function_error(extension_path) from e
+        finally:
+            if side_by_side:
+                sys.modules[self.fullname] = registered
+        load.apply_build_info(module, self.build_info)
+        return module
+
+    def __is_own_build(self, module: types.ModuleType) -> bool:
+        """
+        Whether the module has been loaded from a build of this `Importable`, i.e. the current, a retained or a
+        reloaded one.
+        """
+        path = os.path.realpath(getattr(module, '__file__', None) or '')
+        if path == os.path.realpath(self.extension_path):
+            return True
+        if os.path.dirname(path) == os.path.realpath(self.old_artifacts_dir):
+            return True
+        # Copies loaded by `ModuleHandle.rebuild_if_changed()`:
+        return os.path.dirname(path) == os.path.realpath(os.path.join(settings.cache_dir, 'reload')) \
+            and os.path.basename(path).startswith(f'{self.name}-')
+
+    def __missing_init_function_error(self, extension_path: str) -> ImportError:
+        """
+        Explain Python's "dynamic module does not define module export function" error, which means that the
+        extension's init function isn't named after the module, in terms of the names the user can change.
+        """
+        preprocessed = self._preprocess()
+        pymodule_name, lib_name = self._get_module_names(preprocessed, preprocessed.cargo_manifest)
+        return ImportError(
+            f"{extension_path} doesn't define the init function PyInit_{self.name}, which Python calls to import it as "
+            f"{self.fullname}. The module's #[pymodule] (or rust-cpython's `py_module_initializer!`) has to be named "
+            f"{self.name!r}" + (f" (it is named {pymodule_name!r})" if pymodule_name else "") + f", and so does "
+            f"`lib.name` in the Cargo manifest (it is {lib_name!r}). If you renamed either of them, rename the "
+            f"other one (or the file or crate) accordingly, or import the module under the new name.",
+            name=self.fullname,
+            path=extension_path,
+        )
+
+    @property
+    def _progress_callback(self) -> Optional[Callable[[BuildProgress], None]]:
+        """Forwards progress events of building this extension to `settings.progress_callback`, if set."""
+        if settings.progress_callback is None:
+            return None
+
+        def callback(event: BuildProgress):
+            event.module = self.fullname
+            settings.progress_callback(event)
+
+        return callback
+
+    def _verify_lockfile(self, crate_path: str) -> List[str]:
+        """
+        Check the hash of the crate's `Cargo.lock` against `settings.lockfile_sha256`, if configured, generating
+        the lockfile first if necessary.
+
+        @return: Additional arguments for cargo, which make sure it doesn't modify the verified lockfile.
+        @raises: `BuildError` if the hash doesn't match.
+        """
+        expected = settings.lockfile_sha256
+        if isinstance(expected, dict):
+            expected = expected.get(self.fullname)
+        if not expected:
+            return []
+
+        try:
+            lockfile_path = Cargo().generate_lockfile(crate_path, env=self._cargo_env)
+        except subprocess.CalledProcessError as e:
+            raise BuildError(f"Failed to resolve the dependencies of {self.path}") from e
+        with open(lockfile_path, 'rb') as f:
+            actual = hashlib.sha256(f.read()).hexdigest()
+
+        if actual != expected.lower():
+            raise BuildError(
+                f"The Cargo.lock of {self.path} ({lockfile_path}) doesn't match the approved hash: expected "
+                f"{expected}, but got {actual}. Review the resolved dependencies; if the change is intended, "
+                f"update `settings.lockfile_sha256`."
+            )
+        return ['--locked']
+
+    def _check_module_name(self, preprocessed: Preprocessor.PreprocessorResult, cargo_manifest: bytes):
+        """
+        Make sure that the name of the (handwritten or generated) `#[pymodule]`, the library's `lib.name` and the
+        python module name match, as Python can't find the module's init function otherwise.
+
+        @raises: `ModuleNameMismatchError` if they don't match.
+        """
+        if settings.load_mode != 'python_ext':
+            return
+        pymodule_name, lib_name = self._get_module_names(preprocessed, cargo_manifest)
+        if pymodule_name is None:
+            return  # e.g. rust-cpython's `py_module_initializer!`
+        if not pymodule_name == lib_name == self.name:
+            raise ModuleNameMismatchError(self.path, self.fullname, pymodule_name, lib_name,
+                                          explicit=self.module_name is not None)
+
+    def _get_module_names(self, preprocessed: Preprocessor.PreprocessorResult,
+                          cargo_manifest: bytes) -> Tuple[Optional[str], str]:
+        """
+        The name of the extension's (handwritten or generated) `#[pymodule]`, or `None` if none was found, and its
+        library's `lib.name`, as given by the final manifest.
+        """
+        if preprocessed.updated_source is not None:
+            contents = preprocessed.updated_source
+        else:
+            with open(self._main_source_path, 'rb') as f:
+                contents = f.read()
+        return detect_pymodule_name(contents), get_lib_name(load_cargo_manifest(cargo_manifest))
+
+    def _check_free_space(self):
+        """
+        Raise an `InsufficientDiskSpaceError` if the file system of the build directory (or of the shared target
+        directory) has less than `settings.min_free_space` free.
+        """
+        if settings.min_free_space <= 0:
+            return
+        for directory in filter(None, (self.build_tempdir, settings.cargo_target_dir)):
+            free = shutil.disk_usage(_get_existing_parent(directory)).free
+            if free < settings.min_free_space:
+                raise InsufficientDiskSpaceError(directory, free, settings.min_free_space)
+
+    @contextmanager
+    def _detect_out_of_space(self):
+        """Translate errors due to the disk running full while building (e.g. when copying the artifact)."""
+        try:
+            yield
+        except OSError as e:
+            if e.errno != errno.ENOSPC:
+                raise
+            raise self.__out_of_space_error() from e
+
+    @property
+    def _cached_files(self) -> Dict[str, str]:
+        """The files stored in the artifact cache for each build, by their names in the cache entry."""
+        return {'extension' + self.extension_suffix: self.extension_path, 'build-info.json': self.build_info_path}
+
+    def _save_checksum(self, release: bool):
+        """Save the checksum of the sources and the toolchain fingerprint of the freshly built extension."""
+        save_checksum(self.extension_path, self.dependencies, release=release, build_env=self.build_env,
+                      inputs=self._codegen_inputs)
+        save_toolchain_fingerprint(self.extension_path, self.toolchain_fingerprint)
+
+    def _restore_cached_artifact(self, cache_key: str, release: bool, start: float) -> Optional[BuildResult]:
+        """
+        Copy the extension from the artifact cache (see `settings.artifact_cache`), if it has been built from the
+        same inputs before, and return the `BuildResult`. Returns `None` if it isn't cached.
+        """
+        self._retain_old_artifact()
+        if not restore_cached_artifact(cache_key, self._cached_files):
+            return None
+
+        self._write_stub()
+        self._save_checksum(release)
+        return BuildResult(rebuilt=True, artifact_path=self.extension_path, from_cache=True,
+                           duration=time.perf_counter() - start)
+
+    def _build_result(self, build_result: Cargo.BuildResult, rewrite_message: Callable[[str], str],
+                      start: float) -> BuildResult:
+        """Describe a successful build by a `BuildResult`, given cargo's result and the time the build started."""
+        warnings = [
+            rewrite_message(m['message'].get('rendered') or m['message']['message'])
+            for m in build_result.compiler_messages
+            if m.get('reason') == 'compiler-message' and m['message'].get('level') == 'warning'
+        ]
+        return BuildResult(
+            rebuilt=True,
+            artifact_path=self.extension_path,
+            cargo_stdout=build_result.stdout,
+            cargo_stderr=build_result.stderr,
+            warnings=warnings,
+            duration=time.perf_counter() - start,
+        )
+
+    def _raise_build_failure(self, build_result: Cargo.BuildResult):
+        """
+        Raise a `BuildError` for the failed build, or an `InsufficientDiskSpaceError` if it failed because the disk
+        ran full. As cargo's own output isn't captured while it is printed, the latter is also assumed if the free
+        space has dropped below `settings.min_free_space`.
+        """
+        self._check_free_space()
+        rendered = [
+            m['message'].get('rendered') or '' for m in build_result.compiler_messages
+            if m.get('reason') == 'compiler-message'
+        ]
+        if any(_OUT_OF_SPACE_PATTERN.search(o) for o in [*build_result.error_output, *rendered]):
+            raise self.__out_of_space_error()
+        raise BuildError(f"Failed to build {self.path}")
+
+    def __out_of_space_error(self) -> InsufficientDiskSpaceError:
+        free = shutil.disk_usage(_get_existing_parent(self.build_tempdir)).free
+        return InsufficientDiskSpaceError(self.build_tempdir, free, settings.min_free_space)
+
+    def _smoke_test_import(self):
+        """
+        Import the freshly built extension in a subprocess, if configured, and raise a `BuildError`
+        describing the failure if it can't be imported. This happens before the checksum is saved, so that
+        a broken build is not considered up-to-date.
+        """
+        if not settings.smoke_test_import or self.cargo_target:
+            return
+        if error := load.smoke_test_import(self.extension_path, self.fullname):
+            raise BuildError(f"Built {self.path}, but importing it failed. {error}")
+
+    def _write_stub(self):
+        """Write the extension's type stub next to it, if configured (see `settings.generate_stubs`)."""
+        if settings.generate_stubs:
+            _write_if_changed(self.stub_path, self.detect_exports().to_stub().encode())
+
+    def _retain_old_artifact(self):
+        """Keep a copy of the current artifact before it is replaced by a new build, if configured."""
+        if settings.keep_old_artifacts <= 0 or not os.path.isfile(self.extension_path):
+            return
+        if (checksum := get_checksum(self.extension_path)) is None:
+            return
+
+        os.makedirs(self.old_artifacts_dir, exist_ok=True)
+        shutil.copy2(self.extension_path, os.path.join(self.old_artifacts_dir, checksum + self.extension_suffix))
+
+        for _, path in self.old_artifacts[settings.keep_old_artifacts:]:
+            os.remove(path)
+
+    def _save_build_info(self, cargo: Cargo, build_result: Cargo.BuildResult, cargo_manifest: bytes, release: bool):
+        manifest = toml.loads(cargo_manifest.decode())
+        package = manifest.get('package', {})
+        resolver = package.get('resolver') \
+            or manifest.get('workspace', {}).get('resolver') \
+            or _DEFAULT_RESOLVERS.get(str(package.get('edition')), '1')
+
+        with open(self.build_info_path, 'w') as f:
+            json.dump({
+                'cargo_version': cargo.version(),
+                'rustc_version': cargo.rustc_version(),
+                'resolver': str(resolver),
+                'version': package.get('version') if isinstance(package.get('version'), str) else None,
+                'features': build_result.features,
+                'release': release,
+                'built_at': datetime.datetime.now(datetime.timezone.utc).isoformat(),
+            }, f, indent=2)
 
 
 class SingleFileImportable(Importable):
@@ -78,10 +1004,13 @@ class SingleFileImportable(Importable):
     @property
     def dependencies(self):
         directory = os.path.dirname(self.path)
-        p = Preprocessor(self.path, lib_name=self.name).process()
+        p = self._preprocessed()
+        build_script = _get_build_script(p.cargo_manifest)
         return [
             self.path,
-            *[os.path.join(directory, d) for d in p.dependency_file_patterns]
+            *[os.path.join(directory, d) for d in p.dependency_file_patterns],
+            *([os.path.join(directory, build_script)] if build_script else []),
+            *([settings.manifest_template] if settings.manifest_template else []),
         ]
 
     @property
@@ -89,7 +1018,9 @@ class SingleFileImportable(Importable):
         return os.path.splitext(os.path.basename(self.path))[0]
 
     @classmethod
-    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True) -> Optional['SingleFileImportable']:
+    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True,
+                   module_name: Optional[str] = None,
+                   settings: Optional[Settings] = None) -> Optional['SingleFileImportable']:
         if not path.endswith('.rs'):
             path += '.rs'
 
@@ -98,10 +1029,13 @@ class SingleFileImportable(Importable):
                 return None
 
             _logger.debug(f"[try_import]: Successfully created SingleFileImportable to import from {path}.")
-            return SingleFileImportable(path, fullname=fullname)
+            return SingleFileImportable(path, fullname=fullname, module_name=module_name, settings=settings)
         _logger.debug(f"[try_import]: Failed to create a SingleFileImportable to import from {path}.")
 
-    def build(self, release: bool = False):
+    def _preprocess(self) -> Preprocessor.PreprocessorResult:
+        return Preprocessor(self.path, lib_name=self.name, override_lib_name=self.module_name is not None).process()
+
+    def _prepare_build_dir(self) -> Tuple[str, Preprocessor.PreprocessorResult, bytes]:
         path = os.path.join(self.build_tempdir, self.__crate_name)
 
         _logger.debug(f"Building in temporary directory {path}")
@@ -109,33 +1043,108 @@ class SingleFileImportable(Importable):
 
         os.makedirs(src_path, exist_ok=True)
 
-        preprocessed = Preprocessor(self.path, lib_name=self.name).process()
+        preprocessed = self._preprocessed()
 
         if preprocessed.updated_source is not None:
-            with open(os.path.join(src_path, 'lib.rs'), 'wb+') as f:
-                f.write(preprocessed.updated_source)
+            _write_if_changed(os.path.join(src_path, 'lib.rs'), preprocessed.updated_source)
         else:
             shutil.copy2(self.path, os.path.join(src_path, 'lib.rs'))
 
-        with open(os.path.join(path, 'Cargo.toml'), 'wb+') as f:
-            f.write(preprocessed.cargo_manifest)
+        # Files included using `//include:` directives keep their location relative to the source, so that e.g.
+        # `mod helpers;` refers to helpers.rs next to it:
+        for file in preprocessed.included_files:
+            os.makedirs(os.path.dirname(os.path.join(src_path, file)), exist_ok=True)
+            with open(os.path.join(os.path.dirname(self.path), file), 'rb') as f:
+                _write_if_changed(os.path.join(src_path, file), f.read())
 
-        build_result = Cargo().build(
-            path,
-            destination_path=self.extension_path,
-            release=release,
-            additional_args=preprocessed.additional_cargo_args,
-        )
+        manifest = preprocessed.cargo_manifest
+        if build_script := _get_build_script(manifest):
+            # The build script's path is given relative to the source file, so we copy it into the
+            # generated crate and point the manifest to the copy:
+            shutil.copy2(os.path.join(os.path.dirname(self.path), build_script), os.path.join(path, 'build.rs'))
+            manifest = merge_cargo_manifests(manifest, {'package': {'build': 'build.rs'}})
+
+        _write_if_changed(os.path.join(path, 'Cargo.toml'), manifest)
+        return path, preprocessed, manifest
+
+    @property
+    def _main_source_path(self) -> str:
+        return self.path
+
+    def _get_source_path(self, path: str) -> str:
+        if os.path.normpath(path) == os.path.join('src', 'lib.rs'):
+            return self.path
+        if os.path.normpath(path).startswith('src' + os.sep):
+            # Files included using `//include:` directives:
+            original = os.path.join(os.path.dirname(self.path), os.path.relpath(path, 'src'))
+            if os.path.isfile(original):
+                return original
+        return path
+
+    @_apply_settings
+    @_detect_cycles
+    @_cache_preprocessing
+    @_lock_build
+    @_report_build
+    def build(self, release: bool = False) -> BuildResult:
+        start = time.perf_counter()
+        self._check_free_space()
+        cache_key = self.input_fingerprint(release=release) if settings.artifact_cache else None
+        if cache_key and (cached := self._restore_cached_artifact(cache_key, release, start)):
+            return cached
+
+        path, preprocessed, manifest = self._prepare_build_dir()
+        self._check_module_name(preprocessed, manifest)
+
+        lockfile_args = self._verify_lockfile(path)
+        self._retain_old_artifact()
+
+        def rewrite_message(message: str) -> str:
+            return self._map_compiler_output(message, path, preprocessed.source_map)
+
+        cargo = Cargo()
+        with _build_slot(), self._detect_out_of_space():
+            build_result = cargo.build(
+                path,
+                destination_path=self.extension_path,
+                release=release,
+                additional_args=[*lockfile_args, *preprocessed.additional_cargo_args],
+                target_dir=settings.cargo_target_dir,
+                env=self._cargo_env,
+                progress_callback=self._progress_callback,
+                target=_check_cargo_target(self.cargo_target),
+                rewrite_message=rewrite_message,
+                suppress_output=settings.verbosity < 2,
+                suppress_build_script_output=settings.suppress_build_script_output,
+            )
+        self._write_compile_commands(path)
 
         if not build_result.success:
-            raise BuildError(f"Failed to build {self.path}")
+            self._raise_build_failure(build_result)
+        self._smoke_test_import()
+        self._write_stub()
 
-        save_checksum(self.extension_path, self.dependencies, release=release)
+        self._save_checksum(release)
+        self._save_build_info(cargo, build_result, manifest, release)
+        if cache_key:
+            store_cached_artifact(cache_key, self._cached_files)
+        return self._build_result(build_result, rewrite_message, start)
 
 
 class CrateImportable(Importable):
     """Importable allowing to import a whole rust crate directory."""
 
+    def __init__(self, path: str, fullname: Optional[str] = None, module_name: Optional[str] = None,
+                 package: Optional[str] = None, settings: Optional[Settings] = None):
+        super().__init__(path, fullname=fullname, module_name=module_name, settings=settings)
+        self.package = package
+        """
+        The name of the workspace member this crate is built as (using `cargo build -p <package>` within a copy of its
+        workspace), if given using `imp_from_path(..., package=...)`. Otherwise, the crate is built on its own.
+        """
+        self.workspace_path = _find_workspace_root(self.__crate_path) if package is not None else None
+        """The root directory of the workspace the crate is built in, if it is built as a workspace member."""
+
     @property
     def __crate_path(self):
         return os.path.dirname(self.__manifest_path)
@@ -147,56 +1156,201 @@ class CrateImportable(Importable):
     @property
     def dependencies(self):
         src_path = os.path.join(self.__crate_path, 'src')
-        p = Preprocessor(os.path.join(src_path, 'lib.rs'), lib_name=self.name).process()
-        return [
+        p = self._preprocessed()
+        dependencies = [
             os.path.join(self.__crate_path, '**/*.rs'),
             os.path.join(self.__crate_path, '**/Cargo.*'),
             *[os.path.join(src_path, d) for d in p.dependency_file_patterns],
         ]
+        if self.workspace_path is not None:
+            # The workspace's manifest and the members the crate depends on by path are part of the build as well:
+            dependencies.extend(os.path.join(self.workspace_path, f) for f in ('Cargo.toml', 'Cargo.lock'))
+            for crate in _get_path_dependencies(self.__crate_path, self.workspace_path):
+                dependencies.extend((os.path.join(crate, '**/*.rs'), os.path.join(crate, '**/Cargo.*')))
+        return dependencies
 
     @classmethod
-    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True) -> Optional['Importable']:
+    def try_create(cls, path: str, fullname: Optional[str] = None, opt_in: bool = True,
+                   module_name: Optional[str] = None, package: Optional[str] = None,
+                   settings: Optional[Settings] = None) -> Optional['Importable']:
+        """
+        @param package: The name of a member of the workspace at (or containing) `path` to build, see `package`.
+        @raises: `ImportError` if `package` is given, but there is no workspace or it has no member of that name.
+        """
         manifest_path = path if path.lower().endswith("/cargo.toml") else os.path.join(path, 'Cargo.toml')
         directory = os.path.dirname(manifest_path)
 
+        if package is not None and os.path.isfile(manifest_path):
+            if (workspace_path := _find_workspace_root(directory)) is None:
+                raise ImportError(f"Can't build the package {package}, as {directory} isn't part of a workspace")
+            if (directory := _get_workspace_members(workspace_path).get(package)) is None:
+                raise ImportError(f"The workspace at {workspace_path} has no member named {package}")
+            manifest_path = os.path.join(directory, 'Cargo.toml')
+
         if os.path.isfile(manifest_path):
             if opt_in \
                     and not os.path.isfile(os.path.join(directory, '.rustimport')) \
                     and not _check_first_line_contains_rustimport(manifest_path):
                 return None
-            return CrateImportable(path=directory, fullname=fullname)
+            return CrateImportable(path=directory, fullname=fullname, module_name=module_name, package=package,
+                                   settings=settings)
 
-    def build(self, release: bool = False):
-        output_path = os.path.join(self.build_tempdir, os.path.basename(self.__crate_path))
-        _logger.debug(f"Building in temporary directory {output_path}")
+    @property
+    def _lockfile_path(self) -> Optional[str]:
+        # The lockfile of a workspace member is in the workspace's root directory:
+        directory = self.__crate_path
+        while True:
+            if os.path.isfile(os.path.join(directory, 'Cargo.lock')):
+                return os.path.join(directory, 'Cargo.lock')
+            if os.path.dirname(directory) == directory:
+                return None
+            directory = os.path.dirname(directory)
 
-        os.makedirs(output_path, exist_ok=True)
-        shutil.copytree(self.__crate_path, output_path, dirs_exist_ok=True)
+    @property
+    def _cargo_package_args(self) -> List[str]:
+        return ['-p', self.package] if self.package is not None else []
 
-        preprocessed = Preprocessor(
+    def _preprocess(self) -> Preprocessor.PreprocessorResult:
+        return Preprocessor(
             os.path.join(self.__crate_path, 'src/lib.rs'),
             lib_name=self.name,
             cargo_manifest_path=os.path.join(self.__crate_path, 'Cargo.toml'),
+            override_lib_name=self.module_name is not None,
         ).process()
 
+    def _prepare_build_dir(self) -> Tuple[str, Preprocessor.PreprocessorResult, bytes]:
+        # Workspace members are built within (a copy of) their workspace, which is mirrored in the build directory:
+        source_path = self.workspace_path or self.__crate_path
+        build_path = os.path.join(self.build_tempdir, os.path.basename(source_path))
+        member = os.path.relpath(self.__crate_path, source_path)
+        output_path = os.path.normpath(os.path.join(build_path, member))
+        _logger.debug(f"Building in temporary directory {output_path}")
+
+        preprocessed = self._preprocessed()
+        crates, subset = self.__get_copied_crates()
+
+        manifest = preprocessed.cargo_manifest
+        parsed = load_cargo_manifest(manifest)
+        if _absolutize_path_dependencies(parsed, self.__crate_path, crates if subset else [source_path]):
+            manifest = toml.dumps(parsed).encode()
+
+        generated = {os.path.normpath(os.path.join(member, 'Cargo.toml')): manifest}
         if preprocessed.updated_source is not None:
-            with open(os.path.join(output_path, 'src/lib.rs'), 'wb') as f:
-                f.write(preprocessed.updated_source)
+            generated[os.path.normpath(os.path.join(member, 'src', 'lib.rs'))] = preprocessed.updated_source
+        if self.workspace_path is not None:
+            for path, contents in self.__get_workspace_manifests(crates, subset).items():
+                generated.setdefault(path, contents)
 
-        with open(os.path.join(output_path, 'Cargo.toml'), 'wb') as f:
-            f.write(preprocessed.cargo_manifest)
+        _copy_source_to_build_dir(source_path, build_path, exclude=set(generated),
+                                  only=[os.path.relpath(c, source_path) for c in crates] if subset else None)
+        for path, contents in generated.items():
+            _write_if_changed(os.path.join(build_path, path), contents)
+        return output_path, preprocessed, manifest
 
-        build_result = Cargo().build(
-            output_path,
-            destination_path=self.extension_path,
-            release=release,
-            additional_args=preprocessed.additional_cargo_args,
-        )
+    def __get_copied_crates(self) -> Tuple[List[str], bool]:
+        """
+        The directories of the crates built along with this one (all members of its workspace, or only the ones it
+        depends on by path if `settings.copy_workspace_subset` is enabled), and whether only those are copied to the
+        build directory rather than the whole workspace.
+        """
+        if self.workspace_path is None:
+            return [self.__crate_path], False
+        members = _get_workspace_members(self.workspace_path)
+        if settings.copy_workspace_subset and self.workspace_path not in members.values():
+            return _get_path_dependencies(self.__crate_path, self.workspace_path), True
+        return sorted({self.__crate_path, *members.values()}), False
+
+    def __get_workspace_manifests(self, crates: List[str], subset: bool) -> Dict[str, bytes]:
+        """
+        The manifests of the workspace and its copied members, as far as they need to be adapted for the build
+        directory: Path dependencies outside the copied crates are pointed to their original location, and the
+        workspace's `members` are restricted to the copied ones if only a subset of them is copied.
+        """
+        manifests = {}
+        for crate in dict.fromkeys([self.workspace_path, *crates]):
+            if crate == self.__crate_path:
+                continue  # Generated by preprocessing
+            manifest = load_cargo_manifest(_read(os.path.join(crate, 'Cargo.toml')))
+            changed = _absolutize_path_dependencies(manifest, crate, crates if subset else [self.workspace_path])
+            if crate == self.workspace_path and subset:
+                manifest['workspace']['members'] = [os.path.relpath(c, self.workspace_path) for c in crates]
+                manifest['workspace'].pop('default-members', None)
+                changed = True
+            if changed:
+                manifests[os.path.normpath(os.path.join(os.path.relpath(crate, self.workspace_path), 'Cargo.toml'))] \
+                    = toml.dumps(manifest).encode()
+        return manifests
+
+    @property
+    def _main_source_path(self) -> str:
+        return os.path.join(self.__crate_path, 'src', 'lib.rs')
+
+    def _get_source_path(self, path: str) -> str:
+        return os.path.join(self.__crate_path, path)
+
+    def _map_compiler_output(self, output: str, crate_path: str, source_map: Optional[SourceMap]) -> str:
+        if self.workspace_path not in (None, self.__crate_path):
+            # Cargo reports locations relative to the workspace's root, which we make relative to the crate (or
+            # absolute, for other members) first:
+            member = os.path.relpath(self.__crate_path, self.workspace_path) + os.sep
+
+            def relocate(m: re.Match) -> str:
+                path = m.group(2)
+                if not os.path.isabs(path):
+                    path = path[len(member):] if path.startswith(member) else os.path.join(self.workspace_path, path)
+                return f'{m.group(1)}{path}{m.group(3)}'
+
+            output = re.sub(r'(-->\s*)([^\s:][^:\n]*)(:\d+:\d+)', relocate, output)
+        return super()._map_compiler_output(output, crate_path, source_map)
+
+    @_apply_settings
+    @_detect_cycles
+    @_cache_preprocessing
+    @_lock_build
+    @_report_build
+    def build(self, release: bool = False) -> BuildResult:
+        start = time.perf_counter()
+        self._check_free_space()
+        cache_key = self.input_fingerprint(release=release) if settings.artifact_cache else None
+        if cache_key and (cached := self._restore_cached_artifact(cache_key, release, start)):
+            return cached
+
+        output_path, preprocessed, manifest = self._prepare_build_dir()
+        self._check_module_name(preprocessed, manifest)
+
+        lockfile_args = self._verify_lockfile(output_path)
+        self._retain_old_artifact()
+
+        def rewrite_message(message: str) -> str:
+            return self._map_compiler_output(message, output_path, preprocessed.source_map)
+
+        cargo = Cargo()
+        with _build_slot(), self._detect_out_of_space():
+            build_result = cargo.build(
+                output_path,
+                destination_path=self.extension_path,
+                release=release,
+                additional_args=[*self._cargo_package_args, *lockfile_args, *preprocessed.additional_cargo_args],
+                target_dir=settings.cargo_target_dir,
+                env=self._cargo_env,
+                progress_callback=self._progress_callback,
+                target=_check_cargo_target(self.cargo_target),
+                rewrite_message=rewrite_message,
+                suppress_output=settings.verbosity < 2,
+                suppress_build_script_output=settings.suppress_build_script_output,
+            )
+        self._write_compile_commands(output_path)
 
         if not build_result.success:
-            raise BuildError(f"Failed to build {self.path}")
+            self._raise_build_failure(build_result)
+        self._smoke_test_import()
+        self._write_stub()
 
-        save_checksum(self.extension_path, self.dependencies, release=release)
+        self._save_checksum(release)
+        self._save_build_info(cargo, build_result, preprocessed.cargo_manifest, release)
+        if cache_key:
+            store_cached_artifact(cache_key, self._cached_files)
+        return self._build_result(build_result, rewrite_message, start)
 
 
 all_importables: List[Type[Importable]] = [
@@ -205,6 +1359,250 @@ all_importables: List[Type[Importable]] = [
 ]
 
 
+# The feature resolver cargo uses by default, depending on the crate's edition:
+_DEFAULT_RESOLVERS = {
+    '2021': '2',
+    '2024': '3',
+}
+
+
+# The crates providing the Python bindings, whose versions are part of the `toolchain_fingerprint`:
+_BINDING_CRATES = ('pyo3', 'cpython')
+
+
+# The target to build for if `settings.pyodide` is enabled, and the flags required to produce a side module
+# which Pyodide's interpreter can load (see https://pyodide.org/en/stable/development/building-packages-from-source.html):
+_PYODIDE_TARGET = 'wasm32-unknown-emscripten'
+_PYODIDE_RUSTFLAGS = [
+    '-Crelocation-model=pic',
+    '-Clink-arg=-sSIDE_MODULE=2',
+    '-Clink-arg=-sWASM_BIGINT',
+    '-Zlink-native-libraries=no',
+]
+
+
+# Matches the errors rustc, the linker and cargo report if writing a file fails because the disk is full:
+_OUT_OF_SPACE_PATTERN = re.compile(r'No space left on device|os error 28|ENOSPC')
+
+
+# Entries of a crate which are never copied to the build directory (at any depth):
+_UNCOPIED_ENTRIES = {'target', '.git', '.cargo'}
+# Top-level entries of a crate which are always copied, even if they are ignored by its `.gitignore`:
+_ALWAYS_COPIED_ENTRIES = {'Cargo.toml', 'Cargo.lock'}
+# Top-level entries of the build directory which are never removed, even if they don't exist in the crate,
+# as they belong to the build (in particular cargo's fingerprints and incremental compilation data):
+_BUILD_DIR_ENTRIES = {'target', 'Cargo.lock', 'compile_commands.json'}
+
+
+def _copy_source_to_build_dir(source: str, destination: str, exclude: Set[str] = frozenset(),
+                              only: Optional[Collection[str]] = None):
+    """
+    Update `destination` to mirror the crate at `source`, for building it there.
+
+    To allow cargo to build incrementally, only files which changed are copied (preserving their
+    modification times, which cargo uses to detect changes), and the build directory's `target`
+    directory is always kept. Entries ignored by `_get_copy_ignore` aren't copied.
+
+    @param exclude: Paths (relative to `source`) which are neither copied nor removed, e.g. because
+                    they are generated.
+    @param only: Paths of directories (relative to `source`) to copy along with the files at the top level of
+                 `source`, e.g. the workspace members required for building one of them, instead of the whole tree.
+    """
+    ignore = _get_copy_ignore(source)
+    copied = set(exclude)
+    copied_dirs = set()
+    for directory, subdirs, files in os.walk(source):
+        relative_dir = os.path.relpath(directory, source)
+        ignored = ignore(directory, [*subdirs, *files])
+        subdirs[:] = [d for d in subdirs if d not in ignored]
+        files = [f for f in files if f not in ignored]
+        if only is not None:
+            # Only descend into the given directories and their parents, whose other files aren't copied though:
+            subdirs[:] = [d for d in subdirs if any(
+                _is_within(os.path.join(relative_dir, d), o) or _is_within(o, os.path.join(relative_dir, d))
+                for o in only
+            )]
+            if relative_dir != '.' and not any(_is_within(relative_dir, o) for o in only):
+                files = []
+        copied_dirs.add(relative_dir)
+        os.makedirs(os.path.join(destination, relative_dir), exist_ok=True)
+
+        for file in files:
+            relative_path = os.path.normpath(os.path.join(relative_dir, file))
+            if relative_path in exclude:
+                continue
+            copied.add(relative_path)
+            src, dst = os.path.join(source, relative_path), os.path.join(destination, relative_path)
+            src_stat = os.stat(src)
+            if os.path.isfile(dst):
+                dst_stat = os.stat(dst)
+                if (dst_stat.st_size, dst_stat.st_mtime_ns) == (src_stat.st_size, src_stat.st_mtime_ns):
+                    continue
+            _logger.debug(f"Copying {src} to the build directory")
+            shutil.copy2(src, dst)
+
+    # Remove files which have been deleted from the source:
+    for directory, subdirs, files in os.walk(destination):
+        relative_dir = os.path.relpath(directory, destination)
+        if relative_dir == '.':
+            subdirs[:] = [d for d in subdirs if d not in _BUILD_DIR_ENTRIES]
+            files = [f for f in files if f not in _BUILD_DIR_ENTRIES]
+        for file in files:
+            if os.path.normpath(os.path.join(relative_dir, file)) not in copied:
+                _logger.debug(f"Removing {os.path.join(directory, file)}, which has been deleted from the crate")
+                os.remove(os.path.join(directory, file))
+
+    # Remove directories which have been deleted from the source, deepest first:
+    for directory, subdirs, files in os.walk(destination, topdown=False):
+        relative_dir = os.path.relpath(directory, destination)
+        if relative_dir != '.' and relative_dir.split(os.sep)[0] not in _BUILD_DIR_ENTRIES \
+                and not os.listdir(directory) and relative_dir not in copied_dirs:
+            os.rmdir(directory)
+
+
+def _find_workspace_root(directory: str) -> Optional[str]:
+    """The root directory of the workspace containing `directory`, i.e. the closest one with a `[workspace]` manifest."""
+    directory = os.path.realpath(directory)
+    while True:
+        manifest_path = os.path.join(directory, 'Cargo.toml')
+        if os.path.isfile(manifest_path) and 'workspace' in toml.load(manifest_path):
+            return directory
+        if os.path.dirname(directory) == directory:
+            return None
+        directory = os.path.dirname(directory)
+
+
+def _get_workspace_members(workspace_path: str) -> Dict[str, str]:
+    """Map the package names of the workspace's members (as given by `members` and `exclude`) to their directories."""
+    workspace = toml.load(os.path.join(workspace_path, 'Cargo.toml'))
+    excluded = {os.path.normpath(os.path.join(workspace_path, e)) for e in workspace['workspace'].get('exclude', [])}
+    directories = [workspace_path] if 'package' in workspace else []
+    for pattern in workspace['workspace'].get('members', []):
+        directories.extend(sorted(glob.glob(os.path.join(workspace_path, pattern))))
+
+    members = {}
+    for directory in map(os.path.normpath, directories):
+        manifest_path = os.path.join(directory, 'Cargo.toml')
+        if directory not in excluded and os.path.isfile(manifest_path):
+            if name := toml.load(manifest_path).get('package', {}).get('name'):
+                members.setdefault(name, directory)
+    return members
+
+
+def _get_path_dependencies(crate_path: str, workspace_path: str) -> List[str]:
+    """
+    The directories of the crate at `crate_path` and the crates within the workspace it (transitively) depends on by
+    path, including dev-dependencies (as cargo loads those of workspace members) and `[patch]`es of the workspace.
+    """
+    workspace = load_cargo_manifest(_read(os.path.join(workspace_path, 'Cargo.toml')))
+    inherited = workspace['workspace'].get('dependencies', {})
+
+    crates, stack = [], [crate_path]
+    stack.extend(os.path.normpath(os.path.join(workspace_path, d['path']))
+                 for patches in workspace.get('patch', {}).values() for d in patches.values()
+                 if isinstance(d, Mapping) and isinstance(d.get('path'), str))
+    while stack:
+        if (directory := stack.pop()) in crates or not os.path.isfile(os.path.join(directory, 'Cargo.toml')):
+            continue
+        crates.append(directory)
+        for name, dependency in _iter_dependencies(load_cargo_manifest(_read(os.path.join(directory, 'Cargo.toml')))):
+            base = directory
+            if isinstance(dependency, Mapping) and dependency.get('workspace') is True:
+                # Dependencies inherited from `[workspace.dependencies]` are relative to the workspace's root:
+                dependency, base = inherited.get(name), workspace_path
+            if isinstance(dependency, Mapping) and isinstance(dependency.get('path'), str):
+                if _is_within(path := os.path.normpath(os.path.join(base, dependency['path'])), workspace_path):
+                    stack.append(path)
+    return sorted(crates)
+
+
+# The sections of a manifest listing dependencies (also within `[target.<cfg>]` tables):
+_DEPENDENCY_SECTIONS = ('dependencies', 'dev-dependencies', 'build-dependencies')
+
+
+def _iter_dependencies(manifest: Mapping) -> Iterator[Tuple[str, Any]]:
+    """Iterate the names and specifications of all dependencies listed by the manifest."""
+    tables = [manifest.get(s) for s in _DEPENDENCY_SECTIONS]
+    tables.extend(t.get(s) for t in manifest.get('target', {}).values() if isinstance(t, Mapping)
+                  for s in _DEPENDENCY_SECTIONS)
+    tables.append(manifest.get('workspace', {}).get('dependencies'))
+    tables.extend(manifest.get('patch', {}).values())
+    for table in tables:
+        if isinstance(table, Mapping):
+            yield from table.items()
+
+
+def _absolutize_path_dependencies(manifest: Mapping, crate_path: str, copied: Collection[str]) -> bool:
+    """
+    Point the relative `path` dependencies of the manifest of the crate at `crate_path` which aren't copied to the
+    build directory (i.e. aren't within one of the `copied` directories) to their absolute location, as they
+    wouldn't be found relative to the build directory.
+
+    @return: Whether any path was changed.
+    """
+    changed = False
+    for _, dependency in _iter_dependencies(manifest):
+        if isinstance(dependency, dict) and isinstance(dependency.get('path'), str) \
+                and not os.path.isabs(dependency['path']):
+            path = os.path.normpath(os.path.join(crate_path, dependency['path']))
+            if not any(_is_within(path, c) for c in copied):
+                dependency['path'] = path
+                changed = True
+    return changed
+
+
+def _is_within(path: str, directory: str) -> bool:
+    """Whether `path` is `directory` or located within it (both either absolute or relative to the same directory)."""
+    return os.path.relpath(path, directory).split(os.sep)[0] != os.pardir
+
+
+def _read(path: str) -> bytes:
+    with open(path, 'rb') as f:
+        return f.read()
+
+
+def _get_copy_ignore(source: str) -> Callable[[str, List[str]], Set[str]]:
+    """
+    Create a function telling which entries of a directory within the crate at `source` not to copy to the build
+    directory (see `settings.copy_ignore`), in the style of `shutil.copytree`'s `ignore` argument.
+    """
+    gitignore = GitIgnore.load(os.path.join(source, '.gitignore'))
+
+    def ignore(directory: str, names: List[str]) -> Set[str]:
+        relative_dir = os.path.relpath(directory, source)
+        ignored = {name for name in names if name in _UNCOPIED_ENTRIES}
+        if gitignore:
+            ignored.update(
+                name for name in names
+                if not (relative_dir == '.' and name in _ALWAYS_COPIED_ENTRIES)
+                and gitignore.matches(os.path.join(relative_dir, name), is_dir=os.path.isdir(os.path.join(directory, name)))
+            )
+        if settings.copy_ignore:
+            ignored.update(settings.copy_ignore(directory, names))
+        return ignored
+
+    return ignore
+
+
+def _get_existing_parent(path: str) -> str:
+    """Return `path` or its closest existing parent, e.g. to query the file system of a directory yet to be created."""
+    path = os.path.abspath(path)
+    while not os.path.exists(path) and os.path.dirname(path) != path:
+        path = os.path.dirname(path)
+    return path
+
+
+def _write_if_changed(path: str, contents: bytes):
+    """Write `contents` to `path`, unless it already contains them (which would needlessly invalidate cargo's cache)."""
+    if os.path.isfile(path):
+        with open(path, 'rb') as f:
+            if f.read() == contents:
+                return
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, 'wb') as f:
+        f.write(contents)
+
+
 def _check_first_line_contains_rustimport(filepath: str) -> bool:
     with open(filepath, "r") as f:
         while not (line := f.readline().strip()):  # skip empty lines
@@ -212,7 +1610,57 @@ def _check_first_line_contains_rustimport(filepath: str) -> bool:
         return "rustimport" in line
 
 
-def get_extension_suffix():
+def _get_locked_versions(lockfile_path: str) -> Dict[str, str]:
+    """The versions of the packages in the given `Cargo.lock`, by their names."""
+    try:
+        lockfile = toml.load(lockfile_path)
+    except (OSError, toml.TomlDecodeError):
+        return {}
+    versions = {}
+    for package in lockfile.get('package', []):
+        # A package might be locked in multiple versions:
+        versions[package['name']] = ', '.join(filter(None, [versions.get(package['name']), package.get('version')]))
+    return versions
+
+
+def _get_build_script(cargo_manifest: bytes) -> Optional[str]:
+    build = toml.loads(cargo_manifest.decode()).get('package', {}).get('build')
+    return build if isinstance(build, str) else None
+
+
+def _get_rustimport_version() -> str:
+    import importlib.metadata
+    try:
+        return importlib.metadata.version('rustimport')
+    except importlib.metadata.PackageNotFoundError:
+        return 'unknown'
+
+
+def _check_cargo_target(target: Optional[str]) -> Optional[str]:
+    """Check that the toolchain required to build for the given target triple is available, and return it."""
+    if target == _PYODIDE_TARGET and not shutil.which('emcc'):
+        raise BuildError(
+            "Building for Pyodide requires the Emscripten compiler (emcc), which could not be found. Install the "
+            "Emscripten SDK in the version matching your Pyodide release and activate it (`source emsdk_env.sh`)."
+        )
+    return target
+
+
+def get_extension_suffix(abi3: bool = False, target: Optional[str] = None):
+    """
+    The file name suffix of extensions built for the running interpreter, or for all CPython versions
+    supporting the stable ABI if `abi3` is given. If cross-compiling for the given `target` triple, the
+    suffix doesn't contain the interpreter's platform tag.
+    """
+    if settings.pyodide:
+        # Pyodide's interpreter only loads extensions carrying its own platform tag:
+        return f'.cpython-{sys.version_info.major}{sys.version_info.minor}-wasm32-emscripten.so'
+    if target:
+        # The tags in `EXT_SUFFIX` describe the host, but untagged extensions are loaded by any CPython:
+        return '.pyd' if '-windows' in target else '.abi3.so' if abi3 else '.so'
+    if abi3:
+        # Like `EXT_SUFFIX`, Windows doesn't tag stable ABI extensions:
+        return '.pyd' if sys.platform == 'win32' else '.abi3.so'
     sysvar = sysconfig.get_config_var  # just an abbreviation for below
     return sysvar("EXT_SUFFIX") or sysvar("SO") or '.so'
 
@@ -220,11 +1668,42 @@ def get_extension_suffix():
 def should_rebuild(imp: Importable, force_rebuild: bool = False, force_release: bool = False):
     """
     Utility to check whether the given `Importable` should be re-built, based on the given
-    `force_rebuild` and `force_release` preferences as well as the global settings.
+    `force_rebuild` and `force_release` preferences as well as the settings of the `Importable`.
     """
 
-    if settings.release_mode:
-        return False
-    if settings.force_rebuild or force_rebuild:
-        return True
-    return imp.needs_rebuild(release=settings.compile_release_binaries or force_release)
+    with imp.settings.applied():
+        if settings.release_mode:
+            return False
+        if settings.force_rebuild or force_rebuild:
+            return True
+        return imp.needs_rebuild(release=settings.compile_release_binaries or force_release)
+
+
+def build_if_needed_and_load(imp: Importable, force_rebuild: bool = False, return_build_result: bool = False):
+    """
+    Build the given `Importable` if it should be re-built (see `should_rebuild`) and load it, using its settings. If
+    `settings.summary` is enabled, a one-line summary of whether it was built and how long it took is
+    logged (or printed to stderr, see `_log_requested`) afterwards.
+
+    @param return_build_result: If true, a tuple of the module and the `BuildResult` is returned.
+    """
+    with imp.settings.applied():
+        start = time.perf_counter()
+        built = should_rebuild(imp, force_rebuild=force_rebuild)
+        if built:
+            build_result = imp.build(release=settings.compile_release_binaries)
+        else:
+            _logger.info(f"{imp.fullname} is up-to-date, skipping the build")
+            build_result = BuildResult(rebuilt=False, artifact_path=imp.extension_path,
+                                       duration=time.perf_counter() - start)
+        module = imp.load()
+
+        if settings.summary:
+            duration = time.perf_counter() - start
+            if built:
+                release = (imp.build_info or {}).get('release', settings.compile_release_binaries)
+                status = f"built in {'release' if release else 'debug'} ({duration:.1f}s)"
+            else:
+                status = f"loaded (cache hit, {duration:.2f}s)"
+            _log_requested(f"{imp.fullname} {status}")
+    return (module, build_result) if return_build_result else module
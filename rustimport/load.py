@@ -1,6 +1,12 @@
 import logging
+import os
+import re
+import signal
+import subprocess
 import sys
+import types
 from contextlib import contextmanager
+from typing import Optional, Callable, Any, List, Tuple
 
 from rustimport import settings
 
@@ -14,14 +20,156 @@ def _actually_load_module(extension_path: str, fullname: str):
     module = importlib.util.module_from_spec(spec)
     spec.loader.exec_module(module)
 
+    # Extensions using single-phase initialization (like pyo3's) name themselves after the leaf name given
+    # in the `#[pymodule]`, unless Python happens to supply the package context. Register them under the
+    # full dotted name, so that e.g. `pkg/accel.rs` imported as `pkg.accel` works with relative imports:
+    module.__name__ = fullname
+    module.__package__ = spec.parent
+    sys.modules[fullname] = module
+
     return module
 
 
+Loader = Callable[[str, str], Any]
+
+# Registered loaders as (predicate, loader) pairs. The last registered loader whose predicate applies is used:
+_loaders: List[Tuple[Callable[[], bool], Loader]] = []
+
+
+def register_loader(predicate: Callable[[], bool], loader: Loader):
+    """
+    Register a function to load built libraries, which is used whenever `predicate()` returns true.
+    Loaders registered later take precedence over earlier ones (including the builtin loaders).
+
+    @param predicate: A callable without arguments determining whether the loader applies, e.g. to the
+                      current platform.
+    @param loader: A callable taking the path of the built library and the module's full name, which
+                   returns the loaded module.
+    """
+    _loaders.append((predicate, loader))
+
+
 def load_module(extension_path: str, fullname: str):
+    return _find_loader()(extension_path, fullname)
+
+
+def _find_loader() -> Loader:
+    for predicate, loader in reversed(_loaders):
+        if predicate():
+            return loader
+    raise ValueError(f"Invalid load mode: {settings.load_mode} (expected 'python_ext' or 'ctypes')")
+
+
+def _load_python_extension(extension_path: str, fullname: str):
+    _check_init_symbol_conflicts(extension_path, fullname)
+
     with dlopen_flags():
         return _actually_load_module(extension_path, fullname)
 
 
+def _load_ctypes_library(extension_path: str, fullname: str):
+    import ctypes
+
+    return ctypes.CDLL(extension_path, mode=settings.rtld_flags or ctypes.DEFAULT_MODE)
+
+
+def apply_build_info(module: Any, build_info: Optional[dict]):
+    """
+    Expose metadata of the build on a loaded module: `__build_info__` (see `settings.expose_build_info`) and,
+    if the module doesn't define it itself (e.g. a handwritten `#[pymodule]`), `__version__` from the
+    crate's version.
+    """
+    if not isinstance(module, types.ModuleType):
+        return  # e.g. a `ctypes.CDLL`
+    if settings.expose_build_info:
+        module.__build_info__ = build_info
+    if not hasattr(module, '__version__') and build_info and build_info.get('version'):
+        module.__version__ = build_info['version']
+
+
+def _check_init_symbol_conflicts(extension_path: str, fullname: str):
+    """
+    Python locates an extension's entrypoint by its `PyInit_<name>` symbol, where `<name>` is the last
+    component of the module name. If another extension exporting the same symbol has already been loaded
+    from a different file, the dynamic linker may resolve the symbol to the already loaded library, which
+    causes the wrong module to be returned silently. Thus, we detect this case and raise an error instead.
+    """
+    import importlib.machinery
+
+    name = fullname.split('.')[-1]
+
+    for module in list(sys.modules.values()):
+        if not isinstance(getattr(module, '__loader__', None), importlib.machinery.ExtensionFileLoader):
+            continue
+        other_path = getattr(module, '__file__', None)
+        if (other_path and module.__name__.split('.')[-1] == name
+                and os.path.realpath(other_path) != os.path.realpath(extension_path)):
+            raise ImportError(
+                f"Cannot load {extension_path} as module {fullname}: The extension {other_path}, which "
+                f"exports the same init symbol (PyInit_{name}), has already been loaded as module "
+                f"{module.__name__}. Rename the library (i.e. `lib.name` and the `#[pymodule]` function) "
+                f"of one of them to import both in the same process.",
+                name=fullname,
+                path=extension_path,
+            )
+
+
+_SMOKE_TEST_SCRIPT = """
+import importlib.util, sys
+if hasattr(sys, "setdlopenflags"):
+    sys.setdlopenflags(sys.getdlopenflags() | int(sys.argv[3]))
+spec = importlib.util.spec_from_file_location(sys.argv[2], sys.argv[1])
+spec.loader.exec_module(importlib.util.module_from_spec(spec))
+"""
+
+
+def smoke_test_import(extension_path: str, fullname: str) -> Optional[str]:
+    """
+    Import the given extension in a separate interpreter, so that failures at initialization (including
+    crashes) neither affect the current interpreter nor surface far from the build.
+
+    @return: A description of the failure, or `None` if the extension was imported successfully.
+    """
+    if _find_loader() is not _load_python_extension:
+        return None
+
+    proc = subprocess.run(
+        [sys.executable, '-c', _SMOKE_TEST_SCRIPT, extension_path, fullname, str(settings.rtld_flags)],
+        capture_output=True,
+        text=True,
+    )
+    if proc.returncode == 0:
+        return None
+
+    output = proc.stderr.strip()
+    if proc.returncode < 0:
+        try:
+            reason = f"The interpreter crashed ({signal.Signals(-proc.returncode).name})"
+        except ValueError:
+            reason = f"The interpreter was killed by signal {-proc.returncode}"
+    elif 'PanicException' in output or 'panicked at' in output:
+        reason = "The module panicked during initialization"
+    elif 'does not define module export function' in output:
+        reason = (f"The extension does not export the init function PyInit_{fullname.split('.')[-1]}; make "
+                  f"sure the `#[pymodule]` function and `lib.name` match the module name")
+    elif m := re.search(r'undefined symbol: (\S+)|Symbol not found: (\S+)', output):
+        symbol = m.group(1) or m.group(2)
+        if re.match(r'_*Py', symbol):
+            reason = (f"The extension references the Python API symbol {symbol}, which this interpreter does "
+                      f"not provide; it was probably built for a different Python version or ABI")
+        else:
+            reason = f"The extension references the missing symbol {symbol}; is a native library not linked?"
+    elif 'cannot open shared object file' in output or 'Library not loaded' in output:
+        reason = "A shared library the extension depends on could not be found"
+    else:
+        reason = "The module failed to initialize"
+    return f"{reason}:\n{output}"
+
+
+register_loader(lambda: settings.load_mode == 'python_ext', _load_python_extension)
+register_loader(lambda: settings.load_mode == 'ctypes', _load_ctypes_library)
+
+
 @contextmanager
 def dlopen_flags():
     # See `rustimport.settings.rtld_flags` for an explanation
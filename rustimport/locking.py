@@ -0,0 +1,70 @@
+import logging
+import os
+import time
+from contextlib import contextmanager
+from typing import Iterator
+
+_logger = logging.getLogger(__name__)
+
+# How often to retry acquiring a lock held by someone else (in seconds):
+_POLL_INTERVAL = 0.1
+
+
+class LockTimeout(Exception):
+    """Raised if a lock couldn't be acquired within the given timeout."""
+
+
+@contextmanager
+def file_lock(path: str, timeout: float) -> Iterator[bool]:
+    """
+    Hold an exclusive lock on the file at `path` (which is created if necessary), waiting for at most `timeout`
+    seconds if another process (or thread) holds it. The lock is released by the operating system if its holder
+    dies, so it never goes stale.
+
+    @return: A context manager yielding whether the lock was held by someone else and thus had to be waited for.
+    @raises: `LockTimeout` if the lock couldn't be acquired within `timeout` seconds.
+    """
+    os.makedirs(os.path.dirname(path), exist_ok=True)
+    with open(path, 'a+b') as f:
+        waited = False
+        deadline = time.monotonic() + timeout
+        while not _try_lock(f.fileno()):
+            if not waited:
+                _logger.info(f"Waiting for the lock {path} held by another process…")
+                waited = True
+            if time.monotonic() >= deadline:
+                raise LockTimeout(f"Timed out after {timeout} seconds waiting for the lock {path}")
+            time.sleep(_POLL_INTERVAL)
+        try:
+            yield waited
+        finally:
+            _unlock(f.fileno())
+
+
+if os.name == 'nt':
+    import msvcrt
+
+    def _try_lock(fd: int) -> bool:
+        os.lseek(fd, 0, os.SEEK_SET)
+        try:
+            msvcrt.locking(fd, msvcrt.LK_NBLCK, 1)
+            return True
+        except OSError:
+            return False
+
+    def _unlock(fd: int):
+        os.lseek(fd, 0, os.SEEK_SET)
+        msvcrt.locking(fd, msvcrt.LK_UNLCK, 1)
+else:
+    import fcntl
+
+    def _try_lock(fd: int) -> bool:
+        # Unlike `fcntl.lockf`, `flock` locks conflict even within the same process, e.g. between threads:
+        try:
+            fcntl.flock(fd, fcntl.LOCK_EX | fcntl.LOCK_NB)
+            return True
+        except BlockingIOError:
+            return False
+
+    def _unlock(fd: int):
+        fcntl.flock(fd, fcntl.LOCK_UN)
@@ -0,0 +1,50 @@
+import logging
+import os
+import shutil
+from typing import Optional, List
+
+from rustimport.importable import Importable
+from rustimport.pre_processing.stubs import write_py_typed_marker
+
+_logger = logging.getLogger(__name__)
+
+
+def generate_package_shim(importable: Importable, output_dir: str, fallback: Optional[str] = None) -> str:
+    """
+    Lay out the (already built) extension as a conventional python package in `output_dir`: The extension
+    itself, an `__init__.py` re-exporting its public names, a `.pyi` type stub and a `py.typed` marker.
+
+    @param fallback: The module to import the same names from if the extension can't be imported, e.g. a
+                     pure-python implementation (`".pure"`, relative to the package). If `None`, import
+                     errors are propagated.
+    @return: The path of the generated `__init__.py`.
+    """
+    api = importable.detect_exports()
+    os.makedirs(output_dir, exist_ok=True)
+
+    extension_path = os.path.join(output_dir, os.path.basename(importable.extension_path))
+    _logger.info(f"Copying {importable.extension_path} to {extension_path}")
+    shutil.copy2(importable.extension_path, extension_path + '.tmp')
+    os.replace(extension_path + '.tmp', extension_path)
+
+    with open(os.path.join(output_dir, f'{importable.name}.pyi'), 'w') as f:
+        f.write(api.to_stub())
+    write_py_typed_marker(output_dir)
+
+    init_path = os.path.join(output_dir, '__init__.py')
+    with open(init_path, 'w') as f:
+        f.write(_render_init(importable.name, api.names, fallback))
+    return init_path
+
+
+def _render_init(name: str, exports: List[str], fallback: Optional[str]) -> str:
+    def import_statement(module: str, indent: str = '') -> str:
+        return f'{indent}from {module} import (\n' + ''.join(f'{indent}    {n},\n' for n in exports) + f'{indent})\n'
+
+    header = f'# Generated by rustimport. Re-exports the public names of the native module `{name}`.\n\n'
+    if fallback:
+        imports = 'try:\n' + import_statement(f'.{name}', '    ') + 'except ImportError:\n' \
+                  + import_statement(fallback, '    ')
+    else:
+        imports = import_statement(f'.{name}')
+    return header + imports + '\n__all__ = [\n' + ''.join(f'    {n!r},\n' for n in exports) + ']\n'
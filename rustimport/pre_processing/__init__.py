@@ -1,10 +1,45 @@
+import glob
+import logging
+import os
 import re
-from dataclasses import dataclass
+from dataclasses import dataclass, field
 from typing import List, Tuple, Optional, Dict, Type
 
-from rustimport.pre_processing.base import merge_cargo_manifests, Template
+import toml
+
+from rustimport import settings
+from rustimport.pre_processing.base import merge_cargo_manifests, Template, split_directive_values, get_profile_directives
+from rustimport.pre_processing.cpython_template import CPythonTemplate
 from rustimport.pre_processing.pyo3_template import PyO3Template
 
+_logger = logging.getLogger(__name__)
+
+
+@dataclass
+class SourceMap:
+    """
+    Maps locations in the preprocessed source back to the original one. Preprocessing keeps the original lines in
+    place: The `//prelude:` is inserted at the start of the first line and generated code (e.g. the `#[pymodule]`)
+    is appended after the original lines.
+    """
+    original_lines: int
+    """The number of lines of the original source; lines after these are generated."""
+    first_line_offset: int = 0
+    """The number of characters inserted at the start of the first line."""
+
+    def map(self, line: int, column: int) -> Optional[Tuple[int, int]]:
+        """
+        Map a 1-based line and column of the preprocessed source to the original source, or return `None` if
+        they refer to generated code.
+        """
+        if line > self.original_lines:
+            return None
+        if line == 1 and self.first_line_offset:
+            if column <= self.first_line_offset:
+                return None
+            return line, column - self.first_line_offset
+        return line, column
+
 
 class Preprocessor:
     @dataclass
@@ -13,17 +48,37 @@ class Preprocessor:
         dependency_file_patterns: List[str]
         updated_source: Optional[bytes]
         additional_cargo_args: List[str]
+        source_map: Optional[SourceMap] = None
+        """Maps locations in `updated_source` back to the original source, if it has been updated."""
+        included_files: List[str] = field(default_factory=list)
+        """
+        The files to copy into the generated crate's `src` directory along with the source, given using `//include:`
+        directives, relative to the source's directory.
+        """
+        source_manifest: bytes = b''
+        """
+        The manifest given by the user (i.e. the crate's `Cargo.toml`, the manifest template and the `//:` lines),
+        before the template filled in its defaults.
+        """
+        directives: Dict[str, List[str]] = field(default_factory=dict)
+        """The named directives from the source's header (see `parse_directives`)."""
 
-    def __init__(self, path: str, lib_name: str, cargo_manifest_path: Optional[str] = None):
+    def __init__(self, path: str, lib_name: str, cargo_manifest_path: Optional[str] = None,
+                 override_lib_name: bool = False):
+        """
+        @param override_lib_name: Whether the templates should name the library `lib_name` even if the manifest gives
+                                  a different `lib.name`, e.g. because the import name was given explicitly.
+        """
         self.path = path
         self.lib_name = lib_name
         self.cargo_manifest_path = cargo_manifest_path
+        self.override_lib_name = override_lib_name
 
     def process(self) -> PreprocessorResult:
         with open(self.path, 'rb') as f:
             contents = f.read()
 
-        manifest, template_name, deps = self.__parse_header(contents)
+        manifest, template_name, template_options, deps, directives = self.__parse_header(contents)
 
         if self.cargo_manifest_path is not None:
             with open(self.cargo_manifest_path, 'rb') as f:
@@ -31,28 +86,186 @@ class Preprocessor:
                     manifest = merge_cargo_manifests(f.read(), manifest)
                 else:
                     manifest = f.read()
+        elif settings.manifest_template:
+            manifest = merge_cargo_manifests(self.__render_manifest_template(settings.manifest_template), manifest)
 
         if template_name:
-            template = all_templates[template_name.lower()](self.path, self.lib_name, contents, manifest)
+            template = all_templates[template_name.lower()](
+                self.path, self.lib_name, contents, manifest, directives=directives, options=template_options,
+                override_lib_name=self.override_lib_name,
+            )
             templating_result = template.process()
         else:
             templating_result = None
+            self.__check_pyo3_dependency(contents, manifest)
+
+        updated_source = templating_result.contents if templating_result else None
+        if test_prelude := [p for value in directives.get('test-prelude', []) for p in split_directive_values(value)]:
+            updated_source = self.__append_test_prelude(test_prelude, updated_source if updated_source is not None else contents)
+            deps = [*deps, *test_prelude]
+        # Files to include into the generated crate, e.g. `//include: helpers.rs, util/*.rs`, which are tracked
+        # like `//d:` dependencies:
+        includes = [p for value in directives.get('include', []) for p in split_directive_values(value)]
+        deps = [*deps, *includes]
+        first_line_offset = 0
+        if prelude := [p.encode() for p in directives.get('prelude', []) if p]:
+            original = updated_source if updated_source is not None else contents
+            updated_source = self.__prepend_prelude(prelude, original)
+            first_line_offset = len(updated_source.decode()) - len(original.decode())
+
+        # Cargo features to enable, e.g. `//features: serde, "extra"`. These must precede the template's
+        # arguments, as those might contain arguments for rustc (following a `--`):
+        cargo_args = []
+        features = [f for value in directives.get('features', []) for f in split_directive_values(value)]
+        if 'no-default-features' in directives:
+            cargo_args.append('--no-default-features')
+            final_manifest = toml.loads((templating_result.cargo_manifest if templating_result else manifest).decode())
+            # Extensions can't be loaded without pyo3's "extension-module" feature, which the pyo3 template enables
+            # through a default feature:
+            if 'extension-module' in final_manifest.get('features', {}) and 'extension-module' not in features:
+                features.append('extension-module')
+        if features:
+            cargo_args.extend(['--features', ','.join(features)])
+        if templating_result:
+            cargo_args.extend(templating_result.additional_cargo_args)
+
+        cargo_manifest = templating_result.cargo_manifest if templating_result else manifest
+        # Profile settings given using `//profile:` directives take precedence over the manifest's:
+        if profiles := get_profile_directives(directives, self.path):
+            cargo_manifest = merge_cargo_manifests(cargo_manifest, {'profile': profiles})
 
         return self.PreprocessorResult(
-            cargo_manifest=templating_result.cargo_manifest if templating_result else manifest,
+            cargo_manifest=cargo_manifest,
             dependency_file_patterns=deps,
-            updated_source=templating_result.contents if templating_result else None,
-            additional_cargo_args=templating_result.additional_cargo_args if templating_result else [],
+            updated_source=updated_source,
+            additional_cargo_args=cargo_args,
+            source_map=SourceMap(
+                original_lines=contents.count(b'\n') + (not contents.endswith(b'\n')),
+                first_line_offset=first_line_offset,
+            ) if updated_source is not None else None,
+            included_files=self.__expand_includes(includes),
+            source_manifest=manifest,
+            directives=directives,
         )
 
+    def __expand_includes(self, patterns: List[str]) -> List[str]:
+        """
+        Expand the file names and glob patterns given using `//include:` directives to the paths of the included files,
+        relative to the source's directory.
+        """
+        directory = os.path.dirname(os.path.abspath(self.path))
+        files = []
+        for pattern in patterns:
+            if glob.has_magic(pattern):
+                matches = sorted(glob.glob(os.path.join(directory, pattern), recursive=True))
+            elif os.path.isfile(os.path.join(directory, pattern)):
+                matches = [os.path.join(directory, pattern)]
+            else:
+                raise FileNotFoundError(f"{self.path}: The file {pattern} given in an //include: directive doesn't exist")
+
+            for match in matches:
+                relative = os.path.relpath(match, directory)
+                if relative.split(os.sep)[0] == os.pardir:
+                    raise ValueError(f"{self.path}: Only files within the source's directory can be included, "
+                                     f"but //include: {pattern} refers to {match}")
+                # The source itself is the crate's `lib.rs` (and might be matched by a pattern like `*.rs`):
+                if relative != os.path.basename(self.path) and relative not in files:
+                    files.append(relative)
+        return files
+
+    def parse_directives(self) -> Dict[str, List[str]]:
+        """Parse the named directives (e.g. `//typemap: ...`) from the source's header."""
+        with open(self.path, 'rb') as f:
+            return self.__parse_header(f.read())[4]
+
     @staticmethod
-    def __parse_header(contents: bytes) -> Tuple[bytes, Optional[str], List[str]]:
+    def __prepend_prelude(prelude: List[bytes], contents: bytes) -> bytes:
+        """
+        Insert the lines given using `//prelude:` directives (e.g. `#[macro_use] extern crate cpython;`) at
+        the very top of the source.
+        """
+        # Inner attributes (`#![...]`) must precede all items:
+        prelude = sorted(prelude, key=lambda line: not line.startswith(b'#!'))
+        # Directives are only parsed from the header, so the first line is always a comment (or empty) and
+        # we can put the prelude on the same line.
+        # This way, line numbers in compiler messages still match the original file:
+        return b' '.join(prelude) + b' ' + contents
+
+    def __append_test_prelude(self, paths: List[str], contents: bytes) -> bytes:
+        """
+        Include the files given using `//test-prelude:` directives (relative to the source file) into a `test_prelude`
+        module, which is only compiled for tests, so that inline tests can share helpers using
+        `use crate::test_prelude::*;`.
+        """
+        directory = os.path.dirname(os.path.abspath(self.path))
+        includes = [
+            b'  include!("' + os.path.join(directory, p).replace(os.sep, '/').replace('"', '\\"').encode() + b'");'
+            for p in paths
+        ]
+        # Appended at the end, so that line numbers in compiler messages still match the original file:
+        return contents + b'\n\n' + b'\n'.join([
+            b'#[cfg(test)]',
+            b'#[allow(dead_code, unused_imports)]',
+            b'pub(crate) mod test_prelude {',
+            b'  use super::*;',
+            *includes,
+            b'}',
+        ]) + b'\n'
+
+    def __render_manifest_template(self, template_path: str) -> bytes:
+        """Fill in the placeholders of the manifest template (see `settings.manifest_template`)."""
+        placeholders = {
+            'name': self.lib_name,
+            'crate_type': 'cdylib',
+            'source_dir': os.path.dirname(os.path.abspath(self.path)),
+        }
+
+        def replace(m: re.Match) -> str:
+            if m.group(1) not in placeholders:
+                raise ValueError(f"Unknown placeholder {m.group(0)} in manifest template {template_path} "
+                                 f"(available: {', '.join(placeholders)})")
+            return placeholders[m.group(1)]
+
+        with open(template_path, 'r') as f:
+            return re.sub(r'\{\{\s*(\w+)\s*}}', replace, f.read()).encode()
+
+    def __check_pyo3_dependency(self, contents: bytes, manifest: bytes):
+        """
+        Heuristically detect a common mistake: Using pyo3's macros without declaring pyo3 as a
+        dependency (nor using the pyo3 template, which would declare it automatically).
+        """
+        if not re.search(rb'#\[(?:pyfunction|pyclass|pymodule)\b', contents):
+            return
+
+        try:
+            dependencies = toml.loads(manifest.decode()).get('dependencies', {})
+        except (toml.TomlDecodeError, UnicodeDecodeError):
+            return  # cargo will report a malformed manifest itself
+
+        if 'pyo3' not in dependencies:
+            _logger.warning(
+                f"{self.path} uses pyo3 macros (e.g. #[pyfunction]), but pyo3 is not declared as a dependency in "
+                f"its Cargo manifest, which will most likely fail the build. Either use the pyo3 template by "
+                f"changing the first line to `// rustimport:pyo3`, or add pyo3 to the manifest manually, e.g.:\n"
+                f"//: [dependencies]\n"
+                f"//: pyo3 = {{ version = \"{settings.pyo3_version}\", features = [\"extension-module\"] }}"
+            )
+
+    @staticmethod
+    def __parse_header(contents: bytes) -> Tuple[bytes, Optional[str], Dict[str, str], List[str], Dict[str, List[str]]]:
         manifest = b''
         template_name = None
+        template_options = {}
         dependency_file_patterns = []
+        directives: Dict[str, List[str]] = {}
 
-        if m := re.match(rb'//\s*rustimport(?:\s*:\s*([\w-]+))?$', contents.lstrip().split(b'\n', 1)[0].strip()):
-            template_name = m.group(1).decode() if m.group(1) else None
+        first_line = contents.lstrip().split(b'\n', 1)[0].strip()
+        if m := re.match(rb'//\s*rustimport(?:\s*:\s*([\w-]+)(?:\s*\((.*)\))?)?$', first_line):
+            template_name = m.group(1).decode() if m.group(1) else settings.default_template
+            # Template options, e.g. `// rustimport:pyo3(manifest=manual)`. Values may be quoted:
+            for option in split_directive_values((m.group(2) or b'').decode()):
+                key, _, value = option.partition('=')
+                template_options[key.strip()] = value.strip()
 
         for line in map(bytes.strip, contents.splitlines()):
             # Break on first non-comment, non-empty line since the header must come before all code:
@@ -62,9 +275,20 @@ class Preprocessor:
                 manifest += line[3:].lstrip() + b'\n'
             elif line.startswith(b'//d:'):
                 dependency_file_patterns.append(line[4:].lstrip().decode())
-        return manifest + b'\n', template_name, dependency_file_patterns
+            elif line[2:].strip().decode() in _FLAG_DIRECTIVES:
+                # Flags don't have a value, e.g. `//no-default-features`:
+                directives.setdefault(line[2:].strip().decode(), []).append('')
+            elif m := re.match(rb'//([a-z][\w-]*):(.*)$', line):
+                # Named directives (e.g. `//lazy-submodule: heavy`) are handed to the template:
+                directives.setdefault(m.group(1).decode(), []).append(m.group(2).strip().decode())
+        return manifest + b'\n', template_name, template_options, dependency_file_patterns, directives
+
+
+# Directives without a value, e.g. `//no-default-features`:
+_FLAG_DIRECTIVES = {'no-default-features'}
 
 
 all_templates: Dict[str, Type[Template]] = {
-    'pyo3': PyO3Template
+    'pyo3': PyO3Template,
+    'cpython': CPythonTemplate,
 }
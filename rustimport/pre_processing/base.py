@@ -1,9 +1,18 @@
 import abc
+import copy
+import logging
+import re
+import shlex
+import sys
 import typing
 from dataclasses import dataclass
 
 import toml
 
+from rustimport import settings
+
+_logger = logging.getLogger(__name__)
+
 
 class Template(abc.ABC):
     @dataclass
@@ -12,27 +21,163 @@ class Template(abc.ABC):
         contents: bytes
         additional_cargo_args: typing.List[str]
 
-    def __init__(self, path: str, lib_name: str, contents: bytes, cargo_manifest: bytes):
+    def __init__(self, path: str, lib_name: str, contents: bytes, cargo_manifest: bytes,
+                 directives: typing.Optional[typing.Dict[str, typing.List[str]]] = None,
+                 options: typing.Optional[typing.Dict[str, str]] = None, override_lib_name: bool = False):
         self.path = path
         self.lib_name = lib_name
+        self.override_lib_name = override_lib_name
+        """Whether `lib_name` takes precedence over a `lib.name` given by the manifest, see `Preprocessor`."""
         self.contents = contents
         self.cargo_manifest = cargo_manifest
+        self.directives = directives or {}
+        self.options = options or {}
 
     @abc.abstractmethod
     def process(self) -> TemplatingResult:
         raise NotImplemented
 
     def _copy_manifest_with_defaults(self, defaults: typing.MutableMapping) -> bytes:
-        return merge_cargo_manifests(defaults, self.cargo_manifest)
+        manifest = merge_cargo_manifests(defaults, self.cargo_manifest)
+        if self.override_lib_name:
+            manifest = merge_cargo_manifests(manifest, {'lib': {'name': self.lib_name}})
+        return manifest
+
+    @staticmethod
+    def _enable_default_feature(manifest: bytes, feature: str) -> bytes:
+        """
+        Add `feature` to the manifest's default features. The user's `[features] default` replaces the template's
+        one when merging the manifests, which would otherwise silently drop features the extension requires.
+        """
+        parsed = load_cargo_manifest(manifest)
+        default = parsed.setdefault('features', {}).setdefault('default', [])
+        if feature in default:
+            return manifest
+        default.append(feature)
+        return toml.dumps(parsed).encode()
+
+    def _get_extension_module_cargo_args(self) -> typing.List[str]:
+        """
+        The arguments to pass to cargo for building a Python extension which doesn't link to libpython (i.e. with
+        the `extension-module` feature of pyo3 or rust-cpython).
+        """
+        target = get_config_directives(self.directives, self.path).get('target', settings.target)
+        if ('apple-darwin' in target if target else sys.platform == "darwin") and not settings.pyodide:
+            # On macOS, because the extension-module feature disables linking to
+            # libpython, some additional linker arguments need to be set.
+            # See more: https://pyo3.rs/master/building_and_distribution.html#macos
+            return [
+                "--",
+                "-C", "link-arg=-undefined",
+                "-C", "link-arg=dynamic_lookup",
+            ]
+        return []
 
 
 def merge_cargo_manifests(a: typing.Union[bytes, typing.Mapping], b: typing.Union[bytes, typing.Mapping]) -> bytes:
     return toml.dumps(_recursive_setdefault(
-        toml.loads(b.decode()) if isinstance(b, bytes) else b,
-        toml.loads(a.decode()) if isinstance(a, bytes) else a,
+        load_cargo_manifest(b) if isinstance(b, bytes) else b,
+        load_cargo_manifest(a) if isinstance(a, bytes) else a,
     )).encode()
 
 
+def load_cargo_manifest(manifest: bytes) -> typing.Dict[str, typing.Any]:
+    """
+    Parse a cargo manifest. Unlike `toml.loads` alone, this handles escape sequences in quoted keys, which
+    `toml.dumps` produces for keys containing quotes, e.g. `[target.'cfg(target_arch = "wasm32")'.dependencies]`.
+    Without this, such keys would gain another level of escaping each time a manifest is processed.
+    """
+    return _unescape_keys(toml.loads(manifest.decode()))
+
+
+def get_lib_name(manifest: typing.Mapping[str, typing.Any]) -> str:
+    """
+    The name of the library built from the given (parsed) manifest, which the extension's init function has to be
+    named after: its `lib.name` or, like cargo does by default, the package's name with dashes replaced by underscores.
+    """
+    return manifest.get('lib', {}).get('name') or manifest.get('package', {}).get('name', '').replace('-', '_')
+
+
+def _unescape_keys(table: typing.MutableMapping) -> typing.MutableMapping:
+    for key in list(table):
+        value = table.pop(key)
+        if isinstance(value, typing.MutableMapping):
+            value = _unescape_keys(value)
+        # Backslashes don't occur in unescaped keys of cargo manifests (e.g. cfg expressions or target triples):
+        table[re.sub(r'\\(["\\])', r'\1', key) if '\\' in key else key] = value
+    return table
+
+
+# Known keys of the sections we validate in `sanitize_cargo_manifest`. Sections not listed here (like
+# `[dependencies]` or `[features]`) contain arbitrary user-defined keys and are not validated.
+# See https://doc.rust-lang.org/cargo/reference/manifest.html
+_KNOWN_MANIFEST_KEYS = {
+    None: {
+        'cargo-features', 'package', 'lib', 'bin', 'example', 'test', 'bench', 'dependencies',
+        'dev-dependencies', 'build-dependencies', 'target', 'badges', 'features', 'lints', 'patch',
+        'replace', 'profile', 'workspace',
+    },
+    'package': {
+        'name', 'version', 'authors', 'edition', 'rust-version', 'description', 'documentation', 'readme',
+        'homepage', 'repository', 'license', 'license-file', 'keywords', 'categories', 'workspace', 'build',
+        'links', 'exclude', 'include', 'publish', 'metadata', 'default-run', 'autolib', 'autobins',
+        'autoexamples', 'autotests', 'autobenches', 'resolver', 'im-a-teapot',
+    },
+    'lib': {
+        'name', 'path', 'test', 'doctest', 'bench', 'doc', 'plugin', 'proc-macro', 'harness', 'edition',
+        'crate-type', 'required-features',
+    },
+}
+
+# Deprecated spellings cargo still accepts, but warns about:
+_DEPRECATED_MANIFEST_KEYS = {
+    None: {'project': 'package', 'dev_dependencies': 'dev-dependencies', 'build_dependencies': 'build-dependencies'},
+    'lib': {'crate_type': 'crate-type', 'proc_macro': 'proc-macro'},
+}
+
+
+def sanitize_cargo_manifest(manifest: bytes, path: str) -> bytes:
+    """
+    Rename deprecated keys in the given cargo manifest to their current spelling and handle unknown
+    keys according to `settings.unused_manifest_keys`, so that cargo doesn't warn about them on every build.
+
+    @param manifest: The manifest to sanitize.
+    @param path: The path of the source file the manifest belongs to, used in log messages.
+    """
+    data = load_cargo_manifest(manifest)
+
+    for section, aliases in _DEPRECATED_MANIFEST_KEYS.items():
+        table = data if section is None else data.get(section)
+        if not isinstance(table, typing.MutableMapping):
+            continue
+        for old, new in aliases.items():
+            if old in table:
+                _logger.debug(f"{path}: Replacing deprecated manifest key `{_format_key(section, old)}` "
+                              f"with `{_format_key(section, new)}`")
+                # The deprecated key takes precedence, as the current one might contain the template's defaults:
+                value = table.pop(old)
+                if isinstance(value, typing.MutableMapping) and isinstance(table.get(new), typing.MutableMapping):
+                    value = _recursive_setdefault(value, table[new])
+                table[new] = value
+
+    if settings.unused_manifest_keys != 'keep':
+        for section, known_keys in _KNOWN_MANIFEST_KEYS.items():
+            table = data if section is None else data.get(section)
+            if not isinstance(table, typing.MutableMapping):
+                continue
+            for key in [k for k in table if k not in known_keys]:
+                del table[key]
+                (_logger.warning if settings.unused_manifest_keys == 'warn' else _logger.debug)(
+                    f"{path}: Removing unknown manifest key `{_format_key(section, key)}`"
+                )
+
+    return toml.dumps(data).encode()
+
+
+def _format_key(section: typing.Optional[str], key: str) -> str:
+    return f"{section}.{key}" if section else key
+
+
 def _recursive_setdefault(original: typing.MutableMapping, defaults: typing.MutableMapping):
     for k, v in defaults.items():
         if k in original and not isinstance(original[k], typing.MutableMapping):
@@ -42,3 +187,116 @@ def _recursive_setdefault(original: typing.MutableMapping, defaults: typing.Muta
         else:
             original[k] = v
     return original
+
+
+_COMMENTS_AND_LITERALS = re.compile(
+    rb'//[^\n]*'                       # line comments
+    rb'|/\*.*?\*/'                     # block comments
+    rb'|\br(#*)".*?"\1'                 # raw strings
+    rb'|b?"(?:\\.|[^"\\])*"'             # strings
+    rb"|b?'(?:\\.|[^\\'])'",            # chars
+    re.DOTALL,
+)
+
+_DELIMITERS = {b'('[0]: b')'[0], b'['[0]: b']'[0], b'{'[0]: b'}'[0]}
+
+
+def split_directive_values(value: str) -> typing.List[str]:
+    """
+    Split a comma- or whitespace-separated list of values given in a directive or template option, e.g.
+    `"a b", c,d`. Values may be quoted to include spaces, commas or other special characters.
+    """
+    lexer = shlex.shlex(value, posix=True)
+    lexer.whitespace += ','
+    lexer.whitespace_split = True
+    lexer.commenters = ''
+    return list(lexer)
+
+
+# The settings which can be overridden for a single extension using `//config: key=value` directives, and their types:
+_CONFIG_DIRECTIVE_SETTINGS = {'use_abi3': bool, 'target': str}
+
+
+def get_config_directives(directives: typing.Mapping[str, typing.List[str]], path: str) -> typing.Dict[str, typing.Any]:
+    """
+    Parse the `//config: key=value` directives overriding settings for a single extension, e.g.
+    `//config: use_abi3=true target=aarch64-unknown-linux-gnu`. If a setting is given multiple times, the last
+    value wins.
+    """
+    config = {}
+    # Spaces around the `=` are allowed, e.g. `//config: target = aarch64-unknown-linux-gnu`:
+    values = [v for d in directives.get('config', []) for v in split_directive_values(re.sub(r'\s*=\s*', '=', d))]
+    for value in values:
+        key, sep, option = value.partition('=')
+        if not sep or key not in _CONFIG_DIRECTIVE_SETTINGS:
+            raise ValueError(f"{path}: Invalid //config: option {value!r} "
+                             f"(expected one of {', '.join(k + '=...' for k in _CONFIG_DIRECTIVE_SETTINGS)})")
+        if _CONFIG_DIRECTIVE_SETTINGS[key] is bool:
+            if option.lower() not in ('true', 'false'):
+                raise ValueError(f"{path}: Invalid value for //config: {key}={option} (expected true or false)")
+            config[key] = option.lower() == 'true'
+        else:
+            config[key] = option
+    return config
+
+
+def get_env_directives(directives: typing.Mapping[str, typing.List[str]], path: str) -> typing.Dict[str, str]:
+    """
+    Parse the `//env: KEY=VALUE` directives setting environment variables for cargo when building a single extension,
+    e.g. `//env: RUSTFLAGS=-C target-cpu=native`. Each directive sets one variable, whose value is the rest of the line.
+    If a variable is given multiple times, the last value wins.
+    """
+    env = {}
+    for directive in directives.get('env', []):
+        key, sep, value = directive.partition('=')
+        if not sep or not re.fullmatch(r'[A-Za-z_][A-Za-z0-9_]*', key.strip()):
+            raise ValueError(f"{path}: Invalid //env: directive {directive!r} (expected KEY=VALUE)")
+        env[key.strip()] = value.strip()
+    return env
+
+
+# The profiles `//profile:` directives apply to, unless prefixed with one of them (e.g. `//profile: release.lto = true`):
+_PROFILE_DIRECTIVE_PROFILES = ('dev', 'release')
+
+
+def get_profile_directives(directives: typing.Mapping[str, typing.List[str]], path: str) -> typing.Dict[str, typing.Any]:
+    """
+    Parse the `//profile: key = value` directives overriding cargo's profile settings for a single extension, e.g.
+    `//profile: opt-level = 1`, into the `profile` table of its manifest. Like `//:` lines, they use TOML syntax. Keys
+    prefixed with `dev.` or `release.` only apply to that profile, all others apply to both.
+    """
+    if not directives.get('profile'):
+        return {}
+    try:
+        values = toml.loads('\n'.join(directives['profile']))
+    except toml.TomlDecodeError as e:
+        raise ValueError(f"{path}: Invalid //profile: directive: {e}") from e
+
+    profiles = {profile: values.pop(profile, {}) for profile in _PROFILE_DIRECTIVE_PROFILES}
+    for profile in profiles.values():
+        _recursive_setdefault(profile, copy.deepcopy(values))
+    return {name: profile for name, profile in profiles.items() if profile}
+
+
+def strip_comments_and_literals(contents: bytes) -> bytes:
+    """
+    Replace all comments, string and char literals in the given rust source with whitespace,
+    preserving line breaks and offsets. This allows to scan the code using regular expressions
+    without matching anything within comments or strings.
+    """
+    return _COMMENTS_AND_LITERALS.sub(lambda m: re.sub(rb'[^\n]', b' ', m.group(0)), contents)
+
+
+def has_balanced_delimiters(contents: bytes) -> bool:
+    """
+    Check whether all parentheses, brackets and braces in the given rust source (which must already
+    be stripped using `strip_comments_and_literals`) are balanced.
+    """
+    stack = []
+    for c in contents:
+        if c in _DELIMITERS:
+            stack.append(_DELIMITERS[c])
+        elif c in _DELIMITERS.values():
+            if not stack or stack.pop() != c:
+                return False
+    return not stack
@@ -0,0 +1,85 @@
+import logging
+import re
+from typing import List, Optional
+
+from rustimport import settings
+from rustimport.pre_processing.base import Template, strip_comments_and_literals, has_balanced_delimiters, \
+    sanitize_cargo_manifest, load_cargo_manifest, get_lib_name
+from rustimport.pre_processing.stubs import PubFunction, find_cpython_functions
+
+_logger = logging.getLogger(__name__)
+
+
+class CPythonTemplate(Template):
+    """
+    Template for extensions using [rust-cpython](https://github.com/dgrunwald/rust-cpython). Generates the manifest
+    and, unless the source invokes `py_module_initializer!` itself, the module initializer exporting all top-level
+    `pub fn`s taking a `Python` token as their first parameter and returning a `PyResult`.
+    """
+
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+        # The code we scan for the initializer, excluding comments and string literals:
+        self.__code = strip_comments_and_literals(self.contents)
+
+    def process(self) -> Template.TemplatingResult:
+        manifest = self.__generate_manifest()
+        # Like the pyo3 template's #[pymodule], the initializer is named after the library:
+        self.__module_name = get_lib_name(load_cargo_manifest(manifest)) or self.lib_name
+        return Template.TemplatingResult(
+            cargo_manifest=manifest,
+            contents=self.__process_content(),
+            additional_cargo_args=self._get_extension_module_cargo_args(),
+        )
+
+    def __generate_manifest(self) -> bytes:
+        return sanitize_cargo_manifest(self._enable_default_feature(self._copy_manifest_with_defaults({
+            'package': {
+                'name': self.lib_name,
+                'version': '0.1.0',
+                'edition': '2021',
+            },
+            'lib': {
+                'name': self.lib_name,
+                'crate-type': ['cdylib'],
+            },
+            # Like pyo3's, rust-cpython's "extension-module" feature breaks standalone binaries like `cargo test`,
+            # so it is enabled through a default feature (see the pyo3 template):
+            'features': {
+                'default': ['extension-module'],
+                'extension-module': ['cpython/extension-module'],
+            },
+            'dependencies': {
+                'cpython': {'version': settings.cpython_version}
+            }
+        }), 'extension-module'), self.path)
+
+    def __process_content(self) -> Optional[bytes]:
+        if re.search(rb'\bpy_module_initializer!', self.__code):
+            return None  # The user wrote the initializer themselves
+        if not has_balanced_delimiters(self.__code):
+            # See the pyo3 template: leave malformed sources untouched so that cargo reports the real error.
+            _logger.warning(f"{self.path} contains unbalanced delimiters; not generating py_module_initializer!.")
+            return None
+        return self.contents + b"\n\n" + self.__generate_initializer()
+
+    def __generate_initializer(self) -> bytes:
+        return b'\n'.join([
+            b'::cpython::py_module_initializer!(' + self.__module_name.encode() + b', |py, m| {',
+            *[
+                b'  m.add(py, "' + f.name.encode() + b'", ::cpython::py_fn!(py, ' + f.name.encode() + b'('
+                + b', '.join(f'{name}: {rust_type}'.encode() for name, rust_type in f.parameters) + b')))?;'
+                for f in self.__find_exported_functions()
+            ],
+            b'  Ok(())',
+            b'});',
+        ])
+
+    def __find_exported_functions(self) -> List[PubFunction]:
+        functions = []
+        for function in find_cpython_functions(self.contents):
+            if function.incompatibility:
+                _logger.warning(f"{self.path}: Not exporting `pub fn {function.name}`, as {function.incompatibility}.")
+            else:
+                functions.append(function)
+        return functions
@@ -1,20 +1,92 @@
+import logging
 import re
 import sys
-from typing import List
+from typing import List, Optional, Tuple
 
-from rustimport.pre_processing.base import Template
+import toml
+
+from rustimport import settings
+from rustimport.pre_processing.base import Template, strip_comments_and_literals, has_balanced_delimiters, \
+    sanitize_cargo_manifest, split_directive_values, merge_cargo_manifests, get_config_directives, \
+    load_cargo_manifest, get_lib_name
+from rustimport.pre_processing.stubs import find_pub_functions, detect_pymodule_name
+
+_logger = logging.getLogger(__name__)
+
+
+# The oldest pyo3 version we can generate bindings for:
+_MIN_PYO3_VERSION = (0, 16)
+# Starting with this version, pyo3 uses the `Bound<'py, T>` API instead of GIL-bound references (`&'py T`):
+_BOUND_API_PYO3_VERSION = (0, 21)
+# Starting with this version, `PyModule::new` returns a `Bound` (and `new_bound` is deprecated):
+_BOUND_CONSTRUCTORS_PYO3_VERSION = (0, 23)
+# The newest `abi3-pyXY` feature offered by pyo3 versions, starting with the given version:
+_ABI3_PYTHON_VERSIONS = [
+    ((0, 16), (3, 10)),
+    ((0, 17), (3, 11)),
+    ((0, 20), (3, 12)),
+    ((0, 22), (3, 13)),
+]
 
 
 class PyO3Template(Template):
+    def __init__(self, *args, **kwargs):
+        super().__init__(*args, **kwargs)
+        # The code we scan for annotations, excluding comments and string literals:
+        self.__code = strip_comments_and_literals(self.contents)
+        self.__pyo3_version: Tuple[int, ...] = _MIN_PYO3_VERSION
+        self.__package_version: Optional[str] = None
+        self.__module_name = self.lib_name
+
     def process(self) -> Template.TemplatingResult:
+        manifest = self.__generate_manifest()
+        self.__pyo3_version = self.__detect_pyo3_version(manifest)
+        manifest = self.__apply_abi3(manifest)
+        # The generated #[pymodule] is named after the library (which the manifest may name differently), so that
+        # its init function is always the one Python looks for in the library:
+        self.__module_name = get_lib_name(load_cargo_manifest(manifest)) or self.lib_name
+        # Exposed as the module's `__version__` (unless inherited from a workspace, i.e. not a string):
+        if isinstance(version := toml.loads(manifest.decode()).get('package', {}).get('version'), str):
+            self.__package_version = version
+
         return Template.TemplatingResult(
-            cargo_manifest=self.__generate_manifest(),
+            cargo_manifest=manifest,
             contents=self.__process_content(),
             additional_cargo_args=self.__get_cargo_args(),
         )
 
+    def __detect_pyo3_version(self, manifest: bytes) -> Tuple[int, ...]:
+        """
+        Determine the pyo3 version used by the extension from its (final) manifest, which determines
+        the API to use in the generated code.
+        """
+        dependency = toml.loads(manifest.decode()).get('dependencies', {}).get('pyo3')
+        requirement = dependency.get('version') if isinstance(dependency, dict) else dependency
+        if not requirement:
+            # e.g. a git or path dependency; assume it's recent:
+            _logger.warning(f"{self.path}: Couldn't determine the pyo3 version, assuming the latest supported API. "
+                            f"Specify the `version` of the pyo3 dependency to avoid this.")
+            return _BOUND_CONSTRUCTORS_PYO3_VERSION
+
+        # We only look at the lower bound of the requirement, e.g. "0.21" in ">=0.21, <0.23":
+        if not (m := re.search(r'(\d+)\.(\d+)', requirement)):
+            raise ValueError(f"{self.path}: Invalid pyo3 version requirement: {requirement}")
+        return int(m.group(1)), int(m.group(2))
+
+    @property
+    def __uses_bound_api(self) -> bool:
+        return self.__pyo3_version >= _BOUND_API_PYO3_VERSION
+
     def __generate_manifest(self) -> bytes:
-        return self._copy_manifest_with_defaults({
+        manifest_mode = self.options.get('manifest', 'auto')
+        if manifest_mode == 'manual':
+            # The user supplies the complete manifest, we only generate the #[pymodule]:
+            return self.__apply_panic_strategy(self.cargo_manifest)
+        elif manifest_mode != 'auto':
+            raise ValueError(f"Invalid value for the pyo3 template's `manifest` option: {manifest_mode} "
+                             f"(expected 'auto' or 'manual')")
+
+        manifest = self._enable_default_feature(self._copy_manifest_with_defaults({
             'package': {
                 'name': self.lib_name,
                 'version': '0.1.0',
@@ -24,47 +96,267 @@ class PyO3Template(Template):
                 'name': self.lib_name,
                 'crate-type': ['cdylib'],
             },
+            # pyo3's "extension-module" feature disables linking to libpython, which is required for
+            # extensions, but breaks standalone binaries like `cargo test`. Thus, we enable it through a
+            # default feature, which can be disabled using `cargo test --no-default-features`. It is kept
+            # if the user specifies their own default features:
+            'features': {
+                'default': ['extension-module'],
+                'extension-module': ['pyo3/extension-module'],
+            },
             'dependencies': {
-                'pyo3': {'version': '0.16.2', 'features': ['extension-module']}
+                'pyo3': {'version': settings.pyo3_version}
             }
+        }), 'extension-module')
+        return self.__apply_panic_strategy(sanitize_cargo_manifest(manifest, self.path))
+
+    def __apply_panic_strategy(self, manifest: bytes) -> bytes:
+        """
+        Apply the template's `panic` option: With `panic=abort`, a panic terminates the process (after the panic
+        hook reported it) instead of unwinding into Python as a `PanicException`.
+        """
+        strategy = self.options.get('panic', 'unwind')
+        if strategy not in ('unwind', 'abort'):
+            raise ValueError(f"Invalid value for the pyo3 template's `panic` option: {strategy} "
+                             f"(expected 'unwind' or 'abort')")
+        if strategy == 'unwind':
+            return manifest
+        return merge_cargo_manifests(manifest, {
+            'profile': {'dev': {'panic': 'abort'}, 'release': {'panic': 'abort'}},
         })
 
-    def __process_content(self) -> bytes:
-        if not re.search(rb'#\[pymodule]\s*(?:\w\s+)*?fn\s+([\w0-9]+)', self.contents):
-            # If the file doesn't contain the "pymodule" macro, we generate it automatically
+    def __apply_abi3(self, manifest: bytes) -> bytes:
+        """
+        Enable pyo3's `abi3-pyXY` feature if the extension is built for the stable ABI (see `settings.use_abi3`),
+        targeting the running interpreter's version or the newest one supported by the used pyo3 version.
+        """
+        if not get_config_directives(self.directives, self.path).get('use_abi3', settings.use_abi3):
+            return manifest
+
+        python_version = sys.version_info[:2]
+        for pyo3_version, max_python_version in _ABI3_PYTHON_VERSIONS:
+            if self.__pyo3_version >= pyo3_version:
+                python_version = min(sys.version_info[:2], max_python_version)
+        feature = f'abi3-py{python_version[0]}{python_version[1]}'
+
+        parsed = load_cargo_manifest(manifest)
+        dependency = parsed.get('dependencies', {}).get('pyo3')
+        if dependency is None:
+            raise ValueError(f"{self.path}: Building for the stable ABI (use_abi3) requires a pyo3 dependency")
+        if isinstance(dependency, str):
+            dependency = {'version': dependency}
+        features = dependency.setdefault('features', [])
+        if not any(f.startswith('abi3') for f in features):
+            features.append(feature)
+        parsed['dependencies']['pyo3'] = dependency
+        return toml.dumps(parsed).encode()
+
+    def __process_content(self) -> Optional[bytes]:
+        auto_bindings = self.__get_auto_bindings()
+        if auto_bindings is not None:
+            # The user overrides the detection below, e.g. if it misfires on a complex file:
+            return self.contents + b"\n\n" + self.__generate_pymodule() if auto_bindings else None
+
+        if not has_balanced_delimiters(self.__code):
+            # The source is malformed, so scanning it is unreliable and the generated bindings might
+            # cause confusing follow-up errors. Leave it untouched so that cargo reports the real error.
+            _logger.warning(f"{self.path} contains unbalanced delimiters; not generating #[pymodule].")
+            return None
+        if detect_pymodule_name(self.contents) is None:
+            # If the file doesn't contain the "pymodule" macro (as a function or declarative module), we generate
+            # it automatically
             return self.contents + b"\n\n" + self.__generate_pymodule()
 
+    def __get_auto_bindings(self) -> Optional[bool]:
+        """
+        Whether the `//auto-bindings: on|off` directive forces (or disables) generating the #[pymodule], or `None`
+        to detect whether the source defines one itself. If given multiple times, the last directive wins.
+        """
+        values = [v.strip().lower() for v in self.directives.get('auto-bindings', []) if v.strip()]
+        if not values:
+            return None
+        if values[-1] not in ('on', 'off'):
+            raise ValueError(f"{self.path}: Invalid value for //auto-bindings: {values[-1]} (expected on or off)")
+        return values[-1] == 'on'
+
     def __generate_pymodule(self) -> bytes:
         # A rather rudimentary implementation of generating PyO3 the "pymodule" macro's contents
-        functions = re.finditer(rb'#\[pyfunction]\s*(?:\w\s+)*?fn\s+([\w0-9]+)', self.contents, re.MULTILINE)
-        structs = re.finditer(rb'#\[pyclass]\s*(?:\w\s+)*?(?:struct|enum)\s+([\w0-9]+)', self.contents, re.MULTILINE)
+        if self.__pyo3_version < _MIN_PYO3_VERSION:
+            raise ValueError(
+                f"{self.path}: pyo3 {'.'.join(map(str, self.__pyo3_version))} is not supported by the pyo3 "
+                f"template (requires at least {'.'.join(map(str, _MIN_PYO3_VERSION))}). Upgrade pyo3 or write "
+                f"the #[pymodule] yourself."
+            )
+
+        functions = self.__find_exported_functions()
+        pub_function_wrappers = self.__generate_pub_function_wrappers() if settings.auto_export_pub else []
+        functions.extend(name for name, _ in pub_function_wrappers)
+        structs = re.finditer(rb'#\[pyclass]\s*(?:[\w()]+\s+)*?(?:struct|enum)\s+([\w0-9]+)', self.__code, re.MULTILINE)
+        lazy_submodules = [s.encode() for s in self.directives.get('lazy-submodule', []) if s]
+        init_statements = [s.encode() for s in self.directives.get('module-init', []) if s]
+        panic_hook = self.__generate_panic_hook()
+        if panic_hook:
+            init_statements.insert(0, b'rustimport_install_panic_hook();')
+        if self.__package_version:
+            # Unlike `m.add`, `setattr` doesn't add the name to the module's `__all__`:
+            init_statements.insert(0, b'm.setattr("__version__", "' + self.__package_version.encode() + b'")?;')
+
+        if self.__uses_bound_api:
+            signature = [
+                b'fn ' + self.__module_name.encode() + b"(m: &Bound<'_, PyModule>) -> PyResult<()> {",
+                b'  let _py = m.py();',
+            ]
+        else:
+            signature = [b'fn ' + self.__module_name.encode() + b'(_py: Python, m: &PyModule) -> PyResult<()> {']
 
         res = [
             b'#[pymodule]',
-            b'fn ' + self.lib_name.encode() + b'(_py: Python, m: &PyModule) -> PyResult<()> {',
+            *signature,
             *[
-                b'  m.add_function(wrap_pyfunction!(' + func.group(1) + b', m)?)?;'
+                b'  m.add_function(wrap_pyfunction!(' + func + b', m)?)?;'
                 for func in functions
             ],
             *[
                 b'  m.add_class::<' + struct.group(1) + b'>()?;'
                 for struct in structs
             ],
+            *([b'  m.add("__getattr__", wrap_pyfunction!(rustimport_lazy_getattr, m)?)?;'] if lazy_submodules else []),
+            *[b'  ' + s for s in init_statements],
             b'  Ok(())',
             b'}'
         ]
 
+        for _, wrapper in pub_function_wrappers:
+            res.extend([b'', wrapper])
+        if lazy_submodules:
+            res.extend([b'', self.__generate_lazy_getattr(lazy_submodules)])
+        # Crate attributes might also be given using `//prelude:` directives:
+        code = b'\n'.join([*(p.encode() for p in self.directives.get('prelude', [])), self.__code])
+        if re.search(rb'#!\[no_std]', code) and not re.search(rb'\bextern\s+crate\s+std\b', code):
+            # pyo3 depends on std anyway, so `#![no_std]` only removes std's prelude. The generated code refers
+            # to std items using absolute paths, which requires the crate to be linked explicitly:
+            res.extend([b'', b'extern crate std;'])
+        if panic_hook:
+            res.extend([b'', panic_hook])
+
         return b'\n'.join(res)
 
+    def __generate_panic_hook(self) -> Optional[bytes]:
+        """
+        Generate a panic hook customizing how panics are reported, as configured by the `//panic-message:`
+        directive, e.g. `//panic-message: prefix="[mymod] " location=false backtrace=always`:
+
+        - `prefix`: Text to prepend to the panic message (default: none)
+        - `location`: Whether to include the panic's location in the Rust source (default: true)
+        - `backtrace`: `auto` to capture a backtrace if enabled by `RUST_BACKTRACE` (the default), `always` or `never`
+
+        The report is written to stderr. The `PanicException` raised in Python carries the panic's message as is.
+        """
+        values = [v for directive in self.directives.get('panic-message', []) for v in split_directive_values(directive)]
+        if not values:
+            return None
+
+        options = {'prefix': '', 'location': 'true', 'backtrace': 'auto'}
+        for value in values:
+            key, sep, option = value.partition('=')
+            if not sep or key not in options:
+                raise ValueError(f"{self.path}: Invalid //panic-message: option {value!r} "
+                                 f"(expected one of {', '.join(k + '=...' for k in options)})")
+            options[key] = option
+        if options['location'] not in ('true', 'false'):
+            raise ValueError(f"{self.path}: Invalid value for //panic-message: location={options['location']} "
+                             f"(expected true or false)")
+        if options['backtrace'] not in ('auto', 'always', 'never'):
+            raise ValueError(f"{self.path}: Invalid value for //panic-message: backtrace={options['backtrace']} "
+                             f"(expected auto, always or never)")
+
+        prefix = options['prefix'].replace('\\', '\\\\').replace('"', '\\"').encode()
+        backtrace = {
+            'auto': b'    ::std::eprintln!("{}", ::std::backtrace::Backtrace::capture());',
+            'always': b'    ::std::eprintln!("{}", ::std::backtrace::Backtrace::force_capture());',
+            'never': None,
+        }[options['backtrace']]
+
+        return b'\n'.join(line for line in [
+            b'fn rustimport_install_panic_hook() {',
+            b'  ::std::panic::set_hook(::std::boxed::Box::new(|info| {',
+            b'    let payload = info.payload();',
+            b'    let message = payload.downcast_ref::<&str>().map(|s| ::std::string::String::from(*s))',
+            b'      .or_else(|| payload.downcast_ref::<::std::string::String>().cloned())',
+            b'      .unwrap_or_else(|| ::std::string::String::from("Box<dyn Any>"));',
+            b'    match info.location() {' if options['location'] == 'true' else None,
+            b'      Some(l) => ::std::eprintln!("' + prefix + b'{} (at {}:{}:{})", message, l.file(), l.line(), l.column()),'
+            if options['location'] == 'true' else None,
+            b'      None => ::std::eprintln!("' + prefix + b'{}", message),' if options['location'] == 'true' else None,
+            b'    }' if options['location'] == 'true' else None,
+            b'    ::std::eprintln!("' + prefix + b'{}", message);' if options['location'] == 'false' else None,
+            backtrace,
+            b'  }));',
+            b'}',
+        ] if line is not None)
+
+    def __find_exported_functions(self) -> List[bytes]:
+        functions = []
+        for m in re.finditer(rb'#\[pyfunction]\s*((?:[\w()]+\s+)*?)fn\s+([\w0-9]+)', self.__code, re.MULTILINE):
+            if settings.export_visibility == 'pub_only' and not re.match(rb'pub\s', m.group(1)):
+                _logger.debug(f"Not exporting non-public function {m.group(2).decode()} "
+                              f"(export_visibility = 'pub_only')")
+                continue
+            functions.append(m.group(2))
+        return functions
+
+    def __generate_pub_function_wrappers(self) -> List[Tuple[bytes, bytes]]:
+        """
+        Generate a `#[pyfunction]` wrapping each top-level `pub fn` which isn't exported explicitly, if pyo3
+        can convert its signature (see `settings.auto_export_pub`). Returns `(wrapper name, wrapper code)` tuples.
+        """
+        wrappers = []
+        for function in find_pub_functions(self.contents):
+            if function.incompatibility:
+                _logger.warning(f"{self.path}: Not exporting `pub fn {function.name}` automatically, as "
+                                f"{function.incompatibility}. Write a #[pyfunction] wrapping it to export it.")
+                continue
+            wrapper_name = b'rustimport_export_' + function.name.encode()
+            parameters = b', '.join(f'{n}: {t}'.encode() for n, t in function.parameters)
+            arguments = b', '.join(n.encode() for n, _ in function.parameters)
+            wrappers.append((wrapper_name, b'\n'.join([
+                b'#[pyfunction]',
+                b'#[pyo3(name = "' + function.name.encode() + b'")]',
+                b'fn ' + wrapper_name + b'(' + parameters + b') -> ' + function.return_type.encode() + b' {',
+                b'  ' + function.name.encode() + b'(' + arguments + b')',
+                b'}',
+            ])))
+        return wrappers
+
+    def __generate_lazy_getattr(self, submodules: List[bytes]) -> bytes:
+        # Generates a module-level `__getattr__` (PEP 562), which is only invoked by Python if normal
+        # attribute lookup fails. On first access, the submodule is created and initialized by calling
+        # the user's function of the same name, and then cached as a regular attribute on the parent.
+        if self.__uses_bound_api:
+            new_module = b'PyModule::new' if self.__pyo3_version >= _BOUND_CONSTRUCTORS_PYO3_VERSION \
+                else b'PyModule::new_bound'
+            signature = b"fn rustimport_lazy_getattr(module: &Bound<'_, PyModule>, name: &str) -> PyResult<Py<PyAny>> {"
+            init = lambda s: b'let sub = ' + new_module + b'(py, "' + s + b'")?; ' + s + b'(py, &sub)?; sub'
+            result = b'  module.setattr(name, &submodule)?;\n  Ok(submodule.into_any().unbind())'
+        else:
+            signature = b'fn rustimport_lazy_getattr(module: &PyModule, name: &str) -> PyResult<PyObject> {'
+            init = lambda s: b'let sub = PyModule::new(py, "' + s + b'")?; ' + s + b'(py, sub)?; sub'
+            result = b'  module.setattr(name, submodule)?;\n  Ok(submodule.into())'
+
+        return b'\n'.join([
+            b'#[pyfunction]',
+            b'#[pyo3(pass_module)]',
+            signature,
+            b'  let py = module.py();',
+            b'  let submodule = match name {',
+            *[b'    "' + s + b'" => { ' + init(s) + b' }' for s in submodules],
+            b'    _ => return Err(pyo3::exceptions::PyAttributeError::new_err(',
+            b'      ::std::format!("module \'{}\' has no attribute \'{}\'", module.name()?, name)',
+            b'    )),',
+            b'  };',
+            result,
+            b'}',
+        ])
+
     def __get_cargo_args(self) -> List[str]:
-        args = []
-        if sys.platform == "darwin":
-            # On macOS, because the extension-module feature disables linking to
-            # libpython, some additional linker arguments need to be set.
-            # See more: https://pyo3.rs/master/building_and_distribution.html#macos
-            args.extend([
-                "--",
-                "-C", "link-arg=-undefined",
-                "-C", "link-arg=dynamic_lookup",
-            ])
-        return args
+        return self._get_extension_module_cargo_args()
@@ -0,0 +1,495 @@
+"""
+Mapping of rust types to python type hints, as used for generating type stubs.
+
+The builtin mapping can be extended per source file using `//typemap: RustType => PythonType`
+directives, e.g. to map a custom `#[pyclass]` or a type converted by a custom `FromPyObject` implementation.
+"""
+
+import os
+import re
+from dataclasses import dataclass, field
+from typing import Dict, List, Iterable, Optional, Tuple
+
+from rustimport import settings
+from rustimport.pre_processing.base import strip_comments_and_literals
+
+# Maps rust types to their python counterparts. Generic types are mapped using their
+# argument types, i.e. "list" + `Vec<i32>` becomes "list[int]".
+_SIMPLE_TYPES: Dict[str, str] = {
+    **{t: 'int' for t in ('i8', 'i16', 'i32', 'i64', 'i128', 'isize', 'u8', 'u16', 'u32', 'u64', 'u128', 'usize')},
+    'f32': 'float',
+    'f64': 'float',
+    'bool': 'bool',
+    'char': 'str',
+    'str': 'str',
+    'String': 'str',
+    'PathBuf': 'str',
+    'Path': 'str',
+    'PyString': 'str',
+    'PyBytes': 'bytes',
+    'PyList': 'list',
+    'PyDict': 'dict',
+    'PyTuple': 'tuple',
+    'PySet': 'set',
+    'PyAny': 'Any',
+    'PyObject': 'Any',
+}
+
+_GENERIC_TYPES: Dict[str, str] = {
+    'Vec': 'list',
+    'VecDeque': 'list',
+    'HashMap': 'dict',
+    'BTreeMap': 'dict',
+    'IndexMap': 'dict',
+    'HashSet': 'set',
+    'BTreeSet': 'set',
+}
+
+# Wrappers which are transparent to Python, i.e. `PyResult<T>` or `PyRef<T>` are just `T`:
+_TRANSPARENT_TYPES = {'PyResult', 'Result', 'Py', 'PyRef', 'PyRefMut', 'PyCell', 'Box', 'Rc', 'Arc', 'Cow'}
+
+
+def parse_typemap(directives: Iterable[str]) -> Dict[str, str]:
+    """
+    Parse `//typemap: RustType => PythonType` directives into a dictionary.
+    """
+    typemap = {}
+    for directive in directives:
+        rust_type, sep, python_type = directive.partition('=>')
+        if sep and rust_type.strip() and python_type.strip():
+            typemap[rust_type.strip()] = python_type.strip()
+    return typemap
+
+
+def map_rust_type(rust_type: str, typemap: Optional[Dict[str, str]] = None,
+                  known_classes: Iterable[str] = ()) -> str:
+    """
+    Map the given rust type (as written in the source code) to a python type hint.
+
+    Types explicitly specified in `typemap` take precedence; custom types map to their name if they
+    are a known `#[pyclass]` and to `Any` otherwise.
+    """
+    typemap = typemap or {}
+    known_classes = set(known_classes)
+
+    def map_type(t: str) -> str:
+        t = re.sub(r"&\s*(?:'\w+\s*)?(?:mut\s+)?|\bdyn\s+|\bimpl\s+", '', t).strip()
+
+        if t in typemap:
+            return typemap[t]
+        if t in ('()', ''):
+            return 'None'
+        if t.startswith('(') and t.endswith(')'):
+            return f"tuple[{', '.join(map_type(a) for a in _split_args(t[1:-1]))}]"
+        if t.startswith('[') and t.endswith(']'):
+            element = _split_args(t[1:-1], separator=';')[0]
+            return 'bytes' if element == 'u8' else f'list[{map_type(element)}]'
+
+        name, _, args = t.partition('<')
+        name = name.strip().split('::')[-1]
+        args = _split_args(args[:-1]) if args else []
+        args = [a for a in args if not a.startswith("'")]  # Skip lifetimes
+
+        if name in typemap:
+            return typemap[name]
+        if name in _SIMPLE_TYPES:
+            return _SIMPLE_TYPES[name]
+        if name in _TRANSPARENT_TYPES and args:
+            return map_type(args[0])
+        if name == 'Option' and args:
+            return f'Optional[{map_type(args[0])}]'
+        if name in ('Vec', 'VecDeque') and args == ['u8']:
+            return 'bytes'
+        if name in _GENERIC_TYPES:
+            return f"{_GENERIC_TYPES[name]}[{', '.join(map_type(a) for a in args)}]" if args \
+                else _GENERIC_TYPES[name]
+        if name in known_classes:
+            return name
+        return 'Any'
+
+    return map_type(rust_type)
+
+
+def _split_args(s: str, separator: str = ',') -> List[str]:
+    """Split a list of generic arguments at top-level separators."""
+    args, depth, current = [], 0, ''
+    for c in s:
+        if c in '<([':
+            depth += 1
+        elif c in '>)]':
+            depth -= 1
+        if c == separator and depth == 0:
+            args.append(current.strip())
+            current = ''
+        else:
+            current += c
+    if current.strip():
+        args.append(current.strip())
+    return args
+
+
+def write_py_typed_marker(directory: str):
+    """
+    Create the [PEP 561](https://peps.python.org/pep-0561/) `py.typed` marker in `directory`, which tells
+    type checkers to use the stubs shipped with a package.
+
+    The marker must be placed in the package's root directory, i.e. next to its `__init__.py`. For a native
+    module within a package (`pkg/accel.so` with `pkg/accel.pyi`), this is the directory of the module and
+    its stub. A single top-level module can't be marked as typed this way; distribute it as a stub-only
+    package instead (a `<module>-stubs` directory containing `__init__.pyi` and `py.typed`).
+    """
+    path = os.path.join(directory, 'py.typed')
+    if not os.path.exists(path):
+        with open(path, 'w'):
+            pass
+
+
+
+@dataclass
+class FunctionSignature:
+    """The python signature of an exported function or method."""
+    name: str
+    parameters: List[str]
+    """The parameters as they appear in the stub, e.g. `["a: int", "b: Optional[str] = ..."]`."""
+    returns: str
+    decorators: List[str] = field(default_factory=list)
+    signature_known: bool = True
+    """False if only the name is known, e.g. when introspecting an extension which doesn't provide signatures."""
+
+    def to_stub(self, indent: str = '') -> str:
+        return ''.join(f'{indent}@{d}\n' for d in self.decorators) \
+            + f"{indent}def {self.name}({', '.join(self.parameters)}) -> {self.returns}: ..."
+
+
+@dataclass
+class ClassSignature:
+    """An exported class (i.e. a `#[pyclass]`) and its `#[pymethods]`."""
+    name: str
+    methods: List[FunctionSignature] = field(default_factory=list)
+
+    def to_stub(self) -> str:
+        body = '\n'.join(m.to_stub(indent='    ') for m in self.methods) or '    ...'
+        return f'class {self.name}:\n{body}'
+
+
+@dataclass
+class ModuleApi:
+    """The python API of an extension module, as detected from its source by `detect_exports`."""
+    functions: List[FunctionSignature] = field(default_factory=list)
+    classes: List[ClassSignature] = field(default_factory=list)
+    submodules: List[str] = field(default_factory=list)
+
+    @property
+    def names(self) -> List[str]:
+        """The names of all public attributes of the module."""
+        return [c.name for c in self.classes] + [f.name for f in self.functions] + self.submodules
+
+    def to_stub(self) -> str:
+        """Render a `.pyi` type stub for the module."""
+        parts = [
+            'from types import ModuleType\nfrom typing import Any, Optional',
+            *(f'{s}: ModuleType' for s in self.submodules),
+            *(c.to_stub() for c in self.classes),
+            *(f.to_stub() for f in self.functions),
+        ]
+        return '\n\n'.join(parts) + '\n'
+
+
+# An attribute, e.g. `#[pyo3(name = "foo")]`:
+_ATTRIBUTE = rb'#\[[^\]]*(?:\[[^\]]*][^\]]*)*]'
+_VISIBILITY = rb'pub(?:\s*\([^)]*\))?\s+'
+_FN_QUALIFIERS = rb'(?:(?:const|async|unsafe|extern(?:\s+"[^"]*")?)\s+)*'
+
+
+def detect_exports(contents: bytes, directives: Optional[Dict[str, List[str]]] = None) -> ModuleApi:
+    """
+    Detect the python API of an extension from the `#[pyfunction]`s, `#[pyclass]`es and `#[pymethods]`
+    in its source. This is a heuristic, but reliable for the common cases.
+
+    @param contents: The rust source.
+    @param directives: The named directives from the source's header, used for `//typemap:` and
+                       `//lazy-submodule:`.
+    """
+    directives = directives or {}
+    typemap = parse_typemap(directives.get('typemap', []))
+    code = strip_comments_and_literals(contents)
+
+    # If the pyo3 template generates the #[pymodule] (see `//auto-bindings:`), it honors `settings.export_visibility`:
+    auto_bindings = [v.strip().lower() for v in directives.get('auto-bindings', []) if v.strip()]
+    generated = auto_bindings[-1] == 'on' if auto_bindings else detect_pymodule_name(contents) is None
+    pub_only = settings.export_visibility == 'pub_only' and generated
+
+    classes = []
+    rust_class_names: Dict[str, str] = {}
+    for m in re.finditer(rb'#\[pyclass\b[^\]]*]((?:\s*' + _ATTRIBUTE + rb')*)\s*(?:' + _VISIBILITY + rb')?(?:struct|enum)\s+(\w+)', code):
+        classes.append(ClassSignature(name=_get_python_name(contents[m.start():m.start(2)]) or m.group(2).decode()))
+        rust_class_names[m.group(2).decode()] = classes[-1].name
+    typemap = {**rust_class_names, **typemap}
+
+    functions = []
+    if settings.auto_export_pub and generated:
+        for f in find_pub_functions(contents):
+            if not f.incompatibility:
+                functions.append(_parse_function(contents, code, f.start, f.name_end, typemap, rust_class_names))
+    for m in re.finditer(rb'#\[pyfunction\b[^\]]*]((?:\s*' + _ATTRIBUTE + rb')*)\s*(' + _VISIBILITY + rb')?' + _FN_QUALIFIERS + rb'fn\s+(\w+)', code):
+        if pub_only and not m.group(2):
+            continue
+        if signature := _parse_function(contents, code, m.start(), m.end(), typemap, rust_class_names):
+            functions.append(signature)
+
+    for m in re.finditer(rb'#\[pymethods]\s*impl(?:\s*<[^>]*>)?\s+(\w+)[^{]*\{', code):
+        class_name = rust_class_names.get(m.group(1).decode())
+        cls = next((c for c in classes if c.name == class_name), None)
+        if cls is None:
+            continue
+        body = code[m.end():_find_closing(code, m.end() - 1)]
+        for method in re.finditer(rb'((?:' + _ATTRIBUTE + rb'\s*)*)(?:' + _VISIBILITY + rb')?' + _FN_QUALIFIERS + rb'fn\s+(\w+)', body):
+            if _nesting_depth(body[:method.start()]) > 0:
+                continue  # Nested within a method's body
+            if signature := _parse_function(contents, code, m.end() + method.start(), m.end() + method.end(),
+                                            typemap, rust_class_names, class_name=m.group(1).decode()):
+                cls.methods.append(signature)
+
+    return ModuleApi(
+        functions=functions,
+        classes=classes,
+        submodules=[s for s in directives.get('lazy-submodule', []) if s],
+    )
+
+
+def detect_pymodule_name(contents: bytes) -> Optional[str]:
+    """
+    Detect the name of the top-level `#[pymodule]` in the given rust source, i.e. the name Python knows the module
+    by, for both the function form (`fn name(...)`) and the declarative form (`mod name { ... }`), taking a
+    `#[pyo3(name = "...")]` or `#[pymodule(name = "...")]` attribute into account. Returns `None` if the source
+    doesn't contain a `#[pymodule]`.
+    """
+    code = strip_comments_and_literals(contents)
+    pattern = rb'#\[pymodule\b[^\]]*]((?:\s*' + _ATTRIBUTE + rb')*)\s*(?:' + _VISIBILITY + rb')?(?:fn|mod)\s+(\w+)'
+    for m in re.finditer(pattern, code):
+        if _nesting_depth(code[:m.start()]) > 0:
+            continue  # A submodule of a declarative module
+        return _get_python_name(contents[m.start():m.start(2)]) or m.group(2).decode()
+    return None
+
+
+@dataclass
+class PubFunction:
+    """A top-level `pub fn` without `#[pyfunction]`, which `settings.auto_export_pub` exports to Python."""
+    name: str
+    parameters: List[Tuple[str, str]]
+    """The names and rust types of the parameters."""
+    return_type: str
+    start: int
+    """The offset of the function (including its attributes) in the source."""
+    name_end: int
+    incompatibility: Optional[str] = None
+    """Why the function can't be exported, or `None` if it can."""
+
+
+# Owned types pyo3 converts from and to python objects, and which are thus exported by `auto_export_pub`:
+_AUTO_EXPORT_TYPES = {
+    'i8', 'i16', 'i32', 'i64', 'i128', 'isize', 'u8', 'u16', 'u32', 'u64', 'u128', 'usize',
+    'f32', 'f64', 'bool', 'char', 'String',
+}
+_AUTO_EXPORT_GENERIC_TYPES = {'Option', 'Vec', 'HashMap', 'BTreeMap', 'HashSet', 'BTreeSet'}
+
+
+def find_pub_functions(contents: bytes) -> List[PubFunction]:
+    """
+    Find the top-level `pub fn`s in the given rust source which aren't annotated with `#[pyfunction]`, and check
+    whether their signatures can be converted by pyo3. This is a best-effort heuristic for `settings.auto_export_pub`:
+    Only parameters and return values of basic types (numbers, `bool`, `char`, `String`, `&str`, `&[u8]`, and `Option`,
+    `Vec`, maps, sets and tuples of these) are supported, and `#[pyclass]`es as return values.
+    """
+    code = strip_comments_and_literals(contents)
+    classes = {m.group(1).decode() for m in re.finditer(
+        rb'#\[pyclass\b[^\]]*](?:\s*' + _ATTRIBUTE + rb')*\s*(?:' + _VISIBILITY + rb')?(?:struct|enum)\s+(\w+)', code)}
+
+    functions = []
+    pattern = rb'((?:' + _ATTRIBUTE + rb'\s*)*)\bpub\s+(' + _FN_QUALIFIERS + rb')fn\s+(\w+)'
+    for m in re.finditer(pattern, code):
+        if _nesting_depth(code[:m.start()]) > 0 or re.search(rb'#\[(?:pyfunction|pymodule|cfg\(test\))', m.group(1)):
+            continue  # e.g. a method, an explicitly exported function or the #[pymodule]
+        function = PubFunction(name=m.group(3).decode(), parameters=[], return_type='()', start=m.start(),
+                               name_end=m.end())
+        functions.append(function)
+        if m.group(2).strip():
+            function.incompatibility = f"it is {m.group(2).decode().strip()}"
+            continue
+        if code[m.end():].lstrip().startswith(b'<'):
+            function.incompatibility = "it is generic"
+            continue
+
+        params_start = code.index(b'(', m.end())
+        params_end = _find_closing(code, params_start)
+        header_end = min([i for i in (code.find(b'{', params_end), code.find(b';', params_end)) if i >= 0] or [len(code)])
+        return_type, *where = re.split(rb'\bwhere\b', code[params_end + 1:header_end])
+        if where:
+            function.incompatibility = "it is generic"
+            continue
+        return_type = return_type.strip()
+        function.return_type = return_type[2:].strip().decode() if return_type.startswith(b'->') else '()'
+
+        for param in _split_args(code[params_start + 1:params_end].decode()):
+            param_pattern, _, rust_type = param.partition(':')
+            param_name = re.sub(r'^mut\s+', '', param_pattern.strip())
+            if not re.fullmatch(r'\w+', param_name):
+                function.incompatibility = f"its parameter `{param_pattern.strip()}` isn't a plain name"
+                break
+            if not _is_auto_exportable(rust_type, classes, argument=True):
+                function.incompatibility = f"pyo3 can't convert its parameter `{param_name}: {rust_type.strip()}`"
+                break
+            function.parameters.append((param_name, rust_type.strip()))
+        else:
+            if not _is_auto_exportable(function.return_type, classes, argument=False):
+                function.incompatibility = f"pyo3 can't convert its return type `{function.return_type}`"
+    return functions
+
+
+def find_cpython_functions(contents: bytes) -> List[PubFunction]:
+    """
+    Find the top-level `pub fn`s in the given rust source which follow rust-cpython's calling convention, i.e. take a
+    `Python` token as their first parameter and return a `PyResult`. The returned parameters exclude the token.
+    """
+    code = strip_comments_and_literals(contents)
+    functions = []
+    pattern = rb'((?:' + _ATTRIBUTE + rb'\s*)*)\bpub\s+fn\s+(\w+)\s*\('
+    for m in re.finditer(pattern, code):
+        if _nesting_depth(code[:m.start()]) > 0 or re.search(rb'#\[cfg\(test\)', m.group(1)):
+            continue
+        params_end = _find_closing(code, m.end() - 1)
+        header_end = min([i for i in (code.find(b'{', params_end), code.find(b';', params_end)) if i >= 0] or [len(code)])
+        return_type = code[params_end + 1:header_end].strip()
+        return_type = return_type[2:].strip().decode() if return_type.startswith(b'->') else '()'
+        params = [p.partition(':') for p in _split_args(code[m.end():params_end].decode())]
+        if not params or not re.fullmatch(r"(?:(?:::)?cpython::)?Python(?:\s*<\s*'\w+\s*>)?", params[0][2].strip()):
+            continue
+        function = PubFunction(name=m.group(2).decode(), return_type=return_type, start=m.start(), name_end=m.end() - 1,
+                               parameters=[(re.sub(r'^mut\s+', '', n.strip()), t.strip()) for n, _, t in params[1:]])
+        if not re.match(r'(?:(?:::)?cpython::)?PyResult\b', return_type):
+            function.incompatibility = "it doesn't return a `PyResult`"
+        elif any(not re.fullmatch(r'\w+', n) for n, _ in function.parameters):
+            function.incompatibility = "its parameters aren't plain names"
+        functions.append(function)
+    return functions
+
+
+def _is_auto_exportable(rust_type: str, classes: Iterable[str], argument: bool) -> bool:
+    t = rust_type.strip()
+    if t.startswith('&'):
+        return argument and re.sub(r"^&\s*(?:'\w+\s*)?", '', t).strip() in ('str', '[u8]')
+    if t == '()':
+        return not argument
+    if t.startswith('(') and t.endswith(')'):
+        return all(_is_auto_exportable(a, classes, argument=False) for a in _split_args(t[1:-1]))
+
+    name, _, args = t.partition('<')
+    name = name.strip()
+    args = _split_args(args[:-1]) if args else []
+    if name in _AUTO_EXPORT_TYPES and not args:
+        return True
+    if name in _AUTO_EXPORT_GENERIC_TYPES and args:
+        return all(_is_auto_exportable(a, classes, argument=False) for a in args)
+    if name == 'PyResult' and len(args) == 1:
+        return not argument and _is_auto_exportable(args[0], classes, argument=False)
+    # Taking a #[pyclass] by value would require it to implement `Clone`:
+    return not argument and name in classes and not args
+
+
+def _find_closing(code: bytes, start: int) -> int:
+    """Find the index of the delimiter closing the one at `start` (or the end of `code` if it isn't closed)."""
+    depth = 0
+    for i in range(start, len(code)):
+        if code[i] in b'([{':
+            depth += 1
+        elif code[i] in b')]}':
+            depth -= 1
+            if depth == 0:
+                return i
+    return len(code)
+
+
+def _nesting_depth(code: bytes) -> int:
+    return sum(code.count(c) for c in (b'(', b'[', b'{')) - sum(code.count(c) for c in (b')', b']', b'}'))
+
+
+def _get_python_name(attributes: bytes) -> Optional[str]:
+    """Get the name given by e.g. `#[pyo3(name = "foo")]` or `#[pyclass(name = "Foo")]`, if any."""
+    if m := re.search(rb'\bname\s*=\s*"([^"]*)"', attributes):
+        return m.group(1).decode()
+    return None
+
+
+def _parse_function(contents: bytes, code: bytes, start: int, name_end: int, typemap: Dict[str, str],
+                    rust_class_names: Dict[str, str], class_name: Optional[str] = None) -> Optional[FunctionSignature]:
+    """
+    Parse the function whose attributes start at `start` and whose name ends at `name_end` into its python
+    signature, or return `None` if it isn't exported (e.g. a `#[setter]`).
+    """
+    attributes = contents[start:name_end]
+    rust_name = re.search(rb'fn\s+(\w+)$', code[start:name_end]).group(1).decode()
+    name = _get_python_name(attributes) or rust_name
+
+    params_start = code.index(b'(', name_end)
+    params_end = _find_closing(code, params_start)
+    header_end = min([i for i in (code.find(b'{', params_end), code.find(b';', params_end)) if i >= 0] or [len(code)])
+    return_type = re.split(rb'\bwhere\b', code[params_end + 1:header_end])[0].strip()
+    return_type = return_type[2:].strip().decode() if return_type.startswith(b'->') else '()'
+
+    known_classes = set(rust_class_names.values())
+    if class_name:
+        typemap = {**typemap, 'Self': rust_class_names.get(class_name, 'Any')}
+    python_type = lambda t: map_rust_type(t, typemap, known_classes)
+
+    decorators = []
+    receiver = []
+    if class_name:
+        if re.search(rb'#\[setter\b', attributes):
+            return None
+        if re.search(rb'#\[new]', attributes):
+            name, return_type = '__init__', '()'
+            receiver = ['self']
+        elif re.search(rb'#\[staticmethod]', attributes):
+            decorators.append('staticmethod')
+        elif re.search(rb'#\[classmethod]', attributes):
+            decorators.append('classmethod')
+            receiver = ['cls']
+        else:
+            if m := re.search(rb'#\[getter(?:\((\w+)\))?]', attributes):
+                decorators.append('property')
+                name = m.group(1).decode() if m.group(1) else re.sub(r'^get_', '', name)
+            receiver = ['self']
+
+    parameters: List[Tuple[str, str]] = []
+    for param in _split_args(code[params_start + 1:params_end].decode()):
+        pattern, _, rust_type = param.partition(':')
+        pattern = re.sub(r'^(?:ref\s+)?(?:mut\s+)?', '', pattern.strip())
+        if not rust_type or re.search(r'\bPython\b', rust_type) or pattern in ('self', 'slf'):
+            continue  # `self`, the GIL token and receivers like `slf: PyRef<Self>`
+        if decorators == ['classmethod'] and not parameters and re.search(r'\bPyType\b', rust_type):
+            continue  # The class, for `#[classmethod]`s
+        parameters.append((pattern, python_type(rust_type)))
+
+    # Defaults and variadic arguments given by e.g. `#[pyo3(signature = (a, b=1, *args))]`:
+    rendered = [f'{n}: {t}' for n, t in parameters]
+    if m := re.search(rb'\bsignature\s*=\s*\(', attributes):
+        types = dict(parameters)
+        close = _find_closing(attributes, m.end() - 1)
+        rendered = []
+        for item in _split_args(attributes[m.end():close].decode()):
+            item_name, has_default, _ = item.partition('=')
+            item_name = item_name.strip()
+            if item_name == '*':
+                rendered.append('*')
+            elif item_name.startswith('*'):
+                rendered.append(item_name)
+            else:
+                rendered.append(f"{item_name}: {types.get(item_name, 'Any')}" + (' = ...' if has_default else ''))
+
+    return FunctionSignature(
+        name=name,
+        parameters=receiver + rendered,
+        returns=python_type(return_type),
+        decorators=decorators,
+    )
@@ -1,7 +1,15 @@
+import contextlib
+import contextvars
 import hashlib
+import logging
 import os
+import shlex
+import sys
 import tempfile
-from typing import Optional
+import types
+from typing import Optional, List, Dict, Any, Union, Callable, Set
+
+import toml
 
 force_rebuild: bool = os.getenv("RUSTIMPORT_FORCE_REBUILD", "0").lower() in ("true", "yes", "1")
 """
@@ -37,6 +45,65 @@ The cargo executable path to use.
 Env var: `RUSTIMPORT_CARGO_EXECUTABLE=<cargo path>`
 """
 
+host_triple: Optional[str] = os.getenv("RUSTIMPORT_HOST_TRIPLE")
+"""
+The target triple of the host (e.g. `x86_64-unknown-linux-musl`), used to locate the built library within
+cargo's target directory (`target/<triple>/<profile>/`) in case cargo doesn't report its path. By default,
+it's detected from the `host:` line of `rustc -vV`'s output; only set this if the detection is wrong in
+your environment.
+
+Env var: `RUSTIMPORT_HOST_TRIPLE=<triple>`
+"""
+
+target: Optional[str] = os.getenv("RUSTIMPORT_TARGET")
+"""
+The target triple to cross-compile extensions for (passed to cargo as `--target`), e.g. `aarch64-unknown-linux-gnu`,
+or `None` to build for the host. Cross-compiled extensions are named without the interpreter's platform tag (e.g.
+`mymod.so`, or `mymod.pyd` for Windows targets), which CPython on the target loads as well. They can't be imported
+locally, so use `rustimport.build`, `build_filepath` or `python -m rustimport build --target <triple>` to build
+them. Changing the target causes extensions to be rebuilt.
+
+This requires the target's standard library (`rustup target add <triple>`) and a linker for it (e.g. configured
+using `CARGO_TARGET_<TRIPLE>_LINKER`). Can be overridden for a single extension using `//config: target=<triple>`.
+
+Env var: `RUSTIMPORT_TARGET=aarch64-unknown-linux-gnu`
+"""
+
+pyodide: bool = os.getenv("RUSTIMPORT_PYODIDE", "0").lower() in ("true", "yes", "1")
+"""
+Whether to build extensions for [Pyodide](https://pyodide.org), i.e. for the `wasm32-unknown-emscripten` target,
+instead of the host. The built extensions are named like Pyodide expects them, for the Python version of the
+building interpreter (which must match Pyodide's), e.g. `mymod.cpython-311-wasm32-emscripten.so`. They can't be
+imported locally, so use `rustimport.build`, `build_filepath` or `python -m rustimport build --pyodide` to build them.
+
+This requires the Emscripten SDK (`emcc` must be on the `PATH`), a nightly toolchain and the target, e.g.
+`rustup target add wasm32-unknown-emscripten --toolchain nightly`.
+
+Env var: `RUSTIMPORT_PYODIDE=true`
+"""
+
+summary: bool = os.getenv("RUSTIMPORT_SUMMARY", "0").lower() in ("true", "yes", "1")
+"""
+Whether to log a one-line summary (at INFO level) after each import, telling whether the extension was up-to-date or
+had to be built and how long that took, e.g. `rustimport: mymod loaded (cache hit, 0.01s)` or
+`rustimport: mymod built in release (12.3s)`. It is printed to stderr unless the application has configured logging
+(e.g. via `rustimport.configure_logging()`). The command line's `--quiet` flag disables it.
+
+Env var: `RUSTIMPORT_SUMMARY=true`
+"""
+
+verbosity: int = int(os.getenv("RUSTIMPORT_VERBOSITY", "2"))
+"""
+How much of cargo's output to print while building extensions:
+
+- `0`: Only errors. Cargo's output is held back and only printed if the build fails.
+- `1`: Like `0`, but rustimport prints a message when an extension starts building and when it's done. They are
+  logged at INFO level instead if the application has configured logging (e.g. via `rustimport.configure_logging()`).
+- `2`: Cargo's full output, printed live while building (the default).
+
+Env var: `RUSTIMPORT_VERBOSITY=1`
+"""
+
 rtld_flags: int = 0
 """
 It can be useful to set rtld_flags to RTLD_GLOBAL. This allows extensions that are
@@ -45,6 +112,63 @@ in a project where several interdependent extensions are loaded but it's
 undesirable to combine the multiple extensions into a single extension.
 """
 
+load_mode: str = os.getenv('RUSTIMPORT_LOAD_MODE', 'python_ext')
+"""
+How to load compiled libraries. Either `"python_ext"` to import them as Python extension modules, or
+`"ctypes"` to load them as plain shared libraries using `ctypes.CDLL`, which allows calling functions
+exported with the C ABI (i.e. `#[no_mangle] pub extern "C" fn ...`) from libraries that don't implement
+the Python extension protocol.
+
+Env var: `RUSTIMPORT_LOAD_MODE=ctypes`
+"""
+
+module_names: Dict[str, str] = {}
+"""
+Maps python import names to the names of the rust files or crates implementing them, for modules whose import name
+differs from their file or crate name. For example, with `{"fast_math": "project_fast_math_core"}`, the import hook
+builds `project_fast_math_core.rs` (or the crate `project_fast_math_core/`) for `import fast_math`, naming the
+extension (and, using the pyo3 template, its `lib.name` and generated `#[pymodule]`) `fast_math`.
+"""
+
+max_concurrent_builds: int = int(os.getenv('RUSTIMPORT_MAX_CONCURRENT_BUILDS', '0'))
+"""
+The maximum number of cargo builds to run at the same time within this process, e.g. when many extensions
+are imported from different threads during startup. Further builds wait until a running one finishes. Since
+every cargo build itself uses all available cores, limiting this prevents thrashing or running out of memory
+on constrained machines. `0` means no limit.
+
+Env var: `RUSTIMPORT_MAX_CONCURRENT_BUILDS=2`
+"""
+
+build_lock_timeout: float = float(os.getenv('RUSTIMPORT_BUILD_LOCK_TIMEOUT', '600'))
+"""
+The maximum time (in seconds) to wait for another process building the same extension, e.g. when several
+pytest-xdist workers or preforked gunicorn workers import it at the same time. Builds of an extension are
+serialized using a lock file in `cache_dir`, and once the other build finished, the extension is only rebuilt
+if it's still outdated. If the timeout is exceeded, a `BuildLockTimeoutError` is raised.
+
+Env var: `RUSTIMPORT_BUILD_LOCK_TIMEOUT=<seconds>`
+"""
+
+suppress_build_script_output: bool = os.getenv("RUSTIMPORT_SUPPRESS_BUILD_SCRIPT_OUTPUT", "0").lower() in ("true", "yes", "1")
+"""
+Whether to hold back the output of build scripts (their `cargo:warning=` lines, e.g. of chatty `-sys` crates) while
+building, and only print it if the build fails. Compiler errors and warnings are printed as usual.
+
+Env var: `RUSTIMPORT_SUPPRESS_BUILD_SCRIPT_OUTPUT=true`
+"""
+
+smoke_test_import: bool = os.getenv("RUSTIMPORT_SMOKE_TEST_IMPORT", "1").lower() in ("true", "yes", "1")
+"""
+Whether to import each extension in a subprocess right after building it. If that fails, e.g. due to a
+missing symbol, an ABI mismatch or a panic in the module's initialization, a `BuildError` describing the
+failure is raised, instead of a possibly confusing error (or crash) when the extension is loaded later on.
+
+Disable this to save the time of starting an additional interpreter on each build.
+
+Env var: `RUSTIMPORT_SMOKE_TEST_IMPORT=false`
+"""
+
 cache_dir: str = os.getenv('RUSTIMPORT_CACHE_DIR') or os.path.join(tempfile.gettempdir(), 'rustimport')
 """
 A directory to store temporary files. By default this directory will be created
@@ -58,6 +182,181 @@ directory does not exist, it'll be created automatically.
 Env var: `RUSTIMPORT_CACHE_DIR=<directory path>`
 """
 
+artifact_cache: bool = os.getenv("RUSTIMPORT_ARTIFACT_CACHE", "0").lower() in ("true", "yes", "1")
+"""
+Whether to keep a copy of each built extension in an "artifacts" directory within `cache_dir`, keyed by the
+extension's input fingerprint (see `Importable.input_fingerprint()`: a hash of its sources, tracked files, build
+environment, target, toolchain versions and so on). An extension whose inputs match a cached artifact is copied from
+the cache instead of being built again.
+
+As the fingerprint doesn't depend on file modification times or the location of the project, a `cache_dir`
+persisted between CI runs, or shared between machines with identical toolchains, lets them reuse each other's builds.
+
+Env var: `RUSTIMPORT_ARTIFACT_CACHE=true`
+"""
+
+artifact_cache_max_size: int = int(os.getenv('RUSTIMPORT_ARTIFACT_CACHE_MAX_SIZE', str(2 * 1024 ** 3)))
+"""
+The maximum total size (in bytes) of the artifact cache (see `artifact_cache`). Whenever an artifact is added, the
+least recently used ones are removed until the cache fits. Defaults to 2 GiB; `0` disables the limit.
+
+Env var: `RUSTIMPORT_ARTIFACT_CACHE_MAX_SIZE=<bytes>`
+"""
+
+min_free_space: int = int(os.getenv('RUSTIMPORT_MIN_FREE_SPACE', str(512 * 1024 ** 2)))
+"""
+The minimum free space (in bytes) the file systems of the build directory (within `cache_dir`) and of
+`cargo_target_dir` must have before a build is started. If there is less space, or a build fails because
+the disk ran full, an `InsufficientDiskSpaceError` is raised instead of a confusing compiler or linker error.
+Defaults to 512 MiB; `0` disables the check.
+
+Env var: `RUSTIMPORT_MIN_FREE_SPACE=<bytes>`
+"""
+
+cargo_target_dir: Optional[str] = os.getenv('RUSTIMPORT_CARGO_TARGET_DIR')
+"""
+A persistent target directory to be shared by all cargo builds (passed to cargo as `CARGO_TARGET_DIR`).
+By default, each extension is built with its own target directory within `cache_dir`.
+
+Sharing a target directory allows dependencies to be compiled only once for all extensions and to be
+reused across separate `python -m rustimport build` invocations, which is especially useful when
+building many extensions from shell scripts or Makefiles.
+
+Env var: `RUSTIMPORT_CARGO_TARGET_DIR=<directory path>`
+"""
+
+copy_ignore: Optional[Callable[[str, List[str]], Set[str]]] = None
+"""
+A function telling which files not to copy when a crate is copied to its build directory, in addition to the default
+rules: `.git`, `.cargo` and `target` directories (at any depth) as well as the files matching the crate's `.gitignore`
+are skipped. Like the `ignore` argument of `shutil.copytree`, it receives the path of a directory within the crate and
+the names of its entries, and returns the names to skip, e.g.:
+
+```python
+rustimport.settings.copy_ignore = lambda directory, names: {n for n in names if n.endswith('.parquet')}
+```
+"""
+
+copy_workspace_subset: bool = os.getenv("RUSTIMPORT_COPY_WORKSPACE_SUBSET", "0").lower() in ("true", "yes", "1")
+"""
+When building a member of a workspace (see the `package` argument of `imp_from_path`), only copy the member and the
+crates it depends on by path (along with the files at the workspace's top level, like its `Cargo.toml` and
+`Cargo.lock`) to the build directory, instead of the whole workspace. This speeds up builds in large workspaces,
+whose other members are irrelevant for the extension. Doesn't apply to workspaces whose root is a package itself.
+
+Env var: `RUSTIMPORT_COPY_WORKSPACE_SUBSET=true|false`
+"""
+
+cc: Optional[str] = os.getenv('RUSTIMPORT_CC')
+"""
+The C compiler to use for building C/C++ code in dependencies (exported to cargo as `CC`).
+
+This flows through to all [`cc`](https://crates.io/crates/cc)-based build scripts, which are used by many
+`-sys` crates, and allows to pin a specific compiler for reproducibility or cross-builds. Changing this
+setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_CC=<compiler path>`
+"""
+
+cxx: Optional[str] = os.getenv('RUSTIMPORT_CXX')
+"""
+The C++ compiler to use for building C/C++ code in dependencies (exported to cargo as `CXX`). See `cc`.
+
+Env var: `RUSTIMPORT_CXX=<compiler path>`
+"""
+
+cflags: Optional[str] = os.getenv('RUSTIMPORT_CFLAGS')
+"""
+Flags to pass to the C compiler when building C/C++ code in dependencies (exported to cargo as `CFLAGS`).
+See `cc`.
+
+Env var: `RUSTIMPORT_CFLAGS=<flags>`
+"""
+
+include_paths: List[str] = list(filter(None, os.getenv('RUSTIMPORT_INCLUDE_PATHS', '').split(os.pathsep)))
+"""
+Additional directories to search for C/C++ headers when building C/C++ code in dependencies, e.g. of
+libraries installed to a custom prefix. These are passed as `-I` flags in `CFLAGS`, `CXXFLAGS` and `CPPFLAGS`
+(in addition to `cflags` or the `CFLAGS` environment variable). Changing this setting causes extensions to
+be rebuilt.
+
+Note that crates which locate system libraries using `pkg-config` (like `openssl-sys`) take their include and
+link paths from the library's `.pc` file instead – for those, add its directory to `PKG_CONFIG_PATH`.
+
+Env var: `RUSTIMPORT_INCLUDE_PATHS=/opt/openssl/include:/opt/other/include` (separated by `os.pathsep`)
+"""
+
+link_search_paths: List[str] = list(filter(None, os.getenv('RUSTIMPORT_LINK_SEARCH_PATHS', '').split(os.pathsep)))
+"""
+Additional directories to search for native libraries when linking, e.g. of libraries installed to a custom
+prefix. These are passed to rustc as `-L` flags (in addition to the `RUSTFLAGS` environment variable).
+Changing this setting causes extensions to be rebuilt. See `include_paths` for crates using `pkg-config`.
+
+This only affects linking: if the library is linked dynamically, the dynamic loader still needs to find it
+when the extension is imported (e.g. using `LD_LIBRARY_PATH`).
+
+Env var: `RUSTIMPORT_LINK_SEARCH_PATHS=/opt/openssl/lib:/opt/other/lib` (separated by `os.pathsep`)
+"""
+
+link_args: Dict[str, List[str]] = \
+    {sys.platform: shlex.split(os.environ['RUSTIMPORT_LINK_ARGS'])} if os.getenv('RUSTIMPORT_LINK_ARGS') else {}
+"""
+Additional arguments for the linker, keyed by the platform (as given by `sys.platform`, e.g. `"linux"`, `"darwin"`
+or `"win32"`) they apply to. Each argument is passed to rustc as `-C link-arg=...` (in addition to the `RUSTFLAGS`
+environment variable). This is useful for extensions which don't get pyo3's automatic link configuration, e.g.
+`{"darwin": ["-undefined", "dynamic_lookup"]}` for rust-cpython modules. Changing this setting causes extensions
+to be rebuilt.
+
+Env var: `RUSTIMPORT_LINK_ARGS="-undefined dynamic_lookup"` (for the current platform, split like a shell does)
+"""
+
+cargo_env: Dict[str, str] = {}
+"""
+Additional environment variables to set for cargo when building extensions, e.g.
+`{"RUSTFLAGS": "-C target-cpu=native", "OPENSSL_DIR": "/opt/openssl"}`. They take precedence over the inherited
+environment, and are in turn overridden by `//env: KEY=VALUE` directives of a single extension. Changing them
+causes a rebuild.
+"""
+
+reproducible: bool = os.getenv("RUSTIMPORT_REPRODUCIBLE", "0").lower() in ("true", "yes", "1")
+"""
+Whether to build deterministically, such that two builds of the same source produce byte-identical
+extensions (e.g. for binary-diffing, caching across machines or supply-chain verification).
+
+This strips machine-specific paths from the artifacts (using rustc's `--remap-path-prefix`), sets a fixed
+`SOURCE_DATE_EPOCH` and disables incremental compilation, which makes builds slower.
+
+Env var: `RUSTIMPORT_REPRODUCIBLE=true`
+"""
+
+keep_old_artifacts: int = int(os.getenv('RUSTIMPORT_KEEP_OLD_ARTIFACTS', '0'))
+"""
+The number of previous builds to retain (in the cache directory) per extension when it is rebuilt.
+This can be useful for debugging or to recover when a new build regresses. Retained builds are listed
+by `python -m rustimport list` and can be loaded using `Importable.load(checksum=...)`.
+
+Env var: `RUSTIMPORT_KEEP_OLD_ARTIFACTS=<number>`
+"""
+
+emit_compile_commands: bool = os.getenv("RUSTIMPORT_EMIT_COMPILE_COMMANDS", "0").lower() in ("true", "yes", "1")
+"""
+Whether to collect the C/C++ compiler invocations during builds into a `compile_commands.json` file in the
+build directory, for use with editors and clang tooling. This only applies to crates with build scripts
+compiling C/C++ code using the [`cc`](https://crates.io/crates/cc) crate (which many `-sys` crates do), and
+is not supported on Windows.
+
+Env var: `RUSTIMPORT_EMIT_COMPILE_COMMANDS=true`
+"""
+
+checksum_dir: Optional[str] = os.getenv('RUSTIMPORT_CHECKSUM_DIR')
+"""
+The directory to store the checksums of the sources each extension was built from in. Checksums are never
+stored next to the sources, so caching works for read-only source directories, too. By default, they are
+stored in a "checksums" directory within `cache_dir`.
+
+Env var: `RUSTIMPORT_CHECKSUM_DIR=<directory path>`
+"""
+
 checksum_hasher = hashlib.sha1
 """
 Specify the hash function to use for hashing. This function should be compatible with all the named
@@ -66,3 +365,335 @@ constructors from `hashlib` (e.g. `hashlib.md5(...)`  or `hashlib.sha256(...)`).
 By default, sha1 is used as it has the [best performance](https://github.com/SharkyRawr/python-hashlib-benchmark)
 and is [reasonably collision-proof](https://crypto.stackexchange.com/a/2584).
 """
+
+checksum_strategy: str = os.getenv('RUSTIMPORT_CHECKSUM_STRATEGY', 'content')
+"""
+The strategy used to determine whether source files have changed since the last build. One of:
+
+- `"content"`: Hash the contents of all tracked files. This is the most precise option but needs
+  to read every byte of every tracked file on each import.
+- `"mtime_size"`: First compare the paths, modification times and sizes of all tracked files, which
+  is very fast, and only hash their contents if those don't match. This speeds up importing large
+  crates considerably. Only the files whose modification time or size changed are hashed (the digests
+  of the others are kept alongside the checksum), and files which were merely touched (e.g. by a git
+  checkout or a formatter) don't cause a rebuild. Files added to or removed from the tracked ones (e.g.
+  matched by a `//d:` glob) always do.
+- `"content+mtime"`: Like `"content"`, but additionally rebuild if any file's modification time
+  changed, even if its contents didn't.
+
+Env var: `RUSTIMPORT_CHECKSUM_STRATEGY=mtime_size`
+"""
+
+export_visibility: str = os.getenv('RUSTIMPORT_EXPORT_VISIBILITY', 'all')
+"""
+Which `#[pyfunction]`s are exported automatically by templates generating the `#[pymodule]` (e.g. `pyo3`).
+Either `"all"` to export all functions regardless of their visibility, or `"pub_only"` to only export
+`pub fn`s, which allows to use Rust's visibility to distinguish public API from internal functions.
+
+Changing this setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_EXPORT_VISIBILITY=pub_only`
+"""
+
+auto_export_pub: bool = os.getenv("RUSTIMPORT_AUTO_EXPORT_PUB", "0").lower() in ("true", "yes", "1")
+"""
+Experimental: Whether templates generating the `#[pymodule]` (e.g. `pyo3`) also export all top-level `pub fn`s
+which aren't annotated with `#[pyfunction]`, for quick prototyping. This is best-effort: Only functions whose
+parameters and return values are of basic types (numbers, `bool`, `String`, `&str`, `Vec`, `Option`, ...) are
+exported, others are skipped with a warning. Explicit `#[pyfunction]` annotations always take precedence.
+
+Changing this setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_AUTO_EXPORT_PUB=true`
+"""
+
+generate_stubs: bool = os.getenv("RUSTIMPORT_GENERATE_STUBS", "0").lower() in ("true", "yes", "1")
+"""
+Whether to write a `.pyi` type stub next to each built extension (e.g. `mymod.pyi` next to `mymod.rs`), so that
+IDEs and type checkers know its API. The stub is generated from the `#[pyfunction]`, `#[pyclass]` and
+`#[pymethods]` annotations in the source; rust types which can't be mapped to python types confidently are
+annotated as `Any` (see `//typemap:` directives to extend the mapping).
+
+Env var: `RUSTIMPORT_GENERATE_STUBS=true`
+"""
+
+expose_build_info: bool = os.getenv("RUSTIMPORT_EXPOSE_BUILD_INFO", "0").lower() in ("true", "yes", "1")
+"""
+Whether to attach metadata about how an extension was built (cargo and rustc versions, feature
+resolver and enabled features) to the loaded module as `__build_info__`. This can be useful for
+audits or debugging "different result on a different machine" reports.
+
+Env var: `RUSTIMPORT_EXPOSE_BUILD_INFO=true`
+"""
+
+manifest_template: Optional[str] = os.getenv('RUSTIMPORT_MANIFEST_TEMPLATE')
+"""
+The path of a `Cargo.toml` template shared by all single-file extensions, e.g. to centralize common
+dependencies, profiles or lints. The following placeholders are replaced for each source file:
+
+- `{{name}}`: The library name, i.e. the module name.
+- `{{crate_type}}`: The crate type required for Python extensions (`cdylib`).
+- `{{source_dir}}`: The absolute path of the directory containing the source file, e.g. for path dependencies.
+
+The `//:` block of a source file takes precedence over the template, which in turn takes precedence over the
+defaults of templates like `pyo3`. Crates use their own `Cargo.toml` and ignore this setting.
+
+Env var: `RUSTIMPORT_MANIFEST_TEMPLATE=/path/to/Cargo.toml.template`
+"""
+
+unused_manifest_keys: str = os.getenv('RUSTIMPORT_UNUSED_MANIFEST_KEYS', 'warn')
+"""
+How templates (e.g. `pyo3`) handle keys in the `//:` manifest which cargo doesn't know, and which it
+would otherwise warn about on every build. Deprecated aliases (e.g. `[project]` or `crate_type`) are
+renamed to their current spelling in any case. One of:
+
+- `"warn"`: Remove unknown keys from the generated `Cargo.toml` and log a warning for each of them.
+- `"drop"`: Remove unknown keys silently.
+- `"keep"`: Pass unknown keys on to cargo unchanged.
+
+Changing this setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_UNUSED_MANIFEST_KEYS=drop`
+"""
+
+lockfile_sha256: Union[None, str, Dict[str, str]] = os.getenv('RUSTIMPORT_LOCKFILE_SHA256')
+"""
+The approved SHA-256 hash of the `Cargo.lock` to build with, either for all extensions or as a dictionary
+mapping module names to hashes. If set, the lockfile (of a crate, or the one generated for a single-file
+extension and kept in the cache directory) is verified before each build, and the build is aborted with a
+`BuildError` if its hash doesn't match. Cargo is then run with `--locked`, so that it can't modify the lockfile.
+
+This guards against unexpected changes in dependency resolution, e.g. a newly published version of a dependency.
+
+Env var: `RUSTIMPORT_LOCKFILE_SHA256=<hash>`
+"""
+
+progress_callback: Optional[Callable[[Any], None]] = None
+"""
+A function receiving progress events while extensions are built, e.g. to show a progress bar in a GUI. Each
+event is a `rustimport.compiler.BuildProgress` with the following attributes:
+
+- `phase`: `"started"`, `"compiled"` (a crate or build script has been compiled, or was up-to-date),
+  `"build_script_executed"` or `"finished"`
+- `module`: The full name of the module being built
+- `crate`: The name of the crate the event refers to, if any
+- `completed`: The number of compilation units (crates and build scripts) compiled so far
+- `total`: The estimated total number of compilation units, or `None` if unknown
+- `fresh`: Whether the unit was up-to-date (for `"compiled"` events)
+- `success`: Whether the build succeeded (for `"finished"` events)
+
+Events are parsed from cargo's JSON messages, so they are emitted regardless of whether output goes to a terminal.
+"""
+
+pyo3_version: str = os.getenv('RUSTIMPORT_PYO3_VERSION', '0.16.2')
+"""
+The version of pyo3 the `pyo3` template adds as a dependency, unless a file specifies a version itself.
+
+Changing this setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_PYO3_VERSION=0.22`
+"""
+
+cpython_version: str = os.getenv('RUSTIMPORT_CPYTHON_VERSION', '0.7.2')
+"""
+The version of rust-cpython the `cpython` template adds as a dependency, unless a file specifies a version itself.
+
+Env var: `RUSTIMPORT_CPYTHON_VERSION=0.7`
+"""
+
+use_abi3: bool = os.getenv("RUSTIMPORT_USE_ABI3", "0").lower() in ("true", "yes", "1")
+"""
+Whether to build extensions against Python's [stable ABI](https://docs.python.org/3/c-api/stable.html), so that
+one build can be loaded by all CPython versions starting with the one running the build (or the newest one the
+used pyo3 version supports, if that's older). The pyo3 template enables pyo3's `abi3-pyXY` feature and the
+extension is named with the `abi3` tag, e.g. `mymod.abi3.so`.
+
+Can be overridden for a single extension using the `//config: use_abi3=true` (or `false`) directive.
+
+Env var: `RUSTIMPORT_USE_ABI3=true`
+"""
+
+default_template: Optional[str] = os.getenv('RUSTIMPORT_DEFAULT_TEMPLATE')
+"""
+The template to use for files that opt in using just `// rustimport`, without specifying a template
+(e.g. `"pyo3"`). By default, no template is used for these files.
+
+Changing this setting causes extensions to be rebuilt.
+
+Env var: `RUSTIMPORT_DEFAULT_TEMPLATE=pyo3`
+"""
+
+config_file: Optional[str] = None
+"""
+The project-level config file the defaults of these settings have been read from (read-only).
+
+On import, rustimport looks for a `rustimport.toml` file, or a `pyproject.toml` file containing a
+`[tool.rustimport]` table, in the current working directory and its parents, and uses the first one found.
+Its keys are the names of the settings in this module, e.g.:
+
+```toml
+[tool.rustimport]
+compile_release_binaries = true
+cache_dir = ".rust-cache"  # Relative paths are relative to the config file
+pyo3_version = "0.22"
+default_template = "pyo3"
+```
+
+Settings are applied in this order, later ones taking precedence: builtin defaults, the config file,
+environment variables and finally assignments in code (e.g. `rustimport.settings.cache_dir = ...`).
+"""
+
+# The settings which can be given in a config file and the environment variables overriding them:
+_CONFIGURABLE_SETTINGS: Dict[str, Optional[str]] = {
+    'force_rebuild': 'RUSTIMPORT_FORCE_REBUILD',
+    'release_mode': 'RUSTIMPORT_RELEASE_MODE',
+    'compile_release_binaries': 'RUSTIMPORT_RELEASE_BINARIES',
+    'cargo_executable': 'RUSTIMPORT_CARGO_EXECUTABLE',
+    'host_triple': 'RUSTIMPORT_HOST_TRIPLE',
+    'target': 'RUSTIMPORT_TARGET',
+    'pyodide': 'RUSTIMPORT_PYODIDE',
+    'summary': 'RUSTIMPORT_SUMMARY',
+    'verbosity': 'RUSTIMPORT_VERBOSITY',
+    'rtld_flags': None,
+    'load_mode': 'RUSTIMPORT_LOAD_MODE',
+    'module_names': None,
+    'max_concurrent_builds': 'RUSTIMPORT_MAX_CONCURRENT_BUILDS',
+    'build_lock_timeout': 'RUSTIMPORT_BUILD_LOCK_TIMEOUT',
+    'suppress_build_script_output': 'RUSTIMPORT_SUPPRESS_BUILD_SCRIPT_OUTPUT',
+    'smoke_test_import': 'RUSTIMPORT_SMOKE_TEST_IMPORT',
+    'cache_dir': 'RUSTIMPORT_CACHE_DIR',
+    'artifact_cache': 'RUSTIMPORT_ARTIFACT_CACHE',
+    'artifact_cache_max_size': 'RUSTIMPORT_ARTIFACT_CACHE_MAX_SIZE',
+    'min_free_space': 'RUSTIMPORT_MIN_FREE_SPACE',
+    'cargo_target_dir': 'RUSTIMPORT_CARGO_TARGET_DIR',
+    'copy_workspace_subset': 'RUSTIMPORT_COPY_WORKSPACE_SUBSET',
+    'cc': 'RUSTIMPORT_CC',
+    'cxx': 'RUSTIMPORT_CXX',
+    'cflags': 'RUSTIMPORT_CFLAGS',
+    'include_paths': 'RUSTIMPORT_INCLUDE_PATHS',
+    'link_search_paths': 'RUSTIMPORT_LINK_SEARCH_PATHS',
+    'link_args': 'RUSTIMPORT_LINK_ARGS',
+    'cargo_env': None,
+    'reproducible': 'RUSTIMPORT_REPRODUCIBLE',
+    'keep_old_artifacts': 'RUSTIMPORT_KEEP_OLD_ARTIFACTS',
+    'emit_compile_commands': 'RUSTIMPORT_EMIT_COMPILE_COMMANDS',
+    'checksum_dir': 'RUSTIMPORT_CHECKSUM_DIR',
+    'checksum_strategy': 'RUSTIMPORT_CHECKSUM_STRATEGY',
+    'export_visibility': 'RUSTIMPORT_EXPORT_VISIBILITY',
+    'auto_export_pub': 'RUSTIMPORT_AUTO_EXPORT_PUB',
+    'generate_stubs': 'RUSTIMPORT_GENERATE_STUBS',
+    'expose_build_info': 'RUSTIMPORT_EXPOSE_BUILD_INFO',
+    'manifest_template': 'RUSTIMPORT_MANIFEST_TEMPLATE',
+    'unused_manifest_keys': 'RUSTIMPORT_UNUSED_MANIFEST_KEYS',
+    'lockfile_sha256': 'RUSTIMPORT_LOCKFILE_SHA256',
+    'pyo3_version': 'RUSTIMPORT_PYO3_VERSION',
+    'cpython_version': 'RUSTIMPORT_CPYTHON_VERSION',
+    'use_abi3': 'RUSTIMPORT_USE_ABI3',
+    'default_template': 'RUSTIMPORT_DEFAULT_TEMPLATE',
+}
+
+# Settings containing paths, which are resolved relative to the config file:
+_PATH_SETTINGS = {'cache_dir', 'cargo_target_dir', 'checksum_dir', 'manifest_template'}
+_LIST_OF_PATHS_SETTINGS = {'include_paths', 'link_search_paths'}
+
+
+def _find_config_file(directory: str) -> Optional[Dict[str, Any]]:
+    """Search `directory` and its parents for a config file and return its path and rustimport config."""
+    while True:
+        for name in ('rustimport.toml', 'pyproject.toml'):
+            path = os.path.join(directory, name)
+            if not os.path.isfile(path):
+                continue
+            config = toml.load(path)
+            if name == 'pyproject.toml':
+                config = config.get('tool', {}).get('rustimport')
+            if config is not None:
+                return {'path': path, 'config': config}
+
+        parent = os.path.dirname(directory)
+        if parent == directory:
+            return None
+        directory = parent
+
+
+def _apply_config_file():
+    global config_file
+
+    try:
+        found = _find_config_file(os.getcwd())
+    except (OSError, toml.TomlDecodeError) as e:
+        logging.getLogger(__name__).warning(f"Failed to read rustimport config file: {e}")
+        return
+    if found is None:
+        return
+
+    config_file = found['path']
+    base_dir = os.path.dirname(config_file)
+    for key, value in found['config'].items():
+        if key not in _CONFIGURABLE_SETTINGS:
+            logging.getLogger(__name__).warning(f"{config_file}: Unknown rustimport setting: {key}")
+            continue
+        if (env_var := _CONFIGURABLE_SETTINGS[key]) and os.getenv(env_var) is not None:
+            continue  # Environment variables take precedence
+
+        if key in _PATH_SETTINGS and isinstance(value, str):
+            value = os.path.join(base_dir, os.path.expanduser(value))
+        elif key in _LIST_OF_PATHS_SETTINGS and isinstance(value, list):
+            value = [os.path.join(base_dir, os.path.expanduser(v)) for v in value]
+        globals()[key] = value
+
+
+_apply_config_file()
+
+
+# The per-call overrides currently in effect (see `Settings`). A context variable keeps concurrent calls
+# in other threads or asyncio tasks from seeing each other's overrides:
+_overrides: contextvars.ContextVar[Dict[str, Any]] = contextvars.ContextVar('rustimport_settings_overrides', default={})
+
+
+class Settings:
+    """
+    A set of settings used for a single call only, overriding the global ones, e.g.:
+
+    ```python
+    rustimport.imp_from_path("mymod.rs", settings=Settings(compile_release_binaries=True))
+    ```
+
+    Settings which are not given fall back to the global ones. Accepts the names of the settings in this
+    module, e.g. `cache_dir` or `compile_release_binaries`.
+    """
+
+    def __init__(self, **overrides: Any):
+        unknown = [key for key in overrides if key not in _CONFIGURABLE_SETTINGS and key not in _CODE_ONLY_SETTINGS]
+        if unknown:
+            raise TypeError(f"Unknown rustimport setting{'s' if len(unknown) > 1 else ''}: {', '.join(unknown)}")
+        self.overrides = overrides
+
+    def __repr__(self):
+        return f"Settings({', '.join(f'{key}={value!r}' for key, value in self.overrides.items())})"
+
+    @contextlib.contextmanager
+    def applied(self):
+        """Apply these settings for the current thread (or asyncio task) until the block is left."""
+        token = _overrides.set({**_overrides.get(), **self.overrides})
+        try:
+            yield self
+        finally:
+            _overrides.reset(token)
+
+
+# Settings which can't be given in a config file, but can be overridden per call:
+_CODE_ONLY_SETTINGS = {'checksum_hasher', 'progress_callback', 'copy_ignore'}
+
+
+class _SettingsModule(types.ModuleType):
+    """Resolves the per-call overrides of `Settings.applied()` before the global settings."""
+
+    def __getattribute__(self, name):
+        overrides = _overrides.get()
+        if name in overrides:
+            return overrides[name]
+        return super().__getattribute__(name)
+
+
+sys.modules[__name__].__class__ = _SettingsModule
@@ -0,0 +1,80 @@
+import logging
+import os
+import time
+from typing import List, Dict, Tuple
+
+from rustimport import BuildError
+from rustimport.checksum import collect_files
+from rustimport.importable import Importable
+
+_logger = logging.getLogger(__name__)
+
+# A snapshot of the files tracked by an importable: their modification times and sizes by path.
+_Snapshot = Dict[str, Tuple[float, int]]
+
+
+def watch(importables: List[Importable], release: bool = False, force_rebuild: bool = False,
+          interval: float = 0.5, debounce: float = 0.3):
+    """
+    Build the given importables (if needed) and rebuild them whenever one of their tracked files (see
+    `Importable.dependencies`) changes, until interrupted using Ctrl+C. A failing build is reported and the
+    importable is watched further, so that fixing the error triggers another build.
+
+    @param interval: The number of seconds between checks for changes.
+    @param debounce: The number of seconds the files must stay unchanged before a build is started, so that
+                     rapid successive saves only cause a single build.
+    """
+    snapshots = {}
+    for importable in importables:
+        if force_rebuild or importable.needs_rebuild(release=release):
+            _build(importable, release)
+        snapshots[importable.path] = _snapshot(importable)
+
+    _logger.info(f"Watching {len(importables)} extension{'s' if len(importables) != 1 else ''} for changes "
+                 f"(press Ctrl+C to stop)…")
+    try:
+        while True:
+            time.sleep(interval)
+            for importable in importables:
+                snapshot = _snapshot(importable)
+                if snapshot == snapshots[importable.path]:
+                    continue
+                while (settled := _wait_and_snapshot(importable, debounce)) != snapshot:
+                    snapshot = settled
+                snapshots[importable.path] = snapshot
+                _build(importable, release)
+    except KeyboardInterrupt:
+        pass
+
+
+def _wait_and_snapshot(importable: Importable, delay: float) -> _Snapshot:
+    time.sleep(delay)
+    return _snapshot(importable)
+
+
+def _snapshot(importable: Importable) -> _Snapshot:
+    try:
+        files = collect_files(importable.dependencies)
+    except Exception as e:
+        # E.g. an invalid header while the file is being edited; the main source is still worth watching:
+        _logger.debug(f"Failed to collect the dependencies of {importable.path}: {e}")
+        files = [importable.path]
+
+    snapshot = {}
+    for file in files:
+        try:
+            stat = os.stat(file)
+        except OSError:
+            continue  # Deleted in the meantime
+        snapshot[file] = (stat.st_mtime, stat.st_size)
+    return snapshot
+
+
+def _build(importable: Importable, release: bool):
+    try:
+        importable.build(release=release)
+    except Exception as e:
+        # Build errors have already been logged along with the compiler's messages:
+        if not isinstance(e, BuildError):
+            _logger.error(f"Failed to build {importable.name}: {e}")
+        _logger.info(f"Waiting for changes to {importable.name}…")
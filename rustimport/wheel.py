@@ -0,0 +1,132 @@
+import base64
+import hashlib
+import logging
+import os
+import re
+import sys
+import sysconfig
+import zipfile
+from typing import List, Optional, Tuple
+
+from rustimport.importable import Importable, _get_rustimport_version
+from rustimport.pre_processing.base import load_cargo_manifest
+
+_logger = logging.getLogger(__name__)
+
+# Versions following PEP 440's canonical public version scheme (e.g. "1.2.3", "1.0.0rc1" or "2.0.post1"), which is
+# what installers require in wheel names:
+_PEP440_VERSION = re.compile(r'(\d+!)?\d+(\.\d+)*((a|b|rc)\d+)?(\.post\d+)?(\.dev\d+)?')
+
+
+def build_wheel(importable: Importable, output_dir: str) -> str:
+    """
+    Package the (already built) extension into a wheel in `output_dir`, tagged for the running interpreter and
+    platform, or for all CPython versions starting with the targeted one if it was built for the stable ABI. The
+    distribution is named after the module and versioned by its manifest's `package.version`.
+
+    @return: The path of the wheel.
+    @raises: `ValueError` if the manifest doesn't specify a valid version, or if the extension is cross-compiled.
+    """
+    if importable.cargo_target:
+        raise ValueError(f"Can't build a wheel of {importable.path} for {importable.cargo_target}, as rustimport "
+                         f"only tags wheels for the running interpreter's platform.")
+
+    version = get_wheel_version(importable)
+    preprocessed = importable._preprocess()
+    package = load_cargo_manifest(preprocessed.source_manifest).get('package', {})
+    abi3_version = _get_abi3_python_version(preprocessed.cargo_manifest) if importable.uses_abi3 else None
+    python_tag, abi_tag = _get_interpreter_tags(abi3_version)
+    platform_tag = re.sub(r'[-.]', '_', sysconfig.get_platform())
+
+    distribution = re.sub(r'[-_.]+', '_', importable.name)
+    dist_info = f'{distribution}-{version}.dist-info'
+    files: List[Tuple[str, bytes]] = []
+    with open(importable.extension_path, 'rb') as f:
+        files.append((os.path.basename(importable.extension_path), f.read()))
+    if os.path.isfile(importable.stub_path):
+        with open(importable.stub_path, 'rb') as f:
+            files.append((os.path.basename(importable.stub_path), f.read()))
+    files.append((f'{dist_info}/METADATA', _render_metadata(importable.name, version, package, abi3_version)))
+    files.append((f'{dist_info}/WHEEL', (
+        f'Wheel-Version: 1.0\n'
+        f'Generator: rustimport ({_get_rustimport_version()})\n'
+        f'Root-Is-Purelib: false\n'
+        f'Tag: {python_tag}-{abi_tag}-{platform_tag}\n'
+    ).encode()))
+
+    record = ''.join(f'{name},sha256={_urlsafe_digest(data)},{len(data)}\n' for name, data in files)
+    files.append((f'{dist_info}/RECORD', (record + f'{dist_info}/RECORD,,\n').encode()))
+
+    os.makedirs(output_dir, exist_ok=True)
+    wheel_path = os.path.join(output_dir, f'{distribution}-{version}-{python_tag}-{abi_tag}-{platform_tag}.whl')
+    with zipfile.ZipFile(wheel_path + '.tmp', 'w', compression=zipfile.ZIP_DEFLATED) as wheel:
+        for name, data in files:
+            wheel.writestr(name, data)
+    os.replace(wheel_path + '.tmp', wheel_path)
+    _logger.info(f"Built {wheel_path}")
+    return wheel_path
+
+
+def get_wheel_version(importable: Importable) -> str:
+    """
+    The version of the extension's wheel, i.e. the `package.version` given by its manifest (as opposed to the
+    template's default) in PEP 440's format.
+
+    @raises: `ValueError` if the manifest doesn't specify a version or it isn't compatible with PEP 440.
+    """
+    preprocessed = importable._preprocess()
+    version = load_cargo_manifest(preprocessed.source_manifest).get('package', {}).get('version')
+    if not isinstance(version, str):
+        raise ValueError(
+            f"{importable.path} doesn't specify a version, which is required to build a wheel. Add it to the "
+            f"manifest, e.g. using `//: [package]` and `//: version = \"0.1.0\"` lines in the source's header "
+            f"(or `version = \"0.1.0\"` in the `[package]` section of the crate's Cargo.toml)."
+        )
+    return _to_pep440_version(version, importable.path)
+
+
+def _to_pep440_version(version: str, path: str) -> str:
+    # Cargo uses semver, whose common pre-releases (e.g. "1.0.0-rc.1") have a PEP 440 equivalent ("1.0.0rc1"):
+    converted = re.sub(r'-(alpha|beta|rc)\.?(\d+)$', lambda m: {'alpha': 'a', 'beta': 'b', 'rc': 'rc'}[m.group(1)]
+                       + m.group(2), version.split('+')[0])
+    if not _PEP440_VERSION.fullmatch(converted):
+        raise ValueError(f"{path}: The version {version} can't be used for a wheel, as it isn't compatible with "
+                         f"PEP 440. Use a plain version like \"1.2.3\" (or a pre-release like \"1.2.3-rc.1\").")
+    return converted
+
+
+def _get_abi3_python_version(cargo_manifest: bytes) -> Optional[Tuple[int, int]]:
+    """The minimum Python version given by pyo3's `abi3-pyXY` feature in the final manifest, if any."""
+    dependency = load_cargo_manifest(cargo_manifest).get('dependencies', {}).get('pyo3')
+    features = dependency.get('features', []) if isinstance(dependency, dict) else []
+    for feature in features:
+        if m := re.fullmatch(r'abi3-py(\d)(\d+)', feature):
+            return int(m.group(1)), int(m.group(2))
+    return None
+
+
+def _get_interpreter_tags(abi3_version: Optional[Tuple[int, int]]) -> Tuple[str, str]:
+    """The python and ABI tags of the wheel (see PEP 425), e.g. `("cp311", "cp311")` or `("cp38", "abi3")`."""
+    if abi3_version:
+        return f'cp{abi3_version[0]}{abi3_version[1]}', 'abi3'
+    version = f'{sys.version_info.major}{sys.version_info.minor}'
+    if sys.implementation.name == 'pypy':
+        # PyPy's ABI tag is given by its SOABI, e.g. `pypy310-pp73`:
+        soabi = sysconfig.get_config_var('SOABI') or f'pypy{version}'
+        return f'pp{version}', '_'.join(soabi.split('-')[:2])
+    return f'cp{version}', f"cp{version}{getattr(sys, 'abiflags', '')}"
+
+
+def _render_metadata(name: str, version: str, package: dict, abi3_version: Optional[Tuple[int, int]]) -> bytes:
+    lines = ['Metadata-Version: 2.1', f'Name: {name}', f'Version: {version}']
+    if isinstance(package.get('description'), str):
+        lines.append(f"Summary: {package['description']}")
+    if isinstance(package.get('license'), str):
+        lines.append(f"License: {package['license']}")
+    if abi3_version:
+        lines.append(f'Requires-Python: >={abi3_version[0]}.{abi3_version[1]}')
+    return ('\n'.join(lines) + '\n').encode()
+
+
+def _urlsafe_digest(data: bytes) -> str:
+    return base64.urlsafe_b64encode(hashlib.sha256(data).digest()).rstrip(b'=').decode()
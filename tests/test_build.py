@@ -0,0 +1,398 @@
+import contextlib
+import contextvars
+import ctypes
+import hashlib
+import io
+import json
+import os
+import shutil
+import sys
+import tempfile
+import threading
+import time
+import unittest
+from typing import List
+from unittest import mock
+
+import rustimport
+from rustimport import BuildError, CircularDependencyError
+from rustimport.compiler import Cargo
+from rustimport.importable import _copy_source_to_build_dir
+from tests.utils import TestCase, CARGO_TARGET_DIR, pyo3_source, requires_cargo
+
+
+def _file_hash(path: str) -> str:
+    with open(path, 'rb') as f:
+        return hashlib.sha256(f.read()).hexdigest()
+
+
+@requires_cargo
+class TestReproducibleBuild(TestCase):
+    # Another target directory, so that the differing flags don't cause rebuilds of the other tests' dependencies:
+    settings = {'reproducible': True, 'cargo_target_dir': os.path.join(CARGO_TARGET_DIR, 'reproducible')}
+
+    def test_builds_in_different_locations_are_identical(self):
+        source = pyo3_source('''
+            #[pyfunction]
+            fn location() -> &'static str { file!() }
+        ''')
+        first = rustimport.get_importable(self.write('first/repro.rs', source))
+        second = rustimport.get_importable(self.write('second/repro.rs', source))
+        first.build()
+        second.build()
+        self.assertEqual(_file_hash(first.extension_path), _file_hash(second.extension_path))
+
+
+@requires_cargo
+class TestCircularDependencies(TestCase):
+    def test_two_node_cycle_is_reported(self):
+        first = rustimport.get_importable(self.write('first.rs', pyo3_source('')))
+        second = rustimport.get_importable(self.write('second.rs', pyo3_source('')))
+
+        def cargo_build(cargo, crate_path, **kwargs):
+            # Building either extension requires building the other one:
+            (second if crate_path.startswith(first.build_tempdir) else first).build()
+
+        with mock.patch.object(Cargo, 'build', autospec=True, side_effect=cargo_build):
+            with self.assertRaises(CircularDependencyError) as cm:
+                first.build()
+        self.assertEqual(cm.exception.cycle, [first.path, second.path, first.path])
+        self.assertIn(f"{first.path} -> {second.path} -> {first.path}", str(cm.exception))
+
+
+@requires_cargo
+class TestPrelude(TestCase):
+    def test_crate_attribute_from_prelude_applies(self):
+        source = '''
+            // rustimport:pyo3
+            //prelude: #![{level}(unused_variables)]
+            use pyo3::prelude::*;
+
+            #[pyfunction]
+            fn unused() {{ let x = 1; }}
+        '''
+        rustimport.get_importable(self.write('allowed.rs', source.format(level='allow'))).build()
+        with self.assertRaises(BuildError), self.assertLogs('rustimport', 'ERROR') as logs:
+            rustimport.get_importable(self.write('denied.rs', source.format(level='deny'))).build()
+        self.assertIn('unused variable: `x`', '\n'.join(logs.output))
+
+
+@requires_cargo
+class TestUnbalancedDelimiters(TestCase):
+    def test_error_points_at_the_source(self):
+        path = self.write('unbalanced.rs', pyo3_source('''
+            #[pyfunction]
+            fn one() -> usize {
+                1
+
+            #[pyfunction]
+            fn two() -> usize {
+                2
+            }
+        '''))
+        with self.assertRaises(BuildError), self.assertLogs('rustimport', 'WARNING') as logs:
+            rustimport.get_importable(path).build()
+        output = '\n'.join(logs.output)
+        self.assertIn('this file contains an unclosed delimiter', output)
+        self.assertIn(f'{path}:11:', output)
+        self.assertIn(' 5 | fn one() -> usize {', output)
+        # No follow-up errors caused by a generated #[pymodule]:
+        self.assertNotIn('wrap_pyfunction', output)
+        self.assertNotIn('#[pymodule]\n', output)
+
+
+class _Cancelled(Exception):
+    pass
+
+
+@requires_cargo
+class TestMaxConcurrentBuilds(TestCase):
+    settings = {'max_concurrent_builds': 2}
+
+    def test_semaphore_bounds_concurrent_cargo_invocations(self):
+        importables = [rustimport.get_importable(self.write(f'concurrent{i}.rs', pyo3_source(''))) for i in range(5)]
+        lock = threading.Lock()
+        running, max_running = 0, 0
+
+        def cargo_build(cargo, crate_path, **kwargs):
+            nonlocal running, max_running
+            with lock:
+                running += 1
+                max_running = max(max_running, running)
+            time.sleep(0.3)
+            with lock:
+                running -= 1
+            # There's nothing to build:
+            raise _Cancelled()
+
+        errors = []
+
+        def build(importable):
+            try:
+                importable.build()
+            except _Cancelled:
+                pass
+            except Exception as e:
+                errors.append(e)
+
+        with mock.patch.object(Cargo, 'build', autospec=True, side_effect=cargo_build):
+            # The threads need to see this test's settings:
+            threads = [threading.Thread(target=contextvars.copy_context().run, args=(build, i)) for i in importables]
+            for thread in threads:
+                thread.start()
+            for thread in threads:
+                thread.join()
+        self.assertEqual(errors, [])
+        self.assertEqual(max_running, 2)
+
+
+@requires_cargo
+class TestIncrementalBuild(TestCase):
+    # Use the build directory's own target directory, which has to be kept between builds:
+    settings = {'cargo_target_dir': None}
+
+    def write_crate(self, value: int) -> str:
+        self.write('incremental/src/lib.rs', pyo3_source(f'''
+            #[pyfunction]
+            fn value() -> i64 {{ {value} }}
+        '''))
+        return os.path.dirname(self.write('incremental/Cargo.toml', '''
+            # rustimport
+            [package]
+            name = "incremental"
+            version = "0.1.0"
+            edition = "2021"
+
+            [lib]
+            crate-type = ["cdylib"]
+
+            [dependencies]
+            pyo3 = "0.16.2"
+        '''))
+
+    def test_small_edit_only_rebuilds_the_crate(self):
+        importable = rustimport.get_importable(self.write_crate(1))
+        first = importable.build()
+        self.assertIn('pyo3', _compiled_units(first))
+
+        self.write_crate(2)
+        second = importable.build()
+        self.assertEqual(_compiled_units(second), ['incremental'])
+        self.assertLess(second.duration, first.duration)
+
+
+def _compiled_units(build_result) -> List[str]:
+    """The names of the units cargo (re)compiled, rather than reusing them from a previous build."""
+    messages = [json.loads(line) for line in build_result.cargo_stdout.splitlines() if line.startswith('{')]
+    return [m['target']['name'] for m in messages if m.get('reason') == 'compiler-artifact' and not m['fresh']]
+
+
+class TestCopySourceToBuildDir(TestCase):
+    def test_only_changed_files_are_copied_and_target_is_kept(self):
+        source = os.path.dirname(self.write('crate/Cargo.toml', '[package]\n'))
+        self.write('crate/src/lib.rs', 'fn a() {}\n')
+        self.write('crate/src/unchanged.rs', 'fn b() {}\n')
+        self.write('crate/src/removed.rs', 'fn c() {}\n')
+        destination = os.path.join(self.dir, 'build')
+        _copy_source_to_build_dir(source, destination)
+        self.write('build/target/debug/.fingerprint/incremental', 'kept')
+        unchanged_mtime = os.stat(os.path.join(destination, 'src', 'unchanged.rs')).st_mtime_ns
+
+        self.write('crate/src/lib.rs', 'fn a() { println!(); }\n')
+        os.remove(os.path.join(source, 'src', 'removed.rs'))
+        with mock.patch('shutil.copy2', wraps=shutil.copy2) as copy:
+            _copy_source_to_build_dir(source, destination)
+
+        self.assertEqual([c.args[0] for c in copy.call_args_list], [os.path.join(source, 'src', 'lib.rs')])
+        with open(os.path.join(destination, 'src', 'lib.rs')) as f:
+            self.assertEqual(f.read(), 'fn a() { println!(); }\n')
+        self.assertEqual(os.stat(os.path.join(destination, 'src', 'unchanged.rs')).st_mtime_ns, unchanged_mtime)
+        self.assertFalse(os.path.exists(os.path.join(destination, 'src', 'removed.rs')))
+        self.assertTrue(os.path.isfile(os.path.join(destination, 'target', 'debug', '.fingerprint', 'incremental')))
+
+
+@requires_cargo
+class TestCustomAllocator(TestCase):
+    def test_global_allocator_from_prelude(self):
+        importable = rustimport.get_importable(self.write('allocator.rs', '''
+            // rustimport:pyo3
+            //prelude: #[global_allocator] static GLOBAL: Counting = Counting;
+            use pyo3::prelude::*;
+            use std::alloc::{GlobalAlloc, Layout, System};
+            use std::sync::atomic::{AtomicUsize, Ordering};
+
+            static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+            struct Counting;
+
+            unsafe impl GlobalAlloc for Counting {
+                unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                    System.alloc(layout)
+                }
+
+                unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                    System.dealloc(ptr, layout)
+                }
+            }
+
+            #[pyfunction]
+            fn allocations() -> usize {
+                let before = ALLOCATIONS.load(Ordering::Relaxed);
+                drop(vec![0u8; 16]);
+                ALLOCATIONS.load(Ordering::Relaxed) - before
+            }
+        '''))
+        importable.build()
+        self.addCleanup(sys.modules.pop, importable.fullname, None)
+        self.assertEqual(importable.load().allocations(), 1)
+
+    def test_no_std(self):
+        for name, attribute in (('nostd', '#![no_std]'), ('nostd_prelude', '//prelude: #![no_std]')):
+            with self.subTest(attribute):
+                importable = rustimport.get_importable(self.write(f'{name}.rs', f'''
+                    // rustimport:pyo3
+                    {attribute}
+                    use pyo3::prelude::*;
+
+                    #[pyfunction]
+                    fn add(a: u32, b: u32) -> u32 {{ a + b }}
+                '''))
+                importable.build()
+                self.addCleanup(sys.modules.pop, importable.fullname, None)
+                self.assertEqual(importable.load().add(1, 2), 3)
+
+
+@requires_cargo
+class TestAutoExportPub(TestCase):
+    settings = {'auto_export_pub': True}
+
+    def test_compatible_pub_functions_are_callable(self):
+        importable = rustimport.get_importable(self.write('autoexport.rs', '''
+            // rustimport:pyo3
+            use pyo3::prelude::*;
+
+            pub fn add(a: i64, b: i64) -> i64 { a + b }
+            pub fn identity<T>(x: T) -> T { x }
+
+            #[pyfunction]
+            pub fn explicit() -> i64 { identity(1) }
+        '''))
+        with self.assertLogs('rustimport', 'WARNING'):
+            importable.build()
+        self.addCleanup(sys.modules.pop, importable.fullname, None)
+        module = importable.load()
+        self.assertEqual(module.add(1, 2), 3)
+        self.assertEqual(module.explicit(), 1)
+        self.assertFalse(hasattr(module, 'identity'))
+
+    def test_changing_the_setting_causes_a_rebuild(self):
+        importable = rustimport.get_importable(self.write('autoexport_toggle.rs', pyo3_source('''
+            pub fn add(a: i64, b: i64) -> i64 { a + b }
+        ''')))
+        importable.build()
+        self.assertFalse(importable.needs_rebuild())
+        with rustimport.Settings(auto_export_pub=False).applied():
+            self.assertTrue(importable.needs_rebuild())
+
+
+_PLAIN_CDYLIB = '''
+    // rustimport
+    //: [package]
+    //: name = "plain"
+    //: version = "0.1.0"
+    //: edition = "2021"
+    //: [lib]
+    //: crate-type = ["cdylib"]
+
+    #[no_mangle]
+    pub extern "C" fn answer() -> i32 { 42 }
+'''
+
+
+@requires_cargo
+@unittest.skipUnless(sys.platform.startswith(('linux', 'darwin')), 'requires a gcc- or clang-style linker')
+class TestLinkArgs(TestCase):
+    # Another target directory, so that the differing flags don't cause rebuilds of the other tests' dependencies:
+    settings = {'cargo_target_dir': os.path.join(CARGO_TARGET_DIR, 'link-args')}
+
+    def test_link_args_for_current_platform_are_applied(self):
+        name = b'rustimport_custom_link_name'
+        link_args = {
+            'linux': [f'-Wl,-soname,{name.decode()}'],
+            'darwin': [f'-Wl,-install_name,@rpath/{name.decode()}'],
+            # Would fail the build if applied:
+            'other-platform': ['-Wl,--no-such-linker-flag'],
+        }
+        with rustimport.Settings(link_args=link_args).applied():
+            importable = rustimport.get_importable(self.write('plain.rs', _PLAIN_CDYLIB))
+            importable.build()
+
+        with open(importable.extension_path, 'rb') as f:
+            self.assertIn(name, f.read())
+        self.assertEqual(ctypes.CDLL(importable.extension_path).answer(), 42)
+
+    def test_changing_link_args_causes_a_rebuild(self):
+        importable = rustimport.get_importable(self.write('plain.rs', _PLAIN_CDYLIB))
+        importable.build()
+        self.assertFalse(importable.needs_rebuild())
+        with rustimport.Settings(link_args={sys.platform: ['-Wl,-v']}).applied():
+            self.assertTrue(importable.needs_rebuild())
+
+
+@requires_cargo
+class TestBuildScriptOutput(TestCase):
+    # Print cargo's output live, rather than collecting all of it:
+    settings = {'verbosity': 2}
+
+    def write_crate(self, name: str, lib: str = 'pub fn answer() -> i32 { 42 }') -> str:
+        self.write(f'{name}/build.rs', '''
+            fn main() {
+                println!("cargo:rerun-if-changed=build.rs");
+                println!("cargo:warning=chatty build script");
+            }
+        ''')
+        self.write(f'{name}/src/lib.rs', lib)
+        return os.path.dirname(self.write(f'{name}/Cargo.toml', f'''
+            # rustimport
+            [package]
+            name = "{name}"
+            version = "0.1.0"
+            edition = "2021"
+
+            [lib]
+            crate-type = ["cdylib"]
+        '''))
+
+    def build(self, path: str, stderr: io.StringIO):
+        """Build the crate, capturing what is printed to stderr, either by cargo itself or by rustimport."""
+        with tempfile.TemporaryFile() as fd_stderr:
+            original_fd = os.dup(2)
+            os.dup2(fd_stderr.fileno(), 2)
+            try:
+                with contextlib.redirect_stderr(stderr):
+                    return rustimport.get_importable(path).build()
+            finally:
+                os.dup2(original_fd, 2)
+                os.close(original_fd)
+                fd_stderr.seek(0)
+                stderr.write(fd_stderr.read().decode())
+
+    def test_output_is_printed_by_default(self):
+        stderr = io.StringIO()
+        self.build(self.write_crate('printed'), stderr)
+        self.assertIn('warning: printed@0.1.0: chatty build script', stderr.getvalue())
+
+    def test_output_is_held_back(self):
+        stderr = io.StringIO()
+        with rustimport.Settings(suppress_build_script_output=True).applied():
+            result = self.build(self.write_crate('held_back'), stderr)
+        self.assertNotIn('chatty build script', stderr.getvalue())
+        self.assertIn('warning: held_back@0.1.0: chatty build script', result.cargo_stderr)
+
+    def test_output_is_printed_if_the_build_fails(self):
+        stderr = io.StringIO()
+        with rustimport.Settings(suppress_build_script_output=True).applied(), self.assertRaises(BuildError):
+            self.build(self.write_crate('failing', lib='pub fn answer() -> i32 { "not a number" }'), stderr)
+        # The compiler error isn't held back, but printed first:
+        self.assertRegex(stderr.getvalue(), r'(?s)mismatched types.*warning: failing@0\.1\.0: chatty build script')
@@ -0,0 +1,176 @@
+import hashlib
+import json
+import os
+import sys
+from unittest import mock
+
+import rustimport
+from rustimport import Settings, settings
+from rustimport.pre_processing import Preprocessor
+from rustimport.checksum import is_checksum_valid, save_checksum, get_checksum, calc_input_fingerprint, \
+    _get_file_index_path, _get_checksum_path
+from tests.utils import TestCase, pyo3_source, requires_cargo
+
+
+class TestChecksum(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.source = self.write('mod.rs', 'fn main() {}\n')
+        self.extension = self.write('mod.so', '')
+
+    def test_hasher_is_resolved_per_call(self):
+        save_checksum(self.extension, [self.source])
+        self.assertEqual(len(get_checksum(self.extension)), hashlib.sha1().digest_size * 2)
+
+        with Settings(checksum_hasher=hashlib.md5).applied():
+            self.assertFalse(is_checksum_valid(self.extension, [self.source]))
+            save_checksum(self.extension, [self.source])
+            self.assertEqual(len(get_checksum(self.extension)), hashlib.md5().digest_size * 2)
+            self.assertTrue(is_checksum_valid(self.extension, [self.source]))
+
+    def test_hasher_is_resolved_per_call_for_mtime_size_index(self):
+        with Settings(checksum_strategy='mtime_size', checksum_hasher=hashlib.md5).applied():
+            save_checksum(self.extension, [self.source])
+            os.utime(self.source, ns=(0, 0))
+            self.assertTrue(is_checksum_valid(self.extension, [self.source]))
+        with open(_get_file_index_path(self.extension)) as f:
+            self.assertEqual(json.load(f)['hasher'], 'md5')
+
+    def test_input_fingerprint_hasher_is_resolved_per_call(self):
+        sha1 = calc_input_fingerprint([self.source], self.dir, {})
+        with Settings(checksum_hasher=hashlib.md5).applied():
+            md5 = calc_input_fingerprint([self.source], self.dir, {})
+        self.assertEqual((len(sha1), len(md5)), (40, 32))
+
+
+class TestChecksumStrategies(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.source = self.write('mod.rs', 'fn main() {}\n')
+        self.extension = self.write('mod.so', '')
+
+    def check(self, strategy: str, change) -> bool:
+        """Save a checksum using `strategy`, apply `change` and return whether the checksum is still valid."""
+        with Settings(checksum_strategy=strategy).applied():
+            save_checksum(self.extension, [self.source])
+            change()
+            return is_checksum_valid(self.extension, [self.source])
+
+    def touch(self):
+        stat = os.stat(self.source)
+        os.utime(self.source, ns=(stat.st_atime_ns, stat.st_mtime_ns + 10 ** 9))
+
+    def modify(self):
+        """Change the contents, keeping the size and modification time."""
+        stat = os.stat(self.source)
+        self.write('mod.rs', 'fn niam() {}\n')
+        os.utime(self.source, ns=(stat.st_atime_ns, stat.st_mtime_ns))
+
+    def test_content(self):
+        self.assertTrue(self.check('content', lambda: None))
+        self.assertTrue(self.check('content', self.touch))
+        self.assertFalse(self.check('content', self.modify))
+
+    def test_mtime_size(self):
+        self.assertTrue(self.check('mtime_size', lambda: None))
+        # The contents are only compared if the modification time or size changed:
+        self.assertTrue(self.check('mtime_size', self.touch))
+        self.assertTrue(self.check('mtime_size', self.modify))
+        self.assertFalse(self.check('mtime_size', lambda: (self.write('mod.rs', 'fn main() { }\n'), self.touch())))
+
+    def test_content_and_mtime(self):
+        self.assertTrue(self.check('content+mtime', lambda: None))
+        self.assertFalse(self.check('content+mtime', self.touch))
+        self.assertFalse(self.check('content+mtime', self.modify))
+
+    def test_mtime_size_only_rehashes_changed_files(self):
+        other = self.write('other.rs', 'fn other() {}\n')
+        with Settings(checksum_strategy='mtime_size').applied():
+            save_checksum(self.extension, [self.source, other])
+            self.touch()
+            with mock.patch('rustimport.checksum.open', wraps=open) as opened:
+                self.assertTrue(is_checksum_valid(self.extension, [self.source, other]))
+            read = [call.args[0] for call in opened.call_args_list if call.args[1:] == ('rb',)]
+            self.assertIn(self.source, read)
+            self.assertNotIn(other, read)
+            # The checksum has been updated to the new modification time:
+            with mock.patch('rustimport.checksum.open', wraps=open) as opened:
+                self.assertTrue(is_checksum_valid(self.extension, [self.source, other]))
+            self.assertNotIn(self.source, [call.args[0] for call in opened.call_args_list])
+
+
+class TestChecksumLocation(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.source = self.write('src/mod.rs', 'fn main() {}\n')
+        self.extension = self.write('src/mod.so', '')
+        self.make_read_only(os.path.dirname(self.source))
+
+    def make_read_only(self, directory: str):
+        mode = os.stat(directory).st_mode
+        os.chmod(directory, mode & ~0o222)
+        self.addCleanup(os.chmod, directory, mode)
+
+    def assert_sources_untouched(self):
+        self.assertEqual(sorted(os.listdir(os.path.dirname(self.source))), ['mod.rs', 'mod.so'])
+
+    def test_checksum_is_stored_in_cache_dir(self):
+        save_checksum(self.extension, [self.source])
+        self.assertTrue(is_checksum_valid(self.extension, [self.source]))
+        self.assertEqual(os.path.dirname(_get_checksum_path(self.extension)), os.path.join(settings.cache_dir, 'checksums'))
+        self.assertTrue(os.path.isfile(_get_checksum_path(self.extension)))
+        self.assert_sources_untouched()
+
+    def test_checksum_dir_setting(self):
+        checksum_dir = os.path.join(self.dir, 'checksums')
+        with Settings(checksum_dir=checksum_dir).applied():
+            save_checksum(self.extension, [self.source])
+            self.assertTrue(is_checksum_valid(self.extension, [self.source]))
+        self.assertEqual([f for f in os.listdir(checksum_dir) if f.endswith('.checksum')],
+                         [os.path.basename(_get_checksum_path(self.extension))])
+        self.assert_sources_untouched()
+
+
+@requires_cargo
+class TestChecksumLocationWithBuild(TestCase):
+    def test_cache_hit_with_read_only_sources(self):
+        path = self.write('src/readonly.rs', pyo3_source(''))
+        rustimport.get_importable(path).build()
+        directory = os.path.dirname(path)
+        mode = os.stat(directory).st_mode
+        os.chmod(directory, mode & ~0o222)
+        self.addCleanup(os.chmod, directory, mode)
+        contents = sorted(os.listdir(directory))
+
+        # Another run, i.e. a new `Importable`:
+        importable = rustimport.get_importable(path)
+        self.assertFalse(importable.needs_rebuild())
+        importable.load()
+        self.addCleanup(sys.modules.pop, importable.fullname, None)
+        self.assertEqual(sorted(os.listdir(directory)), contents)
+        self.assertEqual(len(contents), 2)
+
+
+@requires_cargo
+class TestPreprocessingOnCacheHit(TestCase):
+    def test_sources_are_preprocessed_once(self):
+        path = self.write('warm.rs', pyo3_source('').replace('\n', '\n//config: use_abi3=false\n', 1))
+        importable = rustimport.get_importable(path)
+        importable.build()
+
+        with mock.patch.object(Preprocessor, 'process', autospec=True, side_effect=Preprocessor.process) as process, \
+                mock.patch.object(Preprocessor, 'parse_directives', autospec=True,
+                                  side_effect=Preprocessor.parse_directives) as parse_directives:
+            self.assertFalse(importable.needs_rebuild())
+        self.assertEqual(process.call_count, 1)
+        self.assertEqual(parse_directives.call_count, 0)
+
+    def test_changes_after_the_check_are_picked_up(self):
+        path = self.write('warm.rs', pyo3_source(''))
+        importable = rustimport.get_importable(path)
+        importable.build()
+        self.assertFalse(importable.needs_rebuild())
+
+        self.write('warm.rs', pyo3_source('').replace('\n', '\n//config: use_abi3=true\n', 1))
+        self.assertTrue(importable.uses_abi3)
+        self.assertTrue(importable.needs_rebuild())
@@ -0,0 +1,47 @@
+import os
+import threading
+import time
+
+import rustimport
+from rustimport import settings
+from rustimport.locking import file_lock
+from tests.utils import TestCase, pyo3_source
+
+
+class TestClean(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.importable = rustimport.get_importable(self.write('cleaned.rs', pyo3_source('')))
+        os.makedirs(os.path.join(self.importable.build_tempdir, 'target'))
+
+    def test_clean_all_keeps_the_rest_of_the_cache_dir(self):
+        kept = [os.path.join(settings.cache_dir, d) for d in ('checksums', 'artifacts', 'git', 'reload')]
+        for d in kept:
+            os.makedirs(d)
+
+        rustimport.clean()
+
+        self.assertFalse(os.path.exists(self.importable.build_tempdir))
+        for d in kept:
+            self.assertTrue(os.path.isdir(d), d)
+
+    def test_clean_waits_for_build_in_progress(self):
+        # Threads don't inherit the test's settings, so the build directory is resolved beforehand:
+        build_dir = self.importable.build_tempdir
+        locked = threading.Event()
+        existed_until_unlocked = []
+
+        def build():
+            with file_lock(build_dir + '.lock', 10):
+                locked.set()
+                time.sleep(0.5)
+                existed_until_unlocked.append(os.path.isdir(build_dir))
+
+        thread = threading.Thread(target=build)
+        thread.start()
+        locked.wait(10)
+        rustimport.clean()
+        thread.join()
+
+        self.assertEqual(existed_until_unlocked, [True])
+        self.assertFalse(os.path.exists(build_dir))
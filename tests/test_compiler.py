@@ -0,0 +1,136 @@
+import json
+import os
+import stat
+import sys
+import textwrap
+import threading
+import unittest
+from unittest import mock
+
+from rustimport import Settings
+from rustimport.compiler import Cargo, _shorten_command_line
+from tests.utils import TestCase
+
+
+def _write_executable(test: TestCase, name: str, script: str) -> str:
+    """Write a Python script to an executable file in the test directory, e.g. to replace cargo or rustc."""
+    path = test.write(name, f'#!{sys.executable}\n' + textwrap.dedent(script).lstrip())
+    os.chmod(path, os.stat(path).st_mode | stat.S_IEXEC)
+    return path
+
+
+@unittest.skipIf(os.name == 'nt', "the fake cargo executable is a script")
+class TestCargoCheck(TestCase):
+    def fake_cargo(self, script: str) -> Cargo:
+        """A `Cargo` running the given Python script instead of cargo."""
+        return Cargo(_write_executable(self, 'fake-cargo', f'import sys\n{script}'))
+
+    def test_check_does_not_block_on_lots_of_stderr_output(self):
+        cargo = self.fake_cargo(
+            "sys.stderr.write('warning: something\\n' * 100_000)\n"
+            "print('{\"reason\": \"build-finished\", \"success\": true}')\n"
+        )
+        results = []
+        thread = threading.Thread(target=lambda: results.append(cargo.check(self.dir)), daemon=True)
+        thread.start()
+        thread.join(30)
+
+        self.assertFalse(thread.is_alive(), "cargo check blocked")
+        [result] = results
+        self.assertTrue(result.success)
+        self.assertEqual(result.error_output, ['warning: something\n' * 100_000])
+
+
+class TestLongCommandLines(TestCase):
+    def test_short_command_line_is_kept(self):
+        cmd = ['cargo', 'rustc', '--lib', '--config', 'a="b"', '--', '-Copt-level=1']
+        self.assertEqual(_shorten_command_line(cmd, self.dir), cmd)
+
+    def test_long_command_line_is_moved_to_files(self):
+        config = [f'env.VAR_{i}="{"x" * 100}"' for i in range(200)]
+        rustc_args = [f'--cfg=feature_{i}' for i in range(2000)]
+        cmd = ['cargo', 'rustc', '--lib', '--features', 'a,b', *(a for c in config for a in ('--config', c)),
+               '--', *rustc_args]
+
+        shortened = _shorten_command_line(cmd, self.dir)
+
+        config_path = os.path.join(self.dir, 'rustimport-config.toml')
+        response_file_path = os.path.join(self.dir, 'rustimport-rustc-args.txt')
+        self.assertEqual(shortened, ['cargo', 'rustc', '--lib', '--features', 'a,b', '--config', config_path,
+                                     '--', f'@{response_file_path}'])
+        with open(config_path) as f:
+            self.assertEqual(f.read().splitlines(), config)
+        with open(response_file_path) as f:
+            self.assertEqual(f.read().splitlines(), rustc_args)
+
+    def test_too_long_command_line_is_reported(self):
+        cmd = ['cargo', 'rustc', '--features', ','.join(f'feature_{i}' for i in range(5000))]
+        with self.assertRaisesRegex(ValueError, "exceeds Windows' length limit"):
+            _shorten_command_line(cmd, self.dir)
+
+    @unittest.skipIf(os.name == 'nt', "the fake cargo executable is a script")
+    def test_build_proceeds_on_windows(self):
+        argv_path = os.path.join(self.dir, 'argv.json')
+        artifact_path = self.write('target/libcrate.so', '')
+        self.write('Cargo.toml', '[package]\nname = "crate"\n')
+        path = _write_executable(self, 'fake-cargo', f'''
+            import json, sys
+            with open({argv_path!r}, 'w') as f:
+                json.dump(sys.argv[1:], f)
+            print(json.dumps({{"reason": "compiler-artifact", "manifest_path": {os.path.join(self.dir, 'Cargo.toml')!r},
+                              "package_id": "crate 0.1.0", "filenames": [{artifact_path!r}], "fresh": False}}))
+            print(json.dumps({{"reason": "build-finished", "success": True}}))
+        ''')
+
+        with mock.patch('os.name', 'nt'):
+            result = Cargo(path).build(self.dir, additional_args=['--', *(f'--cfg=feature_{i}' for i in range(5000))])
+
+        self.assertTrue(result.success)
+        self.assertEqual(result.artifact_path, artifact_path)
+        with open(argv_path) as f:
+            argv = json.load(f)
+        self.assertEqual(argv[-2:], ['--', '@' + os.path.join(self.dir, 'rustimport-rustc-args.txt')])
+
+
+@unittest.skipIf(os.name == 'nt', "the fake executables are scripts")
+class TestHostTriple(TestCase):
+    musl = 'x86_64-unknown-linux-musl'
+
+    def setUp(self):
+        super().setUp()
+        rustc = _write_executable(self, 'fake-rustc', f'''
+            print("rustc 1.80.0 (051478957 2024-07-21)")
+            print("binary: rustc")
+            print("host: {self.musl}")
+            print("release: 1.80.0")
+        ''')
+        patcher = mock.patch.dict(os.environ, {'RUSTC': rustc})
+        patcher.start()
+        self.addCleanup(patcher.stop)
+        # The fake cargo reports no artifact, so that it has to be looked up in the target directory:
+        self.cargo = Cargo(_write_executable(self, 'fake-cargo', '''
+            print('{"reason": "build-finished", "success": true}')
+        '''))
+        self.crate_path = os.path.dirname(self.write('crate/Cargo.toml', '''
+            [package]
+            name = "musl-crate"
+        '''))
+
+    def test_host_triple_is_parsed_from_rustc(self):
+        self.assertEqual(self.cargo.host_triple(), self.musl)
+
+    def test_host_triple_setting_takes_precedence(self):
+        with Settings(host_triple='aarch64-unknown-linux-gnu').applied():
+            self.assertEqual(self.cargo.host_triple(), 'aarch64-unknown-linux-gnu')
+
+    def test_artifact_is_found_in_host_triple_directory(self):
+        artifact_path = self.write(f'crate/target/{self.musl}/debug/libmusl_crate.so', '')
+        result = self.cargo.build(self.crate_path)
+        self.assertTrue(result.success)
+        self.assertEqual(result.artifact_path, artifact_path)
+
+    def test_artifact_is_not_found_with_other_host_triple(self):
+        self.write(f'crate/target/{self.musl}/debug/libmusl_crate.so', '')
+        with Settings(host_triple='x86_64-unknown-linux-gnu').applied(), self.assertLogs('rustimport', 'WARNING'):
+            result = self.cargo.build(self.crate_path)
+        self.assertIsNone(result.artifact_path)
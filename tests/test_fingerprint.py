@@ -0,0 +1,34 @@
+import rustimport
+from rustimport import Settings
+from tests.utils import TestCase, pyo3_source, requires_cargo
+
+
+@requires_cargo
+class TestInputFingerprint(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.source = pyo3_source('''
+            #[pyfunction]
+            fn one() -> i64 { 1 }
+        ''')
+        self.importable = rustimport.get_importable(self.write('fingerprinted.rs', self.source))
+
+    def test_release_defaults_to_current_setting(self):
+        debug = self.importable.input_fingerprint()
+        with Settings(compile_release_binaries=True).applied():
+            release = self.importable.input_fingerprint()
+        self.assertNotEqual(debug, release)
+        self.assertEqual(debug, self.importable.input_fingerprint(release=False))
+        self.assertEqual(release, self.importable.input_fingerprint(release=True))
+
+    def test_stable_across_locations(self):
+        other = rustimport.get_importable(self.write('elsewhere/fingerprinted.rs', self.source))
+        self.assertEqual(self.importable.input_fingerprint(), other.input_fingerprint())
+
+    def test_changes_with_sources(self):
+        before = self.importable.input_fingerprint()
+        self.write('fingerprinted.rs', pyo3_source('''
+            #[pyfunction]
+            fn one() -> i64 { 2 }
+        '''))
+        self.assertNotEqual(before, self.importable.input_fingerprint())
@@ -0,0 +1,83 @@
+import importlib
+import sys
+
+import rustimport
+from tests.utils import TestCase, pyo3_source, requires_cargo
+
+
+@requires_cargo
+class TestLoadOldArtifacts(TestCase):
+    settings = {'keep_old_artifacts': 1}
+
+    def test_loads_old_build_alongside_current(self):
+        path = self.write('sidebyside.rs', pyo3_source('''
+            #[pyfunction]
+            fn version() -> i64 { 1 }
+        '''))
+        importable = rustimport.get_importable(path)
+        importable.build()
+        self.write('sidebyside.rs', pyo3_source('''
+            #[pyfunction]
+            fn version() -> i64 { 2 }
+        '''))
+        importable.build()
+        self.addCleanup(sys.modules.pop, importable.fullname, None)
+
+        current = importable.load()
+        [(checksum, _)] = importable.old_artifacts
+        old = importable.load(checksum=checksum)
+
+        self.assertEqual(current.version(), 2)
+        self.assertEqual(old.version(), 1)
+        self.assertIs(sys.modules[importable.fullname], current)
+
+
+@requires_cargo
+class TestInitSymbolConflicts(TestCase):
+    def test_loading_two_modules_with_the_same_name_is_reported(self):
+        first, second = (
+            rustimport.get_importable(self.write(f'{directory}/twin.rs', pyo3_source(f'''
+                #[pyfunction]
+                fn origin() -> &'static str {{ "{directory}" }}
+            ''')))
+            for directory in ('first', 'second')
+        )
+        first.build()
+        second.build()
+        self.addCleanup(sys.modules.pop, 'twin', None)
+
+        self.assertEqual(first.load().origin(), 'first')
+        with self.assertRaisesRegex(ImportError, r'exports the same init symbol \(PyInit_twin\)'):
+            second.load()
+        self.assertEqual(sys.modules['twin'].origin(), 'first')
+
+
+@requires_cargo
+class TestPackageSubmodule(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.write('rustpkg/__init__.py', '')
+        self.write('rustpkg/accel.rs', pyo3_source('''
+            #[pyfunction]
+            fn answer() -> i64 { 42 }
+        '''))
+        sys.path.insert(0, self.dir)
+        self.addCleanup(sys.path.remove, self.dir)
+        self.addCleanup(lambda: [sys.modules.pop(m, None) for m in ('rustpkg', 'rustpkg.accel')])
+
+    def test_submodule_is_registered_under_its_dotted_name(self):
+        module = rustimport.imp('rustpkg.accel')
+        self.assertEqual(module.__name__, 'rustpkg.accel')
+        self.assertEqual(module.__package__, 'rustpkg')
+        self.assertIs(sys.modules['rustpkg.accel'], module)
+        self.assertNotIn('accel', sys.modules)
+        self.assertEqual(module.answer(), 42)
+
+    def test_relative_import_from_package(self):
+        self.write('rustpkg/user.py', '''
+            from .accel import answer
+        ''')
+        rustimport.imp('rustpkg.accel')
+        self.addCleanup(sys.modules.pop, 'rustpkg.user', None)
+        importlib.invalidate_caches()
+        self.assertEqual(importlib.import_module('rustpkg.user').answer(), 42)
@@ -0,0 +1,56 @@
+import io
+import logging
+from contextlib import redirect_stderr
+from unittest import mock
+
+import rustimport
+from rustimport import Settings
+from rustimport.importable import build_if_needed_and_load, _report_build
+from tests.utils import TestCase
+
+
+class TestRequestedOutput(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.importable = mock.Mock(fullname='mymod', path='mymod.rs', settings=Settings())
+        self.importable.needs_rebuild.return_value = False
+
+    def configure_logging(self) -> io.StringIO:
+        stream = io.StringIO()
+        handler = rustimport.configure_logging(stream=stream)
+        self.addCleanup(logging.getLogger('rustimport').removeHandler, handler)
+        return stream
+
+    def test_summary_is_printed_without_logging_configured(self):
+        stderr = io.StringIO()
+        with Settings(summary=True).applied(), redirect_stderr(stderr):
+            build_if_needed_and_load(self.importable)
+        self.assertRegex(stderr.getvalue(), r'^rustimport: mymod loaded \(cache hit, .*s\)\n$')
+
+    def test_summary_is_logged_with_logging_configured(self):
+        stream = self.configure_logging()
+        stderr = io.StringIO()
+        with Settings(summary=True).applied(), redirect_stderr(stderr):
+            build_if_needed_and_load(self.importable)
+        self.assertEqual(stderr.getvalue(), '')
+        self.assertIn('rustimport: mymod loaded (cache hit', stream.getvalue())
+
+    def test_no_summary_by_default(self):
+        stderr = io.StringIO()
+        with redirect_stderr(stderr):
+            build_if_needed_and_load(self.importable)
+        self.assertEqual(stderr.getvalue(), '')
+
+    def test_build_messages_are_printed_at_verbosity_1(self):
+        build = _report_build(lambda importable, release: None)
+        stderr = io.StringIO()
+        with Settings(verbosity=1).applied(), redirect_stderr(stderr):
+            build(self.importable)
+        self.assertRegex(stderr.getvalue(), r'^rustimport: Building mymod…\nrustimport: Done building mymod \(.*s\)\n$')
+
+    def test_no_build_messages_at_verbosity_0(self):
+        build = _report_build(lambda importable, release: None)
+        stderr = io.StringIO()
+        with Settings(verbosity=0).applied(), redirect_stderr(stderr):
+            build(self.importable)
+        self.assertEqual(stderr.getvalue(), '')
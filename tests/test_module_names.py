@@ -0,0 +1,125 @@
+import rustimport
+from rustimport import ModuleNameMismatchError, Settings
+from rustimport.pre_processing.stubs import detect_pymodule_name, detect_exports
+from tests.utils import TestCase
+
+_FUNCTION_FORM = '''
+    // rustimport
+    //: [lib]
+    //: name = "{lib}"
+    //: [dependencies]
+    //: pyo3 = {{ version = "0.16.2", features = ["extension-module"] }}
+    use pyo3::prelude::*;
+
+    #[pymodule]
+    fn {pymodule}(_py: Python, m: &PyModule) -> PyResult<()> {{
+        Ok(())
+    }}
+'''
+
+_DECLARATIVE_FORM = '''
+    // rustimport
+    //: [lib]
+    //: name = "{lib}"
+    //: [dependencies]
+    //: pyo3 = {{ version = "0.21", features = ["extension-module"] }}
+    use pyo3::prelude::*;
+
+    #[pymodule]
+    mod {pymodule} {{
+        #[pymodule]
+        mod nested {{}}
+    }}
+'''
+
+_TEMPLATE_DECLARATIVE_FORM = '''
+    // rustimport:pyo3
+    //: [lib]
+    //: name = "{lib}"
+    //: [dependencies]
+    //: pyo3 = "0.21"
+    use pyo3::prelude::*;
+
+    #[pymodule]
+    mod {pymodule} {{
+        use super::*;
+
+        #[pyfunction]
+        fn one() -> usize {{
+            1
+        }}
+    }}
+'''
+
+_GENERATED_FORM = '''
+    // rustimport:pyo3
+    //: [lib]
+    //: name = "{lib}"
+    use pyo3::prelude::*;
+'''
+
+
+_FORMS = {
+    'function': _FUNCTION_FORM,
+    'declarative': _DECLARATIVE_FORM,
+    'template declarative': _TEMPLATE_DECLARATIVE_FORM,
+    'generated': _GENERATED_FORM,
+}
+
+
+class TestDetectPymoduleName(TestCase):
+    def test_function_form(self):
+        self.assertEqual(detect_pymodule_name(b'#[pymodule]\nfn mymod(_py: Python, m: &PyModule) {}'), 'mymod')
+        self.assertEqual(detect_pymodule_name(b'#[pymodule]\npub fn mymod(m: &Bound<PyModule>) {}'), 'mymod')
+
+    def test_declarative_form(self):
+        self.assertEqual(detect_pymodule_name(b'#[pymodule]\nmod mymod {\n#[pymodule]\nmod sub {}\n}'), 'mymod')
+
+    def test_name_attribute(self):
+        self.assertEqual(detect_pymodule_name(b'#[pymodule]\n#[pyo3(name = "renamed")]\nfn mymod() {}'), 'renamed')
+        self.assertEqual(detect_pymodule_name(b'#[pymodule(name = "renamed")]\nmod mymod {}'), 'renamed')
+
+    def test_no_pymodule(self):
+        self.assertIsNone(detect_pymodule_name(b'// #[pymodule]\nfn mymod() {}'))
+        self.assertIsNone(detect_pymodule_name(b'const S: &str = "#[pymodule] fn mymod() {}";'))
+
+
+class TestModuleNameCheck(TestCase):
+    def check(self, template: str, lib: str, pymodule: str = ''):
+        importable = rustimport.get_importable(self.write('mymod.rs', template.format(lib=lib, pymodule=pymodule)))
+        _, preprocessed, manifest = importable._prepare_build_dir()
+        importable._check_module_name(preprocessed, manifest)
+
+    def test_matching_names(self):
+        for form, template in _FORMS.items():
+            with self.subTest(form):
+                self.check(template, lib='mymod', pymodule='mymod')
+
+    def test_pymodule_mismatch(self):
+        for form in ('function', 'declarative', 'template declarative'):
+            with self.subTest(form):
+                with self.assertRaises(ModuleNameMismatchError) as cm:
+                    self.check(_FORMS[form], lib='mymod', pymodule='other')
+                self.assertEqual((cm.exception.pymodule_name, cm.exception.lib_name), ('other', 'mymod'))
+                self.assertIn('#[pymodule] name:   other', str(cm.exception))
+
+    def test_lib_name_mismatch(self):
+        for form, template in _FORMS.items():
+            with self.subTest(form):
+                with self.assertRaises(ModuleNameMismatchError) as cm:
+                    self.check(template, lib='other', pymodule='other')
+                self.assertEqual((cm.exception.pymodule_name, cm.exception.lib_name), ('other', 'other'))
+                self.assertIn('is imported as mymod, but its library and #[pymodule] are named other', str(cm.exception))
+
+
+class TestTemplateWithDeclarativeModule(TestCase):
+    def test_no_pymodule_is_generated(self):
+        plain = _TEMPLATE_DECLARATIVE_FORM.format(lib='mymod', pymodule='mymod')
+        named = plain.replace('#[pymodule]', '#[pymodule(name = "mymod")]')
+        for source in (plain, named):
+            with self.subTest(source=source):
+                importable = rustimport.get_importable(self.write('mymod.rs', source))
+                self.assertIsNone(importable._preprocess().updated_source)
+                with Settings(export_visibility='pub_only').applied():
+                    # The user's #[pymodule] isn't generated, so the export visibility doesn't apply:
+                    self.assertEqual([f.name for f in detect_exports(source.encode()).functions], ['one'])
@@ -0,0 +1,372 @@
+import logging
+import re
+
+import toml
+
+from rustimport import Settings
+from rustimport.pre_processing import Preprocessor
+from rustimport.pre_processing.base import split_directive_values, has_balanced_delimiters, load_cargo_manifest
+from tests.utils import TestCase
+
+
+class PreprocessorTestCase(TestCase):
+    def process(self, source: str, name: str = 'mod') -> Preprocessor.PreprocessorResult:
+        return Preprocessor(self.write(f'{name}.rs', source), name).process()
+
+    def manifest(self, source: str) -> dict:
+        return load_cargo_manifest(self.process(source).cargo_manifest)
+
+
+class TestUnusedManifestKeys(PreprocessorTestCase):
+    source = '''
+        // rustimport:pyo3
+        //: [package]
+        //: stray = true
+        //: [lib]
+        //: crate_type = ["cdylib", "rlib"]
+        use pyo3::prelude::*;
+    '''
+
+    def test_deprecated_keys_are_renamed(self):
+        manifest = self.manifest(self.source)
+        self.assertEqual(manifest['lib']['crate-type'], ['cdylib', 'rlib'])
+        self.assertNotIn('crate_type', manifest['lib'])
+
+    def test_unknown_keys_are_removed_with_warning(self):
+        with self.assertLogs('rustimport', 'WARNING') as logs:
+            manifest = self.manifest(self.source)
+        self.assertNotIn('stray', manifest['package'])
+        self.assertIn('Removing unknown manifest key `package.stray`', logs.output[0])
+
+    def test_unknown_keys_are_removed_silently(self):
+        with Settings(unused_manifest_keys='drop').applied(), self.assertLogs('rustimport', 'DEBUG') as logs:
+            manifest = self.manifest(self.source)
+        self.assertNotIn('stray', manifest['package'])
+        self.assertEqual([r.levelname for r in logs.records if r.levelno >= logging.WARNING], [])
+
+    def test_unknown_keys_can_be_kept(self):
+        with Settings(unused_manifest_keys='keep').applied():
+            manifest = self.manifest(self.source)
+        self.assertIs(manifest['package']['stray'], True)
+        self.assertNotIn('crate_type', manifest['lib'])
+
+    def test_user_defined_sections_are_not_validated(self):
+        manifest = self.manifest('''
+            // rustimport:pyo3
+            //: [features]
+            //: fancy = []
+            use pyo3::prelude::*;
+        ''')
+        self.assertEqual(manifest['features']['fancy'], [])
+
+
+class TestPrelude(PreprocessorTestCase):
+    def test_prelude_is_injected_at_the_top(self):
+        result = self.process('''
+            // rustimport:pyo3
+            //prelude: #![allow(dead_code)]
+            //prelude: #[macro_use] extern crate pyo3;
+            use pyo3::prelude::*;
+        ''')
+        source = result.updated_source.decode()
+        self.assertTrue(source.startswith('#![allow(dead_code)] #[macro_use] extern crate pyo3; // rustimport:pyo3\n'))
+        # Line numbers are preserved:
+        self.assertEqual(source.splitlines()[3], 'use pyo3::prelude::*;')
+        self.assertEqual(result.source_map.map(1, 54), (1, 1))
+        self.assertIsNone(result.source_map.map(1, 10))
+
+    def test_prelude_without_template(self):
+        result = self.process('''
+            // rustimport
+            //prelude: #![allow(non_snake_case)]
+            //: [dependencies]
+            //: pyo3 = { version = "0.16.2", features = ["extension-module"] }
+            use pyo3::prelude::*;
+        ''')
+        self.assertTrue(result.updated_source.decode().startswith('#![allow(non_snake_case)] // rustimport\n'))
+
+
+class TestDirectiveValues(PreprocessorTestCase):
+    def test_split_directive_values(self):
+        self.assertEqual(split_directive_values('a, b c,d'), ['a', 'b', 'c', 'd'])
+        self.assertEqual(split_directive_values('"with space", \'with,comma\', "quote\\"d"'),
+                         ['with space', 'with,comma', 'quote"d'])
+        self.assertEqual(split_directive_values('#hash, ?query'), ['#hash', '?query'])
+
+    def test_git_dependency_with_special_characters(self):
+        url = 'https://example.com/repo.git?ref=a%20b&x=1#main'
+        manifest = self.manifest(f'''
+            // rustimport:pyo3
+            //: [dependencies]
+            //: mylib = {{ git = "{url}", branch = "feature/x y" }}
+            use pyo3::prelude::*;
+        ''')
+        self.assertEqual(manifest['dependencies']['mylib'], {'git': url, 'branch': 'feature/x y'})
+
+    def test_quoted_features(self):
+        result = self.process('''
+            // rustimport:pyo3
+            //features: "serde", 'with space', "a,b" plain
+            //features: "dep:optional#1"
+            use pyo3::prelude::*;
+        ''')
+        i = result.additional_cargo_args.index('--features')
+        self.assertEqual(result.additional_cargo_args[i + 1], 'serde,with space,a,b,plain,dep:optional#1')
+
+    def test_quoted_template_options(self):
+        result = self.process('''
+            // rustimport:pyo3(manifest="manual")
+            //: [package]
+            //: name = "mod"
+            //: version = "1.0.0"
+            //: edition = "2021"
+            //: [lib]
+            //: crate-type = ["cdylib"]
+            //: [dependencies]
+            //: pyo3 = "0.16.2"
+            use pyo3::prelude::*;
+        ''')
+        self.assertNotIn('extension-module', toml.loads(result.cargo_manifest.decode()).get('features', {}))
+
+
+class TestExtensionModuleFeature(PreprocessorTestCase):
+    def test_enabled_by_default(self):
+        features = self.manifest('''
+            // rustimport:pyo3
+            use pyo3::prelude::*;
+        ''')['features']
+        self.assertEqual(features['default'], ['extension-module'])
+        self.assertEqual(features['extension-module'], ['pyo3/extension-module'])
+
+    def test_kept_with_user_default_features(self):
+        features = self.manifest('''
+            // rustimport:pyo3
+            //: [features]
+            //: default = ["simd"]
+            //: simd = []
+            use pyo3::prelude::*;
+        ''')['features']
+        self.assertEqual(features['default'], ['simd', 'extension-module'])
+        self.assertEqual(features['extension-module'], ['pyo3/extension-module'])
+
+    def test_kept_with_user_default_features_for_cpython(self):
+        features = self.manifest('''
+            // rustimport:cpython
+            //: [features]
+            //: default = []
+            use cpython::{PyResult, Python};
+        ''')['features']
+        self.assertEqual(features['default'], ['extension-module'])
+
+
+class TestPyO3VersionDetection(PreprocessorTestCase):
+    def pymodule(self, pyo3_dependency: str) -> str:
+        source = self.process(f'''
+            // rustimport:pyo3
+            //: [dependencies]
+            //: pyo3 = {pyo3_dependency}
+            use pyo3::prelude::*;
+
+            #[pyfunction]
+            fn f() {{}}
+        ''').updated_source.decode()
+        return source[source.index('#[pymodule]'):]
+
+    def test_gil_ref_api_before_0_21(self):
+        for version in ('"0.16.2"', '"0.20"', '{ version = "~0.18.1", features = ["abi3"] }'):
+            with self.subTest(version):
+                pymodule = self.pymodule(version)
+                self.assertIn('fn mod(_py: Python, m: &PyModule) -> PyResult<()>', pymodule)
+                self.assertIn('m.add_function(wrap_pyfunction!(f, m)?)?;', pymodule)
+
+    def test_bound_api_since_0_21(self):
+        for version in ('"0.21"', '">=0.22, <0.24"', '{ version = "0.23.1" }'):
+            with self.subTest(version):
+                pymodule = self.pymodule(version)
+                self.assertIn("fn mod(m: &Bound<'_, PyModule>) -> PyResult<()>", pymodule)
+                self.assertIn('let _py = m.py();', pymodule)
+
+    def test_default_version_from_settings(self):
+        source = '''
+            // rustimport:pyo3
+            use pyo3::prelude::*;
+        '''
+        self.assertIn('m: &PyModule', self.process(source).updated_source.decode())
+        with Settings(pyo3_version='0.22').applied():
+            self.assertIn("m: &Bound<'_, PyModule>", self.process(source).updated_source.decode())
+
+    def test_unknown_version_assumes_latest_api(self):
+        with self.assertLogs('rustimport', 'WARNING') as logs:
+            source = self.process('''
+                // rustimport:pyo3(manifest=manual)
+                //: [package]
+                //: name = "mod"
+                //: version = "0.1.0"
+                //: [lib]
+                //: crate-type = ["cdylib"]
+                //: [dependencies]
+                //: pyo3 = { git = "https://github.com/PyO3/pyo3", features = ["extension-module"] }
+                use pyo3::prelude::*;
+            ''').updated_source.decode()
+        self.assertIn("m: &Bound<'_, PyModule>", source)
+        self.assertIn("Couldn't determine the pyo3 version", logs.output[0])
+
+    def test_unsupported_version(self):
+        with self.assertRaisesRegex(ValueError, r'pyo3 0\.15 is not supported by the pyo3 template'):
+            self.pymodule('"0.15"')
+
+
+class TestNoStd(PreprocessorTestCase):
+    def generated_code(self, source: str) -> str:
+        source = self.process(source).updated_source.decode()
+        return source[source.index('#[pymodule]'):]
+
+    def test_std_is_linked_for_no_std_crates(self):
+        for attribute in ('#![no_std]', '//prelude: #![no_std]'):
+            with self.subTest(attribute):
+                code = self.generated_code(f'''
+                    // rustimport:pyo3
+                    {attribute}
+                    use pyo3::prelude::*;
+                ''')
+                self.assertEqual(code.count('extern crate std;'), 1)
+
+    def test_std_is_not_linked_twice(self):
+        code = self.generated_code('''
+            // rustimport:pyo3
+            #![no_std]
+            extern crate std;
+            use pyo3::prelude::*;
+        ''')
+        self.assertNotIn('extern crate std;', code)
+
+    def test_no_allocator_is_declared(self):
+        code = self.generated_code('''
+            // rustimport:pyo3
+            //prelude: #[global_allocator] static GLOBAL: std::alloc::System = std::alloc::System;
+            use pyo3::prelude::*;
+        ''')
+        self.assertNotIn('global_allocator', code)
+
+
+_PUB_FUNCTIONS = '''
+    // rustimport:pyo3
+    use pyo3::prelude::*;
+
+    pub fn add(a: i64, b: i64) -> i64 { a + b }
+    pub fn greet(name: String) -> PyResult<String> { Ok(format!("Hello, {}!", name)) }
+    pub fn identity<T>(x: T) -> T { x }
+    pub fn first(p: *const u8) -> u8 { unsafe { *p } }
+    pub async fn later() {}
+    pub unsafe fn danger() {}
+
+    #[pyfunction]
+    pub fn explicit() -> i64 { 1 }
+
+    fn hidden() -> i64 { 2 }
+'''
+
+
+class TestAutoExportPub(PreprocessorTestCase):
+    def exported(self, result) -> list:
+        return re.findall(r'wrap_pyfunction!\((\w+), m\)', result.updated_source.decode())
+
+    def test_disabled_by_default(self):
+        self.assertEqual(self.exported(self.process(_PUB_FUNCTIONS)), ['explicit'])
+
+    def test_compatible_functions_are_exported(self):
+        with Settings(auto_export_pub=True).applied(), self.assertLogs('rustimport', 'WARNING') as logs:
+            result = self.process(_PUB_FUNCTIONS)
+        self.assertEqual(self.exported(result), ['explicit', 'rustimport_export_add', 'rustimport_export_greet'])
+        self.assertIn('#[pyo3(name = "add")]\nfn rustimport_export_add(a: i64, b: i64) -> i64 {\n  add(a, b)\n}',
+                      result.updated_source.decode())
+
+        warnings = '\n'.join(logs.output)
+        for name, reason in (('identity', 'it is generic'), ('first', "pyo3 can't convert its parameter `p: *const u8`"),
+                             ('later', 'it is async'), ('danger', 'it is unsafe')):
+            self.assertIn(f"Not exporting `pub fn {name}` automatically, as {reason}", warnings)
+        self.assertNotIn('explicit', warnings)
+        self.assertNotIn('hidden', warnings)
+
+
+class TestUnbalancedDelimiters(PreprocessorTestCase):
+    def test_has_balanced_delimiters(self):
+        self.assertTrue(has_balanced_delimiters(b'fn f(a: [u8; 2]) { g(a) }'))
+        self.assertFalse(has_balanced_delimiters(b'fn f() { g( }'))
+        self.assertFalse(has_balanced_delimiters(b'fn f() {'))
+        self.assertFalse(has_balanced_delimiters(b'fn f() }'))
+
+    def test_no_pymodule_is_generated_for_malformed_sources(self):
+        with self.assertLogs('rustimport', 'WARNING') as logs:
+            result = self.process('''
+                // rustimport:pyo3
+                use pyo3::prelude::*;
+
+                #[pyfunction]
+                fn one() -> usize {
+                    1
+            ''')
+        self.assertIsNone(result.updated_source)
+        self.assertIn('contains unbalanced delimiters; not generating #[pymodule]', '\n'.join(logs.output))
+
+
+class TestAutoBindings(PreprocessorTestCase):
+    def test_forced_on(self):
+        # Would be detected as a handwritten #[pymodule]:
+        result = self.process('''
+            // rustimport:pyo3
+            //auto-bindings: on
+            use pyo3::prelude::*;
+
+            #[pyfunction]
+            fn one() -> usize { 1 }
+
+            #[pymodule]
+            fn helper(_py: Python, m: &PyModule) -> PyResult<()> { Ok(()) }
+        ''')
+        source = result.updated_source.decode()
+        self.assertEqual(source.count('#[pymodule]'), 2)
+        self.assertIn('m.add_function(wrap_pyfunction!(one, m)?)?;', source)
+
+    def test_forced_off(self):
+        # Would get a generated #[pymodule]:
+        result = self.process('''
+            // rustimport:pyo3
+            //auto-bindings: on
+            //auto-bindings: off
+            use pyo3::prelude::*;
+
+            #[pyfunction]
+            fn one() -> usize { 1 }
+        ''')
+        self.assertIsNone(result.updated_source)
+
+    def test_invalid_value(self):
+        with self.assertRaisesRegex(ValueError, 'Invalid value for //auto-bindings: maybe'):
+            self.process('''
+                // rustimport:pyo3
+                //auto-bindings: maybe
+                use pyo3::prelude::*;
+            ''')
+
+
+class TestFeatureOnlyDependencies(PreprocessorTestCase):
+    def test_getrandom_feature_is_kept(self):
+        manifest = self.manifest('''
+            // rustimport:pyo3
+            //: [dependencies]
+            //: rand = "0.8.5"
+            //: getrandom = { version = "0.2", features = ["custom"] }
+            //:
+            //: [target.'cfg(target_arch = "wasm32")'.dependencies]
+            //: getrandom = { version = "0.2", features = ["js"] }
+            //profile: opt-level = 3
+            use pyo3::prelude::*;
+        ''')
+        # getrandom isn't used by the source, but enables a feature of rand's dependency:
+        self.assertEqual(manifest['dependencies']['getrandom'], {'version': '0.2', 'features': ['custom']})
+        # The quoted key stays intact, also when the manifest is processed repeatedly (here for the profile):
+        self.assertEqual(manifest['target'], {
+            'cfg(target_arch = "wasm32")': {'dependencies': {'getrandom': {'version': '0.2', 'features': ['js']}}},
+        })
+        self.assertEqual(manifest['profile']['release']['opt-level'], 3)
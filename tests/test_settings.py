@@ -0,0 +1,71 @@
+import sys
+import threading
+
+import rustimport
+from rustimport import Settings, settings
+from rustimport.batch import build_importables
+from rustimport.importable import SingleFileImportable, should_rebuild
+from tests.utils import TestCase, pyo3_source, requires_cargo
+
+
+class TestSettingsOverlay(TestCase):
+    def test_overrides_apply_only_within_block(self):
+        with Settings(verbosity=1, summary=True).applied():
+            self.assertEqual((settings.verbosity, settings.summary), (1, True))
+            with Settings(verbosity=2).applied():
+                self.assertEqual((settings.verbosity, settings.summary), (2, True))
+            self.assertEqual(settings.verbosity, 1)
+        self.assertEqual(settings.verbosity, 0)  # as set by `TestCase`
+
+    def test_overrides_are_not_seen_by_other_threads(self):
+        seen = []
+        with Settings(compile_release_binaries=True).applied():
+            thread = threading.Thread(target=lambda: seen.append(settings.compile_release_binaries))
+            thread.start()
+            thread.join()
+            self.assertTrue(settings.compile_release_binaries)
+        self.assertEqual(seen, [False])
+
+    def test_unknown_settings_are_rejected(self):
+        with self.assertRaisesRegex(TypeError, 'Unknown rustimport settings: foo, bar'):
+            Settings(foo=1, bar=2)
+
+
+@requires_cargo
+class TestPerCallSettings(TestCase):
+    def test_different_profiles_per_call(self):
+        source = pyo3_source('''
+            #[pyfunction]
+            fn debug_assertions() -> bool { cfg!(debug_assertions) }
+        ''')
+        release_path = self.write('release/profiled_release.rs', source)
+        debug_path = self.write('debug/profiled_debug.rs', source)
+        self.addCleanup(lambda: [sys.modules.pop(m, None) for m in ('profiled_release', 'profiled_debug')])
+
+        release = rustimport.imp_from_path(release_path, settings=Settings(compile_release_binaries=True))
+        debug = rustimport.imp_from_path(debug_path, settings=Settings(compile_release_binaries=False))
+
+        self.assertFalse(settings.compile_release_binaries)
+        self.assertFalse(release.debug_assertions())
+        self.assertTrue(debug.debug_assertions())
+        self.assertTrue(rustimport.get_importable(release_path).build_info['release'])
+        self.assertFalse(rustimport.get_importable(debug_path).build_info['release'])
+
+    def test_build_filepath_with_per_call_settings(self):
+        importable = rustimport.build_filepath(self.write('built_release.rs', pyo3_source('')),
+                                               settings=Settings(compile_release_binaries=True))
+        self.assertTrue(importable.build_info['release'])
+        self.assertFalse(importable.needs_rebuild(release=True))
+
+    def test_importables_keep_their_settings(self):
+        path = self.write('forced.rs', pyo3_source(''))
+        rustimport.build_filepath(path)
+        importable = rustimport.get_importable(path)
+        forced = SingleFileImportable(path, settings=Settings(force_rebuild=True))
+
+        self.assertFalse(should_rebuild(importable))
+        self.assertTrue(should_rebuild(forced))
+        for jobs in (1, 2):
+            with self.subTest(jobs=jobs):
+                # The settings are applied in worker processes, too:
+                self.assertEqual(build_importables([importable, forced], jobs=jobs).built, [path])
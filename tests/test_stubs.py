@@ -0,0 +1,57 @@
+import rustimport
+from rustimport.pre_processing.stubs import parse_typemap, map_rust_type
+from tests.utils import TestCase, pyo3_source
+
+_CUSTOM_TYPES = '''
+    #[pyclass]
+    struct Point { x: f64, y: f64 }
+
+    #[pyclass(name = "Line")]
+    struct RustLine { start: Point, end: Point }
+
+    #[pyfunction]
+    fn origin() -> Point { Point { x: 0.0, y: 0.0 } }
+
+    #[pyfunction]
+    fn length(line: &RustLine) -> f64 { 0.0 }
+
+    #[pyfunction]
+    fn load(config: Config, fallback: Option<Vec<Meters>>) -> PyResult<Meters> { todo!() }
+'''
+
+
+class TestParseTypemap(TestCase):
+    def test_parse_typemap(self):
+        self.assertEqual(parse_typemap(['Meters => float', ' Path=>os.PathLike ', 'invalid', 'Empty =>']),
+                         {'Meters': 'float', 'Path': 'os.PathLike'})
+
+    def test_map_rust_type(self):
+        typemap = {'Meters': 'float'}
+        self.assertEqual(map_rust_type('Vec<Meters>', typemap), 'list[float]')
+        self.assertEqual(map_rust_type('&Point', typemap, known_classes=['Point']), 'Point')
+        self.assertEqual(map_rust_type('Option<Unknown>', typemap), 'Optional[Any]')
+
+
+class TestCustomTypes(TestCase):
+    def signatures(self, source: str) -> dict:
+        api = rustimport.get_importable(self.write('shapes.rs', source)).detect_exports()
+        return {f.name: f.to_stub() for f in api.functions}
+
+    def test_known_classes_and_fallback_to_any(self):
+        signatures = self.signatures(pyo3_source(_CUSTOM_TYPES))
+        self.assertEqual(signatures['origin'], 'def origin() -> Point: ...')
+        # The python name of the class is used:
+        self.assertEqual(signatures['length'], 'def length(line: Line) -> float: ...')
+        self.assertEqual(signatures['load'], 'def load(config: Any, fallback: Optional[list[Any]]) -> Any: ...')
+
+    def test_typemap_directive(self):
+        signatures = self.signatures(
+            '//typemap: Meters => float\n'
+            '//typemap: Config => dict\n'
+            '//typemap: Point => tuple[float, float]\n'
+            + pyo3_source(_CUSTOM_TYPES)
+        )
+        self.assertEqual(signatures['load'], 'def load(config: dict, fallback: Optional[list[float]]) -> float: ...')
+        # The typemap takes precedence over the known classes:
+        self.assertEqual(signatures['origin'], 'def origin() -> tuple[float, float]: ...')
+        self.assertEqual(signatures['length'], 'def length(line: Line) -> float: ...')
@@ -0,0 +1,49 @@
+"""Helpers shared by the tests."""
+import os
+import shutil
+import tempfile
+import textwrap
+import unittest
+from contextlib import ExitStack
+
+from rustimport import Settings
+
+# All tests share a cargo target directory, so that dependencies like pyo3 are only compiled once:
+CARGO_TARGET_DIR = os.path.join(tempfile.gettempdir(), 'rustimport-tests-target')
+
+requires_cargo = unittest.skipUnless(shutil.which('cargo'), 'cargo is not installed')
+
+
+class TestCase(unittest.TestCase):
+    """
+    Runs each test in a fresh temporary directory with its own cache directory. Subclasses can override
+    settings for all of their tests via `settings`.
+    """
+
+    settings = {}
+
+    def setUp(self):
+        self.dir = tempfile.mkdtemp(prefix='rustimport-test-')
+        self.addCleanup(shutil.rmtree, self.dir, ignore_errors=True)
+        stack = ExitStack()
+        self.addCleanup(stack.close)
+        stack.enter_context(Settings(**{
+            'cache_dir': os.path.join(self.dir, '.cache'),
+            'cargo_target_dir': CARGO_TARGET_DIR,
+            'smoke_test_import': False,
+            'verbosity': 0,
+            **self.settings,
+        }).applied())
+
+    def write(self, name: str, contents: str) -> str:
+        """Write `contents` (dedented) to the file `name` in the test directory and return its path."""
+        path = os.path.join(self.dir, name)
+        os.makedirs(os.path.dirname(path), exist_ok=True)
+        with open(path, 'w') as f:
+            f.write(textwrap.dedent(contents).lstrip())
+        return path
+
+
+def pyo3_source(body: str) -> str:
+    """A single-file pyo3 extension exporting the functions in `body`."""
+    return '// rustimport:pyo3\nuse pyo3::prelude::*;\n\n' + textwrap.dedent(body).lstrip()